@@ -6,14 +6,16 @@ mod text;
 
 use std::borrow::Cow;
 
-use cairo::{Context, Filter, Format, ImageSurface, Matrix, SurfacePattern};
+use cairo::{Context, Extend, Filter, Format, Glyph, ImageSurface, Matrix, SurfacePattern};
 
-use piet::kurbo::{Affine, PathEl, Point, QuadBez, Rect, Shape, Size};
+use piet::kurbo::{Affine, BezPath, Line, PathEl, Point, QuadBez, Rect, Shape, Size};
+use piet::util;
 use piet::{
     Color, Error, FixedGradient, ImageFormat, InterpolationMode, IntoBrush, LineCap, LineJoin,
-    RenderContext, StrokeStyle, TextLayout,
+    RenderContext, StrokeStyle, TextLayout, TextPathOptions, UnderlineStyle,
 };
 
+use crate::text::Decoration;
 pub use crate::text::{CairoText, CairoTextLayout, CairoTextLayoutBuilder};
 
 pub struct CairoRenderContext<'a> {
@@ -31,9 +33,10 @@ pub struct CairoRenderContext<'a> {
 impl<'a> CairoRenderContext<'a> {
     /// Create a new Cairo back-end.
     ///
-    /// At the moment, it uses the "toy text API" for text layout, but when
-    /// we change to a more sophisticated text layout approach, we'll probably
-    /// need a factory for that as an additional argument.
+    /// Fonts are matched with fontconfig and opened with FreeType, and text
+    /// is shaped with rustybuzz before its glyphs are drawn, so no font
+    /// factory is needed. Cairo's "toy text API" is only used as a last
+    /// resort, if the font fontconfig matches can't be loaded.
     pub fn new(ctx: &Context) -> CairoRenderContext {
         CairoRenderContext {
             ctx,
@@ -164,13 +167,110 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
     fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
         let pos = pos.into();
         let rect = layout.image_bounds() + pos.to_vec2();
-        let brush = layout.fg_color.make_brush(self, || rect);
-        self.ctx.set_scaled_font(&layout.font);
-        self.set_brush(&*brush);
 
-        for lm in &layout.line_metrics {
-            self.ctx.move_to(pos.x, pos.y + lm.y_offset + lm.baseline);
-            self.ctx.show_text(&layout.text[lm.range()]);
+        let lines = layout
+            .line_metrics
+            .iter()
+            .zip(&layout.line_runs)
+            .zip(&layout.x_offsets);
+
+        // backgrounds go behind the text of every line, so that they don't
+        // cover the descenders of the line above.
+        let mut backgrounds: Vec<(&Color, Rect)> = Vec::new();
+        for (line, ((lm, pieces), x_offset)) in lines.enumerate() {
+            let transform = Affine::translate(pos.to_vec2()) * layout.line_transform(line);
+            for piece in pieces {
+                if let Some(color) = &layout.runs[piece.run].style.background_color {
+                    let x = x_offset + piece.x;
+                    let y = lm.y_offset;
+                    let bg_rect = Rect::new(x, y, x + piece.width, y + lm.height);
+                    backgrounds.push((color, transform.transform_rect_bbox(bg_rect)));
+                }
+            }
+        }
+        // adjacent rects of the same color are filled together, so there are
+        // no seams between them.
+        let mut backgrounds = backgrounds.into_iter().peekable();
+        while let Some((color, bg_rect)) = backgrounds.next() {
+            self.ctx
+                .rectangle(bg_rect.x0, bg_rect.y0, bg_rect.width(), bg_rect.height());
+            while let Some((_, bg_rect)) = backgrounds.next_if(|(next, _)| *next == color) {
+                self.ctx
+                    .rectangle(bg_rect.x0, bg_rect.y0, bg_rect.width(), bg_rect.height());
+            }
+            let brush = color.make_brush(self, || rect);
+            self.set_brush(&*brush);
+            self.ctx.fill();
+        }
+
+        for (line, piece, origin) in layout.positioned_pieces() {
+            let run = &layout.runs[piece.run];
+            let origin = origin + pos.to_vec2();
+            let (glyph_transform, glyphs) = layout.piece_glyphs(piece, origin);
+
+            // pieces are drawn in the frame of their line, which is turned
+            // around `pos` in vertical layouts.
+            let line_transform = Affine::translate(pos.to_vec2())
+                * layout.line_transform(line)
+                * Affine::translate(-pos.to_vec2());
+            self.ctx.save();
+            self.ctx.transform(affine_to_matrix(line_transform));
+
+            // as in CSS, underlines and overlines are drawn beneath the
+            // text, and strikethroughs over it.
+            let decoration_brush = run.style.decoration_color().make_brush(self, || rect);
+            self.set_brush(&*decoration_brush);
+            for decoration in run.underline().into_iter().chain(run.overline()) {
+                self.draw_decoration(decoration, origin, piece.width);
+            }
+
+            let brush = run.style.fg_color.make_brush(self, || rect);
+            self.set_brush(&*brush);
+            self.ctx.save();
+            self.ctx.transform(affine_to_matrix(glyph_transform));
+            self.ctx.set_scaled_font(&run.font);
+            self.ctx.show_glyphs(&glyphs);
+            self.ctx.restore();
+
+            if let Some(decoration) = run.strikethrough() {
+                self.set_brush(&*decoration_brush);
+                self.draw_decoration(decoration, origin, piece.width);
+            }
+            self.ctx.restore();
+        }
+    }
+
+    fn draw_text_on_path(
+        &mut self,
+        layout: &Self::TextLayout,
+        path: impl Shape,
+        options: &TextPathOptions,
+    ) {
+        let baseline = match layout.line_metric(0) {
+            Some(lm) => lm.y_offset + lm.baseline,
+            None => return,
+        };
+        let rect = path.bounding_box();
+        let (glyph_runs, runs): (Vec<_>, Vec<_>) =
+            layout.glyph_runs_with_fonts().into_iter().unzip();
+        let transforms = util::glyph_run_transforms(path, &glyph_runs, baseline, options);
+        for ((glyph_run, run), transforms) in glyph_runs.iter().zip(runs).zip(transforms) {
+            let brush = run.style.fg_color.make_brush(self, || rect);
+            self.set_brush(&*brush);
+            self.ctx.set_scaled_font(&run.font);
+            let glyphs = glyph_run.glyphs.iter().zip(glyph_run.glyph_positions());
+            for ((glyph, pos), transform) in glyphs.zip(transforms) {
+                if let Some(transform) = transform {
+                    self.ctx.save();
+                    self.ctx.transform(affine_to_matrix(transform));
+                    self.ctx.show_glyphs(&[Glyph {
+                        index: glyph.id.into(),
+                        x: pos.x,
+                        y: pos.y,
+                    }]);
+                    self.ctx.restore();
+                }
+            }
         }
     }
 
@@ -338,14 +438,37 @@ fn draw_image<'a>(
     }
 
     let _ = ctx.with_save(|rc| {
+        let scale_x = dst_rect.width() / src_rect.width();
+        let scale_y = dst_rect.height() / src_rect.height();
+
+        // The scale at which the image will end up on the device, taking the
+        // current transform into account.
+        let matrix = rc.ctx.get_matrix();
+        let device_scale_x = scale_x * matrix.xx.hypot(matrix.yx);
+        let device_scale_y = scale_y * matrix.xy.hypot(matrix.yy);
+        let minifying = device_scale_x < 1.0 || device_scale_y < 1.0;
+
+        let prefiltered = if interp == InterpolationMode::HighQuality {
+            prefilter_image(image, src_rect, device_scale_x, device_scale_y)
+        } else {
+            None
+        };
+        let (image, src_rect) = match &prefiltered {
+            Some((image, src_rect)) => (image, *src_rect),
+            None => (image, src_rect),
+        };
+        let scale_x = dst_rect.width() / src_rect.width();
+        let scale_y = dst_rect.height() / src_rect.height();
+
         let surface_pattern = SurfacePattern::create(image);
         let filter = match interp {
             InterpolationMode::NearestNeighbor => Filter::Nearest,
             InterpolationMode::Bilinear => Filter::Bilinear,
+            InterpolationMode::Bicubic => Filter::Best,
+            InterpolationMode::HighQuality if minifying => Filter::Good,
+            InterpolationMode::HighQuality => Filter::Best,
         };
         surface_pattern.set_filter(filter);
-        let scale_x = dst_rect.width() / src_rect.width();
-        let scale_y = dst_rect.height() / src_rect.height();
         rc.clip(dst_rect);
         rc.ctx.translate(
             dst_rect.x0 - scale_x * src_rect.x0,
@@ -358,6 +481,48 @@ fn draw_image<'a>(
     });
 }
 
+/// Shrink the `src_rect` area of `image` by repeatedly halving it, until it is
+/// no more than twice the size it will be drawn at.
+///
+/// Each halving averages 2x2 blocks of pixels, so the result is equivalent to
+/// a mipmap level. Returns the reduced image along with the area of it that
+/// corresponds to `src_rect`, or `None` if no reduction is needed.
+fn prefilter_image(
+    image: &ImageSurface,
+    src_rect: Rect,
+    mut scale_x: f64,
+    mut scale_y: f64,
+) -> Option<(ImageSurface, Rect)> {
+    let mut reduced: Option<ImageSurface> = None;
+    let mut area = src_rect;
+    while scale_x <= 0.5 || scale_y <= 0.5 {
+        let step_x = if scale_x <= 0.5 { 0.5 } else { 1.0 };
+        let step_y = if scale_y <= 0.5 { 0.5 } else { 1.0 };
+        let width = (area.width() * step_x).ceil() as i32;
+        let height = (area.height() * step_y).ceil() as i32;
+        if width < 1 || height < 1 {
+            break;
+        }
+        let level = ImageSurface::create(Format::ARgb32, width, height).ok()?;
+        {
+            let ctx = Context::new(&level);
+            ctx.scale(step_x, step_y);
+            ctx.translate(-area.x0, -area.y0);
+            // Sampling bilinearly at exactly half scale averages each 2x2 block.
+            let pattern = SurfacePattern::create(reduced.as_ref().unwrap_or(image));
+            pattern.set_filter(Filter::Bilinear);
+            pattern.set_extend(Extend::Pad);
+            ctx.set_source(&pattern);
+            ctx.paint();
+        }
+        area = Size::new(area.width() * step_x, area.height() * step_y).to_rect();
+        reduced = Some(level);
+        scale_x /= step_x;
+        scale_y /= step_y;
+    }
+    reduced.map(|image| (image, area))
+}
+
 impl<'a> IntoBrush<CairoRenderContext<'a>> for Brush {
     fn make_brush<'b>(
         &'b self,
@@ -402,6 +567,65 @@ impl<'a> CairoRenderContext<'a> {
         }
     }
 
+    /// Draw a decoration for a `width` wide piece of text, whose baseline
+    /// starts at `origin`.
+    ///
+    /// The source should already be set to the decoration's brush.
+    fn draw_decoration(&mut self, decoration: Decoration, origin: Point, width: f64) {
+        let bounds = decoration.bounds(origin, width);
+        let thickness = decoration.thickness;
+        match decoration.style {
+            UnderlineStyle::Single => {
+                self.ctx
+                    .rectangle(bounds.x0, bounds.y0, width, bounds.height());
+                self.ctx.fill();
+            }
+            UnderlineStyle::Double => {
+                self.ctx.rectangle(bounds.x0, bounds.y0, width, thickness);
+                self.ctx
+                    .rectangle(bounds.x0, bounds.y1 - thickness, width, thickness);
+                self.ctx.fill();
+            }
+            UnderlineStyle::Dotted => {
+                // zero-length dashes with round caps are circles
+                let style = StrokeStyle::new()
+                    .line_cap(LineCap::Round)
+                    .dash(vec![0.0, thickness * 2.0], 0.0);
+                let y = bounds.center().y;
+                let line = Line::new((bounds.x0 + thickness / 2.0, y), (bounds.x1, y));
+                self.set_path(line);
+                self.set_stroke(thickness, Some(&style));
+                self.ctx.stroke();
+            }
+            UnderlineStyle::Wavy => {
+                // the wave's stroke fills the decoration's bounds
+                let amplitude = (bounds.height() - thickness) / 2.0;
+                let half_period = thickness * 3.0;
+                let y = bounds.center().y;
+                let mut path = BezPath::new();
+                path.move_to((bounds.x0, y));
+                let mut wave_x = bounds.x0;
+                let mut sign = -1.0;
+                while wave_x < bounds.x1 {
+                    // a quadratic's peak is half way to its control point
+                    let control = (wave_x + half_period / 2.0, y + sign * amplitude * 2.0);
+                    wave_x += half_period;
+                    path.quad_to(control, (wave_x, y));
+                    sign = -sign;
+                }
+                // the last wave is cut off at the end of the text
+                self.ctx.save();
+                self.ctx
+                    .rectangle(bounds.x0, bounds.y0, width, bounds.height());
+                self.ctx.clip();
+                self.set_path(path);
+                self.set_stroke(thickness, None);
+                self.ctx.stroke();
+                self.ctx.restore();
+            }
+        }
+    }
+
     /// Set the stroke parameters.
     fn set_stroke(&mut self, width: f64, style: Option<&StrokeStyle>) {
         self.ctx.set_line_width(width);
@@ -1,26 +1,51 @@
 //! Text functionality for Piet cairo backend
 
+mod attributes;
+mod bidi;
+mod fallback;
+mod fonts;
 mod grapheme;
 mod lines;
+mod shaping;
 
+use std::f64::consts::FRAC_PI_2;
 use std::fmt;
-use std::ops::RangeBounds;
+use std::ops::{Range, RangeBounds};
 use std::rc::Rc;
 
-use cairo::{FontFace, FontOptions, FontSlant, FontWeight, Matrix, ScaledFont};
+use cairo::{
+    Content, Context, FontFace, FontSlant, FontWeight, Matrix, PathSegment, RecordingSurface,
+    ScaledFont,
+};
 
-use piet::kurbo::{Point, Rect, Size};
+use piet::kurbo::{Affine, BezPath, Point, Rect, Size, Vec2};
 use piet::{
-    util, Color, Error, FontFamily, FontStyle, HitTestPoint, HitTestPosition, LineMetric, Text,
-    TextAttribute, TextLayout, TextLayoutBuilder, TextStorage,
+    util, Error, FontFaceInfo, FontFamily, FontMetrics, FontStyle, FontVariation, Glyph, GlyphRun,
+    HitTestPoint, HitTestPosition, Hyphens, Justification, LineHeight, LineMetric, TabStops, Text,
+    TextAlignment, TextAttribute, TextLayout, TextLayoutBuilder, TextOverflow, TextStorage,
+    UnderlineStyle, WritingMode,
 };
 
+use rustybuzz::Direction;
+use unic_bidi::Level;
 use unicode_segmentation::UnicodeSegmentation;
 
+use self::attributes::{AttributeSpans, TextStyle};
+use self::bidi::BidiLevels;
+use self::fonts::{FaceQuery, FontData};
 use self::grapheme::{get_grapheme_boundaries, point_x_in_grapheme};
-
-/// Right now, we don't need any state, as the "toy text API" treats the
-/// access to system font information as a global. This will change.
+use self::lines::{Break, Tabs};
+use self::shaping::{Cluster, ShapedGlyph};
+
+/// Drawn in place of text that is cut off by truncation.
+const ELLIPSIS: &str = "\u{2026}";
+/// Shown at the end of a line that breaks a word.
+const HYPHEN: &str = "\u{2010}";
+
+/// Right now, we don't need any state: system fonts are matched with
+/// fontconfig and opened with FreeType, and fonts loaded with `load_font` are
+/// shared by every thread. Layouts shape their text with rustybuzz, and draw
+/// the glyphs with cairo's `show_glyphs`.
 // we use a phantom lifetime here to match the API of the d2d backend,
 // and the likely API of something with access to system font information.
 #[derive(Clone)]
@@ -31,31 +56,136 @@ struct CairoFont {
     family: FontFamily,
 }
 
+/// A font at a particular size, along with its data (if available) for shaping.
+#[derive(Clone)]
+pub(crate) struct ResolvedFont {
+    pub(crate) scaled: ScaledFont,
+    data: Option<FontData>,
+}
+
+/// Lines are laid out horizontally, and in vertical layouts each one is then
+/// turned into a column; the fields here describe the unturned lines.
 #[derive(Clone)]
 pub struct CairoTextLayout {
-    // we currently don't handle range attributes, so we stash the default
-    // color here and then just grab it when we draw ourselves.
-    pub(crate) fg_color: Color,
     size: Size,
+    /// The layout's size, extended to include any decorations that fall
+    /// outside it.
+    image_bounds: Rect,
     trailing_ws_width: f64,
-    pub(crate) font: ScaledFont,
+    /// The runs of uniformly styled text; these cover the whole text, in order,
+    /// and there is always at least one. If the text is truncated, they are
+    /// followed by the run of the ellipsis that replaces the hidden text.
+    ///
+    /// Tabs, hyphens and justification change the advances of the runs to
+    /// suit the current lines.
+    pub(crate) runs: Vec<StyleRun>,
+    /// The runs as they were shaped, before any changes for the lines.
+    shaped: Rc<[StyleRun]>,
+    /// Where lines can end in the text.
+    breaks: Vec<Break>,
     pub(crate) text: Rc<dyn TextStorage>,
+    alignment: TextAlignment,
+    line_height: LineHeight,
+    writing_mode: WritingMode,
+    max_lines: Option<usize>,
+    overflow: TextOverflow,
+    tabs: Option<Tabs>,
+    justification: Justification,
+    bidi: BidiLevels,
 
     // currently calculated on build
     pub(crate) line_metrics: Vec<LineMetric>,
+    /// The horizontal offset of each line, from the alignment.
+    pub(crate) x_offsets: Vec<f64>,
+    /// The pieces of runs on each line, in visual order.
+    pub(crate) line_runs: Vec<Vec<LineRun>>,
+}
+
+/// A range of a layout's text with a single style and script, the font it
+/// resolves to, and its shaped glyphs.
+#[derive(Clone)]
+pub(crate) struct StyleRun {
+    pub(crate) range: Range<usize>,
+    pub(crate) style: TextStyle,
+    pub(crate) font: ScaledFont,
+    /// The data of the font, if it can be shaped with rustybuzz.
+    data: Option<FontData>,
+    /// The glyphs, in visual order.
+    glyphs: Vec<ShapedGlyph>,
+    /// The clusters, in logical order.
+    clusters: Vec<Cluster>,
+    level: Level,
+    /// Whether the run is upright in a vertical line.
+    upright: bool,
+    /// Whether the run is an ellipsis, drawn in place of the text it covers.
+    is_ellipsis: bool,
+    /// The glyphs of the hyphen shown when a line ends in a word that is
+    /// broken in this run; these are only shaped if the run can be broken.
+    hyphen: Vec<ShapedGlyph>,
+    /// The object the run reserves space for, in place of its text.
+    object: Option<InlineObject>,
+    decorations: DecorationMetrics,
+}
+
+/// The space reserved for an inline object.
+#[derive(Clone, Copy, Debug)]
+struct InlineObject {
+    size: Size,
+    /// The distance from the top of the object to its baseline.
+    baseline: f64,
+}
+
+/// Where a run's decorations are drawn, as the distance of their top edge
+/// below the baseline, and their thickness.
+#[derive(Clone, Copy)]
+struct DecorationMetrics {
+    underline: (f64, f64),
+    overline: (f64, f64),
+    strikethrough: (f64, f64),
+}
+
+/// A line drawn under, over or through a run of text.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Decoration {
+    pub(crate) style: UnderlineStyle,
+    /// The distance of the top of the line below the baseline.
+    pub(crate) top: f64,
+    pub(crate) thickness: f64,
+}
+
+/// The part of a run that is on a particular line.
+#[derive(Clone, Debug)]
+pub(crate) struct LineRun {
+    /// The index of the run in the layout.
+    pub(crate) run: usize,
+    pub(crate) range: Range<usize>,
+    /// The offset of the left edge of the piece from the start of the line.
+    pub(crate) x: f64,
+    pub(crate) width: f64,
 }
 
 pub struct CairoTextLayoutBuilder {
     text: Rc<dyn TextStorage>,
     defaults: util::LayoutDefaults,
+    attributes: AttributeSpans,
+    alignment: TextAlignment,
+    line_height: LineHeight,
+    writing_mode: WritingMode,
+    max_lines: Option<usize>,
+    overflow: TextOverflow,
+    tab_stops: Option<TabStops>,
+    hyphens: Hyphens,
+    language: Option<String>,
+    justification: Justification,
+    inline_objects: Vec<(Range<usize>, InlineObject)>,
     width_constraint: f64,
 }
 
 impl CairoText {
     /// Create a new factory that satisfies the piet `Text` trait.
     ///
-    /// No state is needed for now because the current implementation is just
-    /// toy text, but that will change when proper text is implemented.
+    /// Every factory sees the same fonts: the system fonts, and any font
+    /// loaded with `load_font` through any factory, on any thread.
     #[allow(clippy::new_without_default)]
     pub fn new() -> CairoText {
         CairoText
@@ -67,16 +197,59 @@ impl Text for CairoText {
     type TextLayoutBuilder = CairoTextLayoutBuilder;
 
     fn font_family(&mut self, family_name: &str) -> Option<FontFamily> {
-        Some(FontFamily::new_unchecked(family_name))
+        fonts::loaded_family(family_name).or_else(|| {
+            if fonts::system_family_exists(family_name) {
+                Some(FontFamily::new_unchecked(family_name))
+            } else {
+                None
+            }
+        })
+    }
+
+    fn families(&mut self) -> Vec<FontFamily> {
+        fonts::families()
+    }
+
+    fn faces(&mut self, family: &FontFamily) -> Vec<FontFaceInfo> {
+        fonts::faces(family)
+    }
+
+    fn load_font(&mut self, data: &[u8]) -> Result<FontFamily, Error> {
+        fonts::load_font(data)
     }
 
-    fn load_font(&mut self, _data: &[u8]) -> Result<FontFamily, Error> {
-        Err(Error::NotSupported)
+    fn font_metrics(
+        &mut self,
+        family: &FontFamily,
+        size: f64,
+        weight: piet::FontWeight,
+        style: FontStyle,
+    ) -> FontMetrics {
+        let family = fonts::available_families(family).swap_remove(0);
+        let query = FaceQuery {
+            weight,
+            style,
+            ..FaceQuery::default()
+        };
+        CairoFont::new(family)
+            .resolve(size, query, &[])
+            .metrics(size)
     }
 
     fn new_text_layout(&mut self, text: impl TextStorage) -> Self::TextLayoutBuilder {
         CairoTextLayoutBuilder {
             defaults: util::LayoutDefaults::default(),
+            attributes: AttributeSpans::default(),
+            alignment: TextAlignment::Start,
+            line_height: LineHeight::Normal,
+            writing_mode: WritingMode::HorizontalTb,
+            max_lines: None,
+            overflow: TextOverflow::Clip,
+            tab_stops: None,
+            hyphens: Hyphens::Manual,
+            language: None,
+            justification: Justification::Auto,
+            inline_objects: Vec::new(),
             text: Rc::new(text),
             width_constraint: f64::INFINITY,
         }
@@ -95,18 +268,610 @@ impl CairoFont {
     }
 
     #[cfg(test)]
-    pub(crate) fn resolve_simple(&self, size: f64) -> ScaledFont {
-        self.resolve(size, FontSlant::Normal, FontWeight::Normal)
+    pub(crate) fn resolve_simple(&self, size: f64) -> ResolvedFont {
+        self.resolve(size, FaceQuery::default(), &[])
+    }
+
+    /// Resolve this family to a font with the given size, face properties and
+    /// variable font axes.
+    ///
+    /// Fonts loaded with `load_font` are preferred, followed by the system
+    /// font fontconfig matches. If neither can be loaded, this falls back to
+    /// cairo's toy font API, which only distinguishes normal and bold.
+    pub(crate) fn resolve(
+        &self,
+        size: f64,
+        query: FaceQuery,
+        variations: &[FontVariation],
+    ) -> ResolvedFont {
+        let face = fonts::loaded_face(&self.family, query)
+            .or_else(|| fonts::system_face(&self.family, query));
+        if let Some((face, data)) = face {
+            return ResolvedFont::new(&face, Some(data), size, variations);
+        }
+
+        let bold = query.weight.to_raw() > piet::FontWeight::MEDIUM.to_raw();
+        let italic = query.style == FontStyle::Italic;
+        let face = FontFace::toy_create(
+            self.family.name(),
+            if italic {
+                FontSlant::Italic
+            } else {
+                FontSlant::Normal
+            },
+            if bold {
+                FontWeight::Bold
+            } else {
+                FontWeight::Normal
+            },
+        );
+        let mut font = ResolvedFont::new(&face, None, size, variations);
+        font.data = fonts::system_font_data(self.family.name(), italic, bold, &font.scaled);
+        font
     }
+}
 
-    /// Create a ScaledFont for this family.
-    pub(crate) fn resolve(&self, size: f64, slant: FontSlant, weight: FontWeight) -> ScaledFont {
-        let font_face = FontFace::toy_create(self.family.name(), slant, weight);
+impl ResolvedFont {
+    fn new(
+        face: &FontFace,
+        data: Option<FontData>,
+        size: f64,
+        variations: &[FontVariation],
+    ) -> ResolvedFont {
         let font_matrix = scale_matrix(size);
         let ctm = scale_matrix(1.0);
-        let options = FontOptions::default();
-        ScaledFont::new(&font_face, &font_matrix, &ctm, &options)
+        let options = fonts::font_options(variations);
+        ResolvedFont {
+            scaled: ScaledFont::new(face, &font_matrix, &ctm, &options),
+            data,
+        }
+    }
+
+    /// The advance of a space in this font, with the spacing of `style`.
+    fn space_width(&self, style: &TextStyle) -> f64 {
+        let shaped = shaping::shape(
+            " ",
+            0..1,
+            Direction::LeftToRight,
+            &self.scaled,
+            self.data.as_ref(),
+            style,
+        );
+        shaped.clusters.iter().map(|cluster| cluster.advance).sum()
+    }
+
+    /// The metrics of this font, which is `size` points.
+    ///
+    /// Values the font's tables don't provide are measured from its glyphs
+    /// or guessed from its ascent and descent.
+    fn metrics(&self, size: f64) -> FontMetrics {
+        let extents = self.scaled.extents();
+        let ink_height = |text| -self.scaled.text_extents(text).y_bearing;
+        let fallback_thickness = (size / 16.0).max(1.0);
+        let mut metrics = FontMetrics {
+            ascent: extents.ascent,
+            descent: extents.descent,
+            line_gap: (extents.height - extents.ascent - extents.descent).max(0.0),
+            x_height: ink_height("x"),
+            cap_height: ink_height("H"),
+            underline_position: extents.descent * 0.5,
+            underline_thickness: fallback_thickness,
+            strikethrough_position: -extents.ascent * 0.3,
+            strikethrough_thickness: fallback_thickness,
+            units_per_em: None,
+        };
+        if let Some(face) = self.data.as_ref().and_then(FontData::face) {
+            let scale = size / face.units_per_em() as f64;
+            metrics.units_per_em = Some(face.units_per_em() as u16);
+            if let Some(height) = face.x_height() {
+                metrics.x_height = height as f64 * scale;
+            }
+            if let Some(height) = face.capital_height() {
+                metrics.cap_height = height as f64 * scale;
+            }
+            // font units are y-up, and thin lines are still drawn a pixel thick
+            let convert = |line: rustybuzz::ttf_parser::LineMetrics| {
+                let thickness = (line.thickness as f64 * scale).max(1.0);
+                (-line.position as f64 * scale, thickness)
+            };
+            if let Some((position, thickness)) = face.underline_metrics().map(convert) {
+                metrics.underline_position = position;
+                metrics.underline_thickness = thickness;
+            }
+            if let Some((position, thickness)) = face.strikeout_metrics().map(convert) {
+                metrics.strikethrough_position = position;
+                metrics.strikethrough_thickness = thickness;
+            }
+        }
+        metrics
+    }
+
+    /// The positions and thicknesses of decorations; overlines are as thick
+    /// as underlines, and sit at the top of the font's ascent.
+    fn decoration_metrics(&self, size: f64) -> DecorationMetrics {
+        let metrics = self.metrics(size);
+        DecorationMetrics {
+            underline: (metrics.underline_position, metrics.underline_thickness),
+            overline: (-metrics.ascent, metrics.underline_thickness),
+            strikethrough: (
+                metrics.strikethrough_position,
+                metrics.strikethrough_thickness,
+            ),
+        }
+    }
+}
+
+impl Decoration {
+    /// The total height of the decoration, which is more than its thickness
+    /// for double and wavy lines.
+    pub(crate) fn height(&self) -> f64 {
+        match self.style {
+            UnderlineStyle::Single | UnderlineStyle::Dotted => self.thickness,
+            UnderlineStyle::Double | UnderlineStyle::Wavy => self.thickness * 3.0,
+        }
+    }
+
+    /// The area covered by the decoration of a `width` wide piece of text,
+    /// whose baseline starts at `origin`.
+    pub(crate) fn bounds(&self, origin: Point, width: f64) -> Rect {
+        let top = origin.y + self.top;
+        Rect::new(origin.x, top, origin.x + width, top + self.height())
+    }
+}
+
+impl StyleRun {
+    fn new(
+        text: &str,
+        range: Range<usize>,
+        style: TextStyle,
+        font: &ResolvedFont,
+        level: Level,
+        upright: bool,
+    ) -> StyleRun {
+        let direction = if upright {
+            Direction::TopToBottom
+        } else if level.is_rtl() {
+            Direction::RightToLeft
+        } else {
+            Direction::LeftToRight
+        };
+        let shaped = shaping::shape(
+            text,
+            range.clone(),
+            direction,
+            &font.scaled,
+            font.data.as_ref(),
+            &style,
+        );
+        let decorations = font.decoration_metrics(style.display_size());
+        StyleRun {
+            range,
+            style,
+            font: font.scaled.clone(),
+            data: font.data.clone(),
+            glyphs: shaped.glyphs,
+            clusters: shaped.clusters,
+            level,
+            upright,
+            is_ellipsis: false,
+            hyphen: Vec::new(),
+            object: None,
+            decorations,
+        }
+    }
+
+    /// A run that draws an ellipsis in the style of `run`; it covers no text
+    /// until it is given the text it hides with `hide`.
+    fn ellipsis(run: &StyleRun, level: Level) -> StyleRun {
+        // fonts without the ellipsis character get three periods instead
+        let glyphs = run.shape_symbol(level, &[ELLIPSIS, "..."]);
+        let advance = glyphs.iter().map(|glyph| glyph.advance).sum();
+        StyleRun {
+            range: 0..0,
+            style: run.style.clone(),
+            font: run.font.clone(),
+            data: run.data.clone(),
+            glyphs,
+            clusters: vec![Cluster {
+                range: 0..0,
+                x: 0.0,
+                advance,
+            }],
+            level,
+            upright: false,
+            is_ellipsis: true,
+            hyphen: Vec::new(),
+            object: None,
+            decorations: run.decorations,
+        }
+    }
+
+    /// A run that reserves space for `object` in place of `range` of the
+    /// text; it has no glyphs, and a single cluster.
+    fn object(
+        range: Range<usize>,
+        style: TextStyle,
+        font: &ResolvedFont,
+        level: Level,
+        object: InlineObject,
+    ) -> StyleRun {
+        let decorations = font.decoration_metrics(style.display_size());
+        StyleRun {
+            range: range.clone(),
+            style,
+            font: font.scaled.clone(),
+            data: font.data.clone(),
+            glyphs: Vec::new(),
+            clusters: vec![Cluster {
+                range,
+                x: 0.0,
+                advance: object.size.width,
+            }],
+            level,
+            upright: false,
+            is_ellipsis: false,
+            hyphen: Vec::new(),
+            object: Some(object),
+            decorations,
+        }
+    }
+
+    /// Shape the first of `texts` that the run's font has glyphs for, or the
+    /// last of them if it has none, in the style of the run.
+    fn shape_symbol(&self, level: Level, texts: &[&str]) -> Vec<ShapedGlyph> {
+        let direction = if level.is_rtl() {
+            Direction::RightToLeft
+        } else {
+            Direction::LeftToRight
+        };
+        let shape = |text: &str| {
+            shaping::shape(
+                text,
+                0..text.len(),
+                direction,
+                &self.font,
+                self.data.as_ref(),
+                &self.style,
+            )
+            .glyphs
+        };
+        let (last, rest) = texts.split_last().unwrap();
+        rest.iter()
+            .map(|text| shape(text))
+            .find(|glyphs| glyphs.iter().all(|glyph| glyph.id != 0))
+            .unwrap_or_else(|| shape(last))
+    }
+
+    /// Shape the hyphen that is shown when a word in this run is broken.
+    fn shape_hyphen(&mut self) {
+        self.hyphen = self.shape_symbol(self.level, &[HYPHEN, "-"]);
+    }
+
+    /// The index of the cluster that contains `pos`.
+    fn cluster_index(&self, pos: usize) -> Option<usize> {
+        match self
+            .clusters
+            .binary_search_by_key(&pos, |cluster| cluster.range.start)
+        {
+            Ok(idx) => Some(idx),
+            Err(0) => None,
+            Err(idx) => Some(idx - 1),
+        }
+    }
+
+    /// Widen the cluster that contains `pos` by `extra`, moving the clusters
+    /// after it; the space is added after its last glyph.
+    fn widen_cluster(&mut self, pos: usize, extra: f64) {
+        let idx = match self.cluster_index(pos) {
+            Some(idx) => idx,
+            None => return,
+        };
+        let start = self.clusters[idx].range.start;
+        self.clusters[idx].advance += extra;
+        for cluster in &mut self.clusters[idx + 1..] {
+            cluster.x += extra;
+        }
+        if let Some(glyph) = self
+            .glyphs
+            .iter_mut()
+            .rev()
+            .find(|glyph| glyph.cluster == start)
+        {
+            glyph.advance += extra;
+        }
+    }
+
+    /// Show the run's hyphen after the cluster that contains `pos`.
+    fn add_hyphen(&mut self, pos: usize) {
+        let idx = match self.cluster_index(pos) {
+            Some(idx) => idx,
+            None => return,
+        };
+        let start = self.clusters[idx].range.start;
+        let hyphen = self.hyphen.iter().map(|glyph| ShapedGlyph {
+            cluster: start,
+            ..glyph.clone()
+        });
+        // the hyphen follows the cluster's glyphs, which is to their left in
+        // right-to-left runs
+        let at = if self.rtl() {
+            self.glyphs.iter().position(|glyph| glyph.cluster == start)
+        } else {
+            self.glyphs
+                .iter()
+                .rposition(|glyph| glyph.cluster == start)
+                .map(|idx| idx + 1)
+        };
+        let at = at.unwrap_or(self.glyphs.len());
+        self.glyphs.splice(at..at, hyphen.collect::<Vec<_>>());
+
+        let advance = self.hyphen.iter().map(|glyph| glyph.advance).sum::<f64>();
+        self.clusters[idx].advance += advance;
+        for cluster in &mut self.clusters[idx + 1..] {
+            cluster.x += advance;
+        }
+    }
+
+    /// Make this ellipsis run stand in for the `hidden` text.
+    fn hide(&mut self, hidden: Range<usize>) {
+        for glyph in &mut self.glyphs {
+            glyph.cluster = hidden.start;
+        }
+        self.clusters[0].range = hidden.clone();
+        self.range = hidden;
+    }
+
+    /// A left-to-right run of `text` using `font` and otherwise default
+    /// attributes, for testing.
+    #[cfg(test)]
+    pub(crate) fn with_font(text: &str, range: Range<usize>, font: &ResolvedFont) -> StyleRun {
+        let style = TextStyle::new(&util::LayoutDefaults::default());
+        StyleRun::new(text, range, style, font, Level::ltr(), false)
+    }
+
+    fn rtl(&self) -> bool {
+        self.level.is_rtl()
+    }
+
+    /// The advance from the start of the run to `pos`, in logical order.
+    ///
+    /// Positions inside a cluster, such as between the letters of a ligature,
+    /// are interpolated between the cluster's graphemes.
+    fn advance_to(&self, text: &str, pos: usize) -> f64 {
+        // the hidden text is all at the start of an ellipsis
+        if self.is_ellipsis && pos < self.range.end {
+            return 0.0;
+        }
+        let idx = match self
+            .clusters
+            .binary_search_by_key(&pos, |cluster| cluster.range.start)
+        {
+            Ok(idx) => return self.clusters[idx].x,
+            Err(0) => return 0.0,
+            Err(idx) => idx - 1,
+        };
+        let cluster = &self.clusters[idx];
+        if pos >= cluster.range.end {
+            return cluster.x + cluster.advance;
+        }
+        let total = text[cluster.range.clone()].graphemes(true).count();
+        let before = text[cluster.range.start..pos].graphemes(true).count();
+        cluster.x + cluster.advance * before as f64 / total as f64
+    }
+
+    /// The advance width of `range`, which should be within this run.
+    pub(crate) fn advance(&self, text: &str, range: Range<usize>) -> f64 {
+        self.advance_to(text, range.end) - self.advance_to(text, range.start)
+    }
+
+    /// The horizontal position of `pos`, relative to the left edge of `part`
+    /// of this run.
+    fn x_in_part(&self, text: &str, part: Range<usize>, pos: usize) -> f64 {
+        let from_start = self.advance(text, part.start..pos);
+        if self.rtl() {
+            self.advance(text, part) - from_start
+        } else {
+            from_start
+        }
+    }
+
+    /// The glyphs for `part` of this run, in visual order.
+    pub(crate) fn glyphs_in(&self, part: Range<usize>) -> impl Iterator<Item = &ShapedGlyph> {
+        self.glyphs
+            .iter()
+            .filter(move |glyph| part.contains(&glyph.cluster))
+    }
+
+    /// The clusters of `part` of this run, in visual order.
+    fn clusters_in(&self, part: Range<usize>) -> Vec<&Cluster> {
+        let mut clusters = self
+            .clusters
+            .iter()
+            .filter(|cluster| part.contains(&cluster.range.start))
+            .collect::<Vec<_>>();
+        if self.rtl() {
+            clusters.reverse();
+        }
+        clusters
+    }
+
+    /// The run's underline, if it has one.
+    pub(crate) fn underline(&self) -> Option<Decoration> {
+        let (top, thickness) = self.decorations.underline;
+        let style = self.style.underline_style;
+        if self.style.underline {
+            Some(Decoration {
+                style,
+                top,
+                thickness,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The run's overline, if it has one.
+    pub(crate) fn overline(&self) -> Option<Decoration> {
+        let (top, thickness) = self.decorations.overline;
+        let style = UnderlineStyle::Single;
+        if self.style.overline {
+            Some(Decoration {
+                style,
+                top,
+                thickness,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The run's strikethrough, if it has one.
+    pub(crate) fn strikethrough(&self) -> Option<Decoration> {
+        let (top, thickness) = self.decorations.strikethrough;
+        let style = UnderlineStyle::Single;
+        if self.style.strikethrough {
+            Some(Decoration {
+                style,
+                top,
+                thickness,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Iterate over the runs that overlap `range`, along with the part of `range`
+/// that each of them covers.
+///
+/// An empty `range` yields the run that it falls in.
+pub(crate) fn runs_in_range(
+    runs: &[StyleRun],
+    range: Range<usize>,
+) -> impl Iterator<Item = (&StyleRun, Range<usize>)> {
+    run_parts(runs, range).map(move |(idx, part)| (&runs[idx], part))
+}
+
+/// Like `runs_in_range`, but yielding the index of each run.
+fn run_parts(
+    runs: &[StyleRun],
+    range: Range<usize>,
+) -> impl Iterator<Item = (usize, Range<usize>)> + '_ {
+    let first = runs
+        .iter()
+        .rposition(|run| run.range.start <= range.start)
+        .unwrap_or(0);
+    let Range { start, end } = range;
+    runs.iter()
+        .enumerate()
+        .skip(first)
+        .take_while(move |(_, run)| run.range.start < end || run.range.start <= start)
+        .map(move |(idx, run)| {
+            let run_start = run.range.start.max(start);
+            let run_end = run.range.end.min(end).max(run_start);
+            (idx, run_start..run_end)
+        })
+}
+
+/// The pieces of the runs on `line`, positioned in visual order.
+///
+/// Following rule L1 of the bidi algorithm, trailing whitespace is placed
+/// according to the paragraph's direction.
+pub(crate) fn line_runs(
+    runs: &[StyleRun],
+    text: &str,
+    line: Range<usize>,
+    paragraph_rtl: bool,
+) -> Vec<LineRun> {
+    let paragraph_level = if paragraph_rtl {
+        Level::rtl()
+    } else {
+        Level::ltr()
+    };
+    let ws_start = line.start + text[line.clone()].trim_end().len();
+
+    let mut pieces = Vec::new();
+    for (idx, part) in run_parts(runs, line) {
+        let level = runs[idx].level;
+        if part.end > ws_start && level != paragraph_level && !part.is_empty() {
+            if part.start < ws_start {
+                pieces.push((idx, part.start..ws_start, level));
+            }
+            pieces.push((idx, part.start.max(ws_start)..part.end, paragraph_level));
+        } else {
+            pieces.push((idx, part, level));
+        }
+    }
+
+    let levels = pieces.iter().map(|piece| piece.2).collect::<Vec<_>>();
+    let mut x = 0.0;
+    bidi::visual_order(&levels)
+        .into_iter()
+        .map(|piece_idx| {
+            let (run, range, _) = pieces[piece_idx].clone();
+            let width = runs[run].advance(text, range.clone());
+            let piece = LineRun {
+                run,
+                range,
+                x,
+                width,
+            };
+            x += width;
+            piece
+        })
+        .collect()
+}
+
+/// The advance width of the given range of `text`, using the fonts of `runs`.
+pub(crate) fn advance_width(runs: &[StyleRun], text: &str, range: Range<usize>) -> f64 {
+    runs_in_range(runs, range)
+        .map(|(run, range)| run.advance(text, range))
+        .sum()
+}
+
+/// The baseline and height of a line made up of the given range of the text;
+/// these are large enough to accommodate the largest font on the line, and
+/// any raised or lowered runs.
+pub(crate) fn vertical_metrics(runs: &[StyleRun], range: Range<usize>) -> (f64, f64) {
+    let (ascent, descent) = runs_in_range(runs, range).fold((0.0, 0.0), |(a, d), (run, _)| {
+        let (ascent, descent) = match run.object {
+            Some(object) => (object.baseline, object.size.height - object.baseline),
+            None => {
+                let extents = run.font.extents();
+                (extents.ascent, extents.height - extents.ascent)
+            }
+        };
+        let offset = run.style.baseline_offset();
+        (f64::max(a, ascent + offset), f64::max(d, descent - offset))
+    });
+    (ascent, ascent + descent)
+}
+
+/// Split `range` at the edges of the inline `objects`, which are in order and
+/// don't overlap, along with the index of the object that each part is in,
+/// if any.
+fn split_objects(
+    range: Range<usize>,
+    objects: &[(Range<usize>, InlineObject)],
+) -> Vec<(Range<usize>, Option<usize>)> {
+    let mut parts = Vec::new();
+    let mut start = range.start;
+    let overlapping = objects
+        .iter()
+        .enumerate()
+        .filter(|(_, (object, _))| object.start < range.end && object.end > range.start);
+    for (idx, (object, _)) in overlapping {
+        if object.start > start {
+            parts.push((start..object.start, None));
+        }
+        let end = object.end.min(range.end);
+        parts.push((start.max(object.start)..end, Some(idx)));
+        start = end;
+    }
+    if start < range.end || parts.is_empty() {
+        parts.push((start..range.end, None));
     }
+    parts
 }
 
 impl fmt::Debug for CairoFont {
@@ -123,8 +888,57 @@ impl TextLayoutBuilder for CairoTextLayoutBuilder {
         self
     }
 
-    fn alignment(self, _alignment: piet::TextAlignment) -> Self {
-        // TextAlignment is not supported by cairo toy text.
+    fn alignment(mut self, alignment: TextAlignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    fn line_height(mut self, height: LineHeight) -> Self {
+        self.line_height = height;
+        self
+    }
+
+    fn writing_mode(mut self, mode: WritingMode) -> Self {
+        self.writing_mode = mode;
+        self
+    }
+
+    fn max_lines(mut self, lines: usize) -> Self {
+        self.max_lines = Some(lines.max(1));
+        self
+    }
+
+    fn overflow(mut self, overflow: TextOverflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    fn tab_stops(mut self, stops: TabStops) -> Self {
+        self.tab_stops = Some(stops);
+        self
+    }
+
+    fn hyphens(mut self, hyphens: Hyphens) -> Self {
+        self.hyphens = hyphens;
+        self
+    }
+
+    fn language(mut self, language: &str) -> Self {
+        self.language = Some(language.to_owned());
+        self
+    }
+
+    fn justification(mut self, justification: Justification) -> Self {
+        self.justification = justification;
+        self
+    }
+
+    fn inline_object(mut self, range: impl RangeBounds<usize>, size: Size, baseline: f64) -> Self {
+        let range = util::resolve_range(range, self.text.len());
+        if !range.is_empty() {
+            self.inline_objects
+                .push((range, InlineObject { size, baseline }));
+        }
         self
     }
 
@@ -134,36 +948,130 @@ impl TextLayoutBuilder for CairoTextLayoutBuilder {
     }
 
     fn range_attribute(
-        self,
-        _range: impl RangeBounds<usize>,
-        _attribute: impl Into<TextAttribute>,
+        mut self,
+        range: impl RangeBounds<usize>,
+        attribute: impl Into<TextAttribute>,
     ) -> Self {
+        let range = util::resolve_range(range, self.text.len());
+        self.attributes.add(range, attribute.into());
         self
     }
 
     fn build(self) -> Result<Self::Out, Error> {
-        // set our default font
-        let font = CairoFont::new(self.defaults.font.clone());
-        let size = self.defaults.font_size;
-        let weight = if self.defaults.weight.to_raw() <= piet::FontWeight::MEDIUM.to_raw() {
-            FontWeight::Normal
-        } else {
-            FontWeight::Bold
-        };
-        let slant = match self.defaults.style {
-            FontStyle::Italic => FontSlant::Italic,
-            FontStyle::Regular => FontSlant::Normal,
-        };
+        let bidi = BidiLevels::new(&self.text);
+        let mut objects = self.inline_objects;
+        objects.sort_by_key(|(range, _)| range.start);
+        // an object that overlaps an earlier one is dropped
+        let mut objects_end = 0;
+        objects.retain(|(range, _)| {
+            let keep = range.start >= objects_end;
+            if keep {
+                objects_end = range.end;
+            }
+            keep
+        });
+
+        let mut runs = Vec::new();
+        for (range, style) in self.attributes.resolve(&self.defaults, self.text.len()) {
+            let fonts = fonts::available_families(&style.font)
+                .into_iter()
+                .map(|family| {
+                    let font = CairoFont::new(family.clone()).resolve(
+                        style.display_size(),
+                        style.face_query(),
+                        &style.font_variations,
+                    );
+                    (family, font)
+                })
+                .collect::<Vec<_>>();
+            for (range, object) in split_objects(range, &objects) {
+                if let Some(idx) = object {
+                    let (object_range, object) = &objects[idx];
+                    // an object that spans several attribute ranges is a
+                    // single run, in the first of them
+                    if range.start == object_range.start {
+                        let level = bidi.split(object_range.clone())[0].1;
+                        let font = &fonts[0].1;
+                        let run = StyleRun::object(
+                            object_range.clone(),
+                            style.clone(),
+                            font,
+                            level,
+                            *object,
+                        );
+                        runs.push(run);
+                    }
+                    continue;
+                }
+                for (range, level) in bidi.split(range) {
+                    for range in shaping::script_runs(&self.text, range) {
+                        let orientations = if self.writing_mode.is_vertical() {
+                            shaping::orientation_runs(&self.text, range)
+                        } else {
+                            vec![(range, false)]
+                        };
+                        for (range, upright) in orientations {
+                            let font_runs = fallback::font_runs(&self.text, range, &fonts, &style);
+                            for (range, font) in font_runs {
+                                let breakable = match self.hyphens {
+                                    Hyphens::None => false,
+                                    Hyphens::Manual => self.text[range.clone()].contains('\u{ad}'),
+                                    Hyphens::Auto => true,
+                                };
+                                let style = style.clone();
+                                let mut run =
+                                    StyleRun::new(&self.text, range, style, &font, level, upright);
+                                if breakable {
+                                    run.shape_hyphen();
+                                }
+                                runs.push(run);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let defaults = &self.defaults;
+        let tabs = self.tab_stops.map(|stops| {
+            let style = TextStyle::new(defaults);
+            let family = fonts::available_families(&style.font).remove(0);
+            let font = CairoFont::new(family).resolve(
+                style.display_size(),
+                style.face_query(),
+                &style.font_variations,
+            );
+            Tabs {
+                stops,
+                space_width: font.space_width(&style),
+            }
+        });
 
-        let scaled_font = font.resolve(size, slant, weight);
+        let lang = self.language.as_deref().and_then(lines::hyphenation_lang);
+        let mut breaks = lines::line_breaks(&self.text, self.hyphens, lang);
+        for (range, _) in &objects {
+            lines::break_around(&mut breaks, range.clone());
+        }
 
         // invalid until update_width() is called
         let mut layout = CairoTextLayout {
-            fg_color: self.defaults.fg_color,
-            font: scaled_font,
+            shaped: runs.clone().into(),
+            runs,
+            breaks,
             size: Size::ZERO,
+            image_bounds: Rect::ZERO,
             trailing_ws_width: 0.0,
             line_metrics: Vec::new(),
+            x_offsets: Vec::new(),
+            line_runs: Vec::new(),
+            alignment: self.alignment,
+            line_height: self.line_height,
+            writing_mode: self.writing_mode,
+            max_lines: self.max_lines,
+            overflow: self.overflow,
+            tabs,
+            justification: self.justification,
+            bidi,
             text: self.text,
         };
 
@@ -179,8 +1087,16 @@ impl fmt::Debug for CairoTextLayoutBuilder {
 }
 
 impl TextLayout for CairoTextLayout {
+    fn set_max_width(&mut self, width: f64) -> Result<(), Error> {
+        self.update_width(width)
+    }
+
     fn size(&self) -> Size {
-        self.size
+        if self.writing_mode.is_vertical() {
+            Size::new(self.size.height, self.size.width)
+        } else {
+            self.size
+        }
     }
 
     fn trailing_whitespace_width(&self) -> f64 {
@@ -188,7 +1104,7 @@ impl TextLayout for CairoTextLayout {
     }
 
     fn image_bounds(&self) -> Rect {
-        self.size.to_rect()
+        self.image_bounds
     }
 
     fn text(&self) -> &str {
@@ -202,7 +1118,16 @@ impl TextLayout for CairoTextLayout {
     }
 
     fn line_metric(&self, line_number: usize) -> Option<LineMetric> {
-        self.line_metrics.get(line_number).cloned()
+        let lm = self.line_metrics.get(line_number)?;
+        if self.writing_mode.is_vertical() {
+            Some(LineMetric {
+                y_offset: self.column_left(lm),
+                baseline: lm.height - lm.baseline,
+                ..lm.clone()
+            })
+        } else {
+            Some(lm.clone())
+        }
     }
 
     fn line_count(&self) -> usize {
@@ -217,6 +1142,10 @@ impl TextLayout for CairoTextLayout {
         if self.text.is_empty() {
             return HitTestPoint::default();
         }
+        if self.writing_mode.is_vertical() {
+            let (inside, line_num) = self.column_at(point.x);
+            return self.hit_test_line(line_num, point, inside);
+        }
 
         let height = self
             .line_metrics
@@ -227,29 +1156,19 @@ impl TextLayout for CairoTextLayout {
         // determine whether this click is within the y bounds of the layout,
         // and what line it coorresponds to. (For points above and below the layout,
         // we hittest the first and last lines respectively.)
-        let (y_inside, lm) = if point.y < 0. {
-            (false, self.line_metrics.first().unwrap())
+        let (y_inside, line_num) = if point.y < 0. {
+            (false, 0)
         } else if point.y >= height {
-            (false, self.line_metrics.last().unwrap())
+            (false, self.line_metrics.len() - 1)
         } else {
-            let line = self
+            let line_num = self
                 .line_metrics
                 .iter()
-                .find(|l| point.y >= l.y_offset && point.y < l.y_offset + l.height)
+                .position(|l| point.y >= l.y_offset && point.y < l.y_offset + l.height)
                 .unwrap();
-            (true, line)
+            (true, line_num)
         };
-
-        // Trailing whitespace is remove for the line
-        let line = &self.text[lm.range()];
-
-        let mut htp = hit_test_line_point(&self.font, line, point);
-        htp.idx += lm.start_offset;
-        if htp.idx == lm.end_offset {
-            htp.idx -= util::trailing_nlf(line).unwrap_or(0);
-        }
-        htp.is_inside &= y_inside;
-        htp
+        self.hit_test_line(line_num, point, y_inside)
     }
 
     fn hit_test_text_position(&self, idx: usize) -> HitTestPosition {
@@ -257,7 +1176,9 @@ impl TextLayout for CairoTextLayout {
         assert!(self.text.is_char_boundary(idx));
 
         if idx == 0 && self.text.is_empty() {
-            return HitTestPosition::new(Point::new(0., self.font.extents().ascent), 0);
+            let (baseline, _) = self.empty_line_metrics();
+            let point = self.line_transform(0) * Point::new(self.x_offsets[0], baseline);
+            return HitTestPosition::new(point, 0);
         }
 
         // first need to find line it's on, and get line start offset
@@ -268,167 +1189,796 @@ impl TextLayout for CairoTextLayout {
 
         // Then for the line, do text position
         // Trailing whitespace is removed for the line
-        let line = &self.text[lm.range()];
         let line_position = idx - lm.start_offset;
 
-        let x_pos = hit_test_line_position(&self.font, line, line_position);
-        HitTestPosition::new(Point::new(x_pos, y_pos), line_num)
+        let pieces = &self.line_runs[line_num];
+        let x_pos =
+            hit_test_line_position(&self.runs, pieces, &self.text, lm.range(), line_position)
+                + self.x_offsets[line_num];
+        let point = self.line_transform(line_num) * Point::new(x_pos, y_pos);
+        HitTestPosition::new(point, line_num)
     }
-}
 
-impl CairoTextLayout {
-    fn update_width(&mut self, new_width: impl Into<Option<f64>>) -> Result<(), Error> {
-        let new_width = new_width.into().unwrap_or(std::f64::INFINITY);
+    fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
+        let text_len = self.text.len();
+        let range = util::resolve_range(range, text_len);
+        let range = range.start.min(text_len)..range.end.min(text_len);
 
-        self.line_metrics = lines::calculate_line_metrics(&self.text, &self.font, new_width);
-        if self.text.is_empty() {
-            self.line_metrics.push(LineMetric {
-                baseline: self.font.extents().ascent,
-                height: self.font.extents().height,
-                ..Default::default()
-            })
-        } else if util::trailing_nlf(&self.text).is_some() {
-            let newline_eof = self
-                .line_metrics
-                .last()
-                .map(|lm| LineMetric {
-                    start_offset: self.text.len(),
-                    end_offset: self.text.len(),
-                    height: lm.height,
-                    baseline: lm.baseline,
-                    y_offset: lm.y_offset + lm.height,
-                    trailing_whitespace: 0,
-                })
-                .unwrap();
-            self.line_metrics.push(newline_eof);
+        let mut result: Vec<Rect> = Vec::new();
+        let lines = self
+            .line_metrics
+            .iter()
+            .zip(&self.line_runs)
+            .zip(&self.x_offsets);
+        for (line, ((lm, pieces), x_offset)) in lines.enumerate() {
+            // as in the default implementation, trailing whitespace is only
+            // included on the line where the range ends.
+            let line_end = if range.end < lm.end_offset || lm.end_offset == text_len {
+                range.end.min(lm.end_offset)
+            } else {
+                lm.end_offset - lm.trailing_whitespace
+            };
+            let start = range.start.max(lm.start_offset);
+            if start >= line_end {
+                continue;
+            }
+
+            let y0 = lm.y_offset;
+            let y1 = y0 + lm.height;
+            let line_start = result.len();
+            for piece in pieces {
+                let part_start = start.max(piece.range.start);
+                let part_end = line_end.min(piece.range.end);
+                if part_start >= part_end {
+                    continue;
+                }
+                let run = &self.runs[piece.run];
+                let a = run.x_in_part(&self.text, piece.range.clone(), part_start);
+                let b = run.x_in_part(&self.text, piece.range.clone(), part_end);
+                let x0 = x_offset + piece.x + a.min(b);
+                let x1 = x_offset + piece.x + a.max(b);
+                // merge with the previous rect if they touch
+                match result[line_start..].last_mut() {
+                    Some(prev) if (prev.x1 - x0).abs() < 1e-6 => prev.x1 = x1,
+                    _ => result.push(Rect::new(x0, y0, x1, y1)),
+                }
+            }
+            let transform = self.line_transform(line);
+            for rect in &mut result[line_start..] {
+                *rect = transform.transform_rect_bbox(*rect);
+            }
+        }
+        result
+    }
+
+    fn glyph_runs(&self) -> Vec<GlyphRun> {
+        self.glyph_runs_with_fonts()
+            .into_iter()
+            .map(|(run, _)| run)
+            .collect()
+    }
+
+    fn to_path(&self, origin: impl Into<Point>) -> BezPath {
+        let origin = origin.into().to_vec2();
+        let mut path = BezPath::new();
+        // cairo only builds paths in a context, although nothing is drawn
+        let surface = match RecordingSurface::create(Content::Alpha, None) {
+            Ok(surface) => surface,
+            Err(_) => return path,
+        };
+        let ctx = Context::new(&surface);
+        for (line, piece, piece_origin) in self.positioned_pieces() {
+            let (transform, glyphs) = self.piece_glyphs(piece, piece_origin);
+            ctx.new_path();
+            ctx.set_scaled_font(&self.runs[piece.run].font);
+            ctx.glyph_path(&glyphs);
+            let mut piece_path = BezPath::new();
+            for segment in ctx.copy_path().iter() {
+                match segment {
+                    PathSegment::MoveTo(p) => piece_path.move_to(p),
+                    PathSegment::LineTo(p) => piece_path.line_to(p),
+                    PathSegment::CurveTo(p1, p2, p3) => piece_path.curve_to(p1, p2, p3),
+                    PathSegment::ClosePath => piece_path.close_path(),
+                }
+            }
+            piece_path
+                .apply_affine(Affine::translate(origin) * self.line_transform(line) * transform);
+            path.extend(piece_path);
         }
+        path
+    }
+
+    fn inline_object_rect(&self, position: usize) -> Option<Rect> {
+        self.positioned_pieces().find_map(|(line, piece, origin)| {
+            let object = self.runs[piece.run].object?;
+            if piece.range.start != position {
+                return None;
+            }
+            let top_left = Point::new(origin.x, origin.y - object.baseline);
+            let rect = Rect::from_origin_size(top_left, object.size);
+            Some(self.line_transform(line).transform_rect_bbox(rect))
+        })
+    }
+}
 
-        let (width, ws_width) = self
+impl CairoTextLayout {
+    /// The pieces of runs on each line, in visual order, with the number of
+    /// their line and the start of their baseline.
+    pub(crate) fn positioned_pieces(&self) -> impl Iterator<Item = (usize, &LineRun, Point)> {
+        let lines = self
             .line_metrics
             .iter()
-            .map(|lm| {
-                let full_width = self.font.text_extents(&self.text[lm.range()]).x_advance;
-                let non_ws_width = if lm.trailing_whitespace > 0 {
+            .zip(&self.line_runs)
+            .zip(&self.x_offsets);
+        lines
+            .enumerate()
+            .flat_map(move |(line, ((lm, pieces), x_offset))| {
+                pieces.iter().map(move |piece| {
+                    let run = &self.runs[piece.run];
+                    let y = lm.y_offset + lm.baseline - run.style.baseline_offset();
+                    (line, piece, Point::new(x_offset + piece.x, y))
+                })
+            })
+    }
+
+    /// The layout's glyph runs, with the style run each was shaped in,
+    /// which has the font to draw it with.
+    pub(crate) fn glyph_runs_with_fonts(&self) -> Vec<(GlyphRun, &StyleRun)> {
+        let mut result = Vec::new();
+        if self.writing_mode.is_vertical() {
+            return result;
+        }
+        for (line, piece, origin) in self.positioned_pieces() {
+            let glyphs = self
+                .visible_glyphs(piece)
+                .map(|glyph| Glyph {
+                    id: glyph.id,
+                    advance: glyph.advance,
+                    offset: Vec2::new(glyph.x_offset, glyph.y_offset),
+                    cluster: glyph.cluster,
+                })
+                .collect::<Vec<_>>();
+            if glyphs.is_empty() {
+                continue;
+            }
+            let run = &self.runs[piece.run];
+            let (family, weight, style) = fonts::font_description(&run.font)
+                .unwrap_or_else(|| (run.style.font.clone(), run.style.weight, run.style.style));
+            let glyph_run = GlyphRun {
+                family,
+                weight,
+                style,
+                font_size: run.style.display_size(),
+                line,
+                range: piece.range.clone(),
+                origin,
+                is_rtl: run.rtl(),
+                glyphs,
+            };
+            result.push((glyph_run, run));
+        }
+        result
+    }
+
+    /// The glyphs that are drawn for `piece`, in visual order.
+    fn visible_glyphs<'a>(&'a self, piece: &LineRun) -> impl Iterator<Item = &'a ShapedGlyph> + 'a {
+        let text = self.text.as_bytes();
+        let run = &self.runs[piece.run];
+        run.glyphs_in(piece.range.clone())
+            // line breaks have advances, but no visible glyph
+            .filter(move |glyph| run.is_ellipsis || !matches!(text[glyph.cluster], b'\n' | b'\r'))
+    }
+
+    /// The glyphs that are drawn for `piece`, positioned for cairo with the
+    /// start of the piece's baseline at `origin` in its line's frame, along
+    /// with the transform they are drawn with.
+    ///
+    /// Upright glyphs in vertical lines are positioned down a column through
+    /// the middle of the line, which the transform turns to run along it.
+    pub(crate) fn piece_glyphs(
+        &self,
+        piece: &LineRun,
+        origin: Point,
+    ) -> (Affine, Vec<cairo::Glyph>) {
+        let run = &self.runs[piece.run];
+        if !run.upright {
+            return (Affine::default(), self.cairo_glyphs(piece, origin));
+        }
+        let extents = run.font.extents();
+        let middle = origin.y - (extents.ascent - extents.descent) / 2.0;
+        let transform = Affine::translate((origin.x, middle)) * Affine::rotate(-FRAC_PI_2);
+        let mut pen_y = 0.0;
+        let glyphs = self
+            .visible_glyphs(piece)
+            .map(|glyph| {
+                let cairo_glyph = cairo::Glyph {
+                    index: glyph.id.into(),
+                    x: glyph.x_offset,
+                    y: pen_y + glyph.y_offset,
+                };
+                pen_y += glyph.advance;
+                cairo_glyph
+            })
+            .collect();
+        (transform, glyphs)
+    }
+
+    /// The glyphs that are drawn for `piece`, positioned for cairo with the
+    /// start of the piece's baseline at `origin`.
+    pub(crate) fn cairo_glyphs(&self, piece: &LineRun, origin: Point) -> Vec<cairo::Glyph> {
+        let mut pen_x = origin.x;
+        self.visible_glyphs(piece)
+            .map(|glyph| {
+                let cairo_glyph = cairo::Glyph {
+                    index: glyph.id.into(),
+                    x: pen_x + glyph.x_offset,
+                    y: origin.y + glyph.y_offset,
+                };
+                pen_x += glyph.advance;
+                cairo_glyph
+            })
+            .collect()
+    }
+
+    fn update_width(&mut self, new_width: impl Into<Option<f64>>) -> Result<(), Error> {
+        let new_width = new_width.into().unwrap_or(std::f64::INFINITY);
+
+        // the runs are changed to suit the lines at the old width
+        self.runs = self.shaped.to_vec();
+        self.line_metrics = lines::calculate_line_metrics(
+            &self.text,
+            &self.runs,
+            &self.breaks,
+            new_width,
+            self.tabs.as_ref(),
+        );
+        self.set_tab_advances();
+        let truncated_pieces = self.truncate(new_width);
+        self.add_hyphens();
+        if self.alignment == TextAlignment::Justified && new_width.is_finite() {
+            self.justify(new_width, truncated_pieces.is_some());
+        }
+        let mut y_offset = 0.0;
+        for lm in &mut self.line_metrics {
+            let (baseline, height) =
+                util::apply_line_height(self.line_height, lm.baseline, lm.height);
+            lm.baseline = baseline;
+            lm.height = height;
+            lm.y_offset = y_offset;
+            y_offset += height;
+        }
+        if self.text.is_empty() {
+            let (baseline, height) = self.empty_line_metrics();
+            self.line_metrics.push(LineMetric {
+                baseline,
+                height,
+                ..Default::default()
+            })
+        } else if util::trailing_nlf(&self.text).is_some()
+            && self.line_metrics.len() < self.max_lines.unwrap_or(usize::MAX)
+        {
+            let newline_eof = self
+                .line_metrics
+                .last()
+                .map(|lm| LineMetric {
+                    start_offset: self.text.len(),
+                    end_offset: self.text.len(),
+                    height: lm.height,
+                    baseline: lm.baseline,
+                    y_offset: lm.y_offset + lm.height,
+                    trailing_whitespace: 0,
+                })
+                .unwrap();
+            self.line_metrics.push(newline_eof);
+        }
+
+        self.line_runs = self
+            .line_metrics
+            .iter()
+            .map(|lm| {
+                let rtl = self.bidi.paragraph_rtl(lm.start_offset);
+                line_runs(self.text_runs(), &self.text, lm.range(), rtl)
+            })
+            .collect();
+        let truncated = truncated_pieces.is_some();
+        if let Some(pieces) = truncated_pieces {
+            *self.line_runs.last_mut().unwrap() = pieces;
+        }
+
+        let last_line = self.line_metrics.len() - 1;
+        let line_widths = self
+            .line_metrics
+            .iter()
+            .enumerate()
+            .map(|(line, lm)| {
+                // the hidden text on a truncated line isn't part of its width
+                if truncated && line == last_line {
+                    let width = self.line_runs[line].iter().map(|piece| piece.width).sum();
+                    return (width, width);
+                }
+                let full_width = advance_width(self.text_runs(), &self.text, lm.range());
+                let non_ws_width = if lm.trailing_whitespace > 0 {
                     let non_ws_range = lm.start_offset..lm.end_offset - lm.trailing_whitespace;
-                    self.font.text_extents(&self.text[non_ws_range]).x_advance
+                    advance_width(self.text_runs(), &self.text, non_ws_range)
                 } else {
                     full_width
                 };
                 (non_ws_width, full_width)
             })
+            .collect::<Vec<_>>();
+
+        // Lines are aligned within the max width if there is one, and otherwise
+        // within the widest line. Trailing whitespace is ignored when aligning;
+        // in right-to-left paragraphs it hangs off the left edge of the line.
+        let align_width = if new_width.is_finite() {
+            new_width
+        } else {
+            line_widths.iter().fold(0.0, |acc: f64, w| acc.max(w.0))
+        };
+        let content_offsets = line_widths
+            .iter()
+            .zip(&self.line_metrics)
+            .map(|(&(width, _), lm)| {
+                let rtl = self.bidi.paragraph_rtl(lm.start_offset);
+                let free_space = (align_width - width).max(0.0);
+                match self.alignment {
+                    TextAlignment::Center => free_space / 2.0,
+                    // justified lines have no free space, except for the last
+                    // of each paragraph, which is aligned like `Start`
+                    TextAlignment::Start | TextAlignment::Justified if rtl => free_space,
+                    TextAlignment::End if !rtl => free_space,
+                    _ => 0.0,
+                }
+            })
+            .collect::<Vec<_>>();
+        self.x_offsets = content_offsets
+            .iter()
+            .zip(&line_widths)
+            .zip(&self.line_metrics)
+            .map(|((x, &(width, full_width)), lm)| {
+                if self.bidi.paragraph_rtl(lm.start_offset) {
+                    x - (full_width - width)
+                } else {
+                    *x
+                }
+            })
+            .collect();
+
+        let (width, ws_width) = line_widths
+            .iter()
+            .zip(&content_offsets)
+            .map(|(&(width, full_width), x)| (x + width, x + full_width))
             .fold((0.0, 0.0), |a: (f64, f64), b| (a.0.max(b.0), a.1.max(b.1)));
 
         let height = self
             .line_metrics
             .last()
             .map(|l| l.y_offset + l.height)
-            .unwrap_or_else(|| self.font.extents().height);
+            .unwrap_or_else(|| self.empty_line_metrics().1);
         self.size = Size::new(width, height);
         self.trailing_ws_width = ws_width;
+        self.image_bounds = self.decorated_pieces().fold(
+            self.size().to_rect(),
+            |bounds, (line, decoration, origin, width)| {
+                let decoration_bounds = decoration.bounds(origin, width);
+                bounds.union(
+                    self.line_transform(line)
+                        .transform_rect_bbox(decoration_bounds),
+                )
+            },
+        );
 
         Ok(())
     }
-}
 
-impl fmt::Debug for CairoTextLayout {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("CairoTextLayout").finish()
+    /// Each decoration in the layout, along with the number of the line, and
+    /// the baseline origin and width of the piece of it that the decoration
+    /// is drawn under, over or through.
+    fn decorated_pieces(&self) -> impl Iterator<Item = (usize, Decoration, Point, f64)> + '_ {
+        let lines = self
+            .line_metrics
+            .iter()
+            .zip(&self.line_runs)
+            .zip(&self.x_offsets);
+        lines
+            .enumerate()
+            .flat_map(move |(line, ((lm, pieces), x_offset))| {
+                pieces.iter().flat_map(move |piece| {
+                    let run = &self.runs[piece.run];
+                    let origin = Point::new(
+                        x_offset + piece.x,
+                        lm.y_offset + lm.baseline - run.style.baseline_offset(),
+                    );
+                    run.underline()
+                        .into_iter()
+                        .chain(run.overline())
+                        .chain(run.strikethrough())
+                        .map(move |decoration| (line, decoration, origin, piece.width))
+                })
+            })
     }
-}
 
-// NOTE this is the same as the old, non-line-aware version of hit_test_point
-// Future: instead of passing Font, should there be some other line-level text layout?
-fn hit_test_line_point(font: &ScaledFont, text: &str, point: Point) -> HitTestPoint {
-    // null case
-    if text.is_empty() {
-        return HitTestPoint::default();
+    /// The transform from the frame of `line`, where the line starts at the
+    /// origin and runs along the x axis, to the layout's coordinates.
+    ///
+    /// Lines in vertical layouts are turned a quarter turn clockwise, so that
+    /// their top edge becomes the right edge of their column.
+    pub(crate) fn line_transform(&self, line: usize) -> Affine {
+        if !self.writing_mode.is_vertical() {
+            return Affine::default();
+        }
+        let lm = &self.line_metrics[line];
+        let right = self.column_left(lm) + lm.height;
+        Affine::translate((right + lm.y_offset, 0.0)) * Affine::rotate(FRAC_PI_2)
     }
 
-    // get bounds
-    // TODO handle if string is not null yet count is 0?
-    let end = UnicodeSegmentation::graphemes(text, true).count() - 1;
-    let end_bounds = match get_grapheme_boundaries(font, text, end) {
-        Some(bounds) => bounds,
-        None => return HitTestPoint::default(),
-    };
+    /// The x position of the left edge of the column for the line `lm`, in a
+    /// vertical layout.
+    fn column_left(&self, lm: &LineMetric) -> f64 {
+        match self.writing_mode {
+            WritingMode::VerticalRl => self.size.height - lm.y_offset - lm.height,
+            _ => lm.y_offset,
+        }
+    }
 
-    let start = 0;
-    let start_bounds = match get_grapheme_boundaries(font, text, start) {
-        Some(bounds) => bounds,
-        None => return HitTestPoint::default(),
-    };
+    /// Whether `x` is within a column of a vertical layout, and the number of
+    /// the column it is in, or of the nearest one.
+    fn column_at(&self, x: f64) -> (bool, usize) {
+        let line = self.line_metrics.iter().position(|lm| {
+            let left = self.column_left(lm);
+            x >= left && x < left + lm.height
+        });
+        if let Some(line) = line {
+            return (true, line);
+        }
+        // columns run from right to left in vertical-rl
+        let before_first = (x < 0.0) != (self.writing_mode == WritingMode::VerticalRl);
+        let line = if before_first {
+            0
+        } else {
+            self.line_metrics.len() - 1
+        };
+        (false, line)
+    }
+
+    /// Hit test a point against line `line_num`; `inside` is whether the
+    /// point is within the line's extent across it.
+    fn hit_test_line(&self, line_num: usize, point: Point, inside: bool) -> HitTestPoint {
+        let lm = &self.line_metrics[line_num];
+
+        // Trailing whitespace is remove for the line
+        let line = &self.text[lm.range()];
+
+        // the line is hit tested in its own frame, as if it started at x = 0
+        let point = self.line_transform(line_num).inverse() * point;
+        let point = Point::new(point.x - self.x_offsets[line_num], point.y);
+        let pieces = &self.line_runs[line_num];
+        let mut htp = hit_test_line_point(&self.runs, pieces, &self.text, lm.range(), point);
+        htp.idx += lm.start_offset;
+        if htp.idx == lm.end_offset {
+            htp.idx -= util::trailing_nlf(line).unwrap_or(0);
+        }
+        htp.is_inside &= inside;
+        htp
+    }
+
+    /// Set the advance of each tab from the tab stops, for the current lines.
+    fn set_tab_advances(&mut self) {
+        let tabs = match &self.tabs {
+            Some(tabs) => tabs,
+            None => return,
+        };
+        let advances = self
+            .line_metrics
+            .iter()
+            .flat_map(|lm| lines::tab_advances(&self.runs, &self.text, lm.range(), tabs))
+            .collect::<Vec<_>>();
+        for (pos, advance) in advances {
+            let extra = advance - self.advance(pos..pos + 1);
+            if let Some(run) = self.run_at_mut(pos) {
+                run.widen_cluster(pos, extra);
+            }
+        }
+    }
 
-    // first test beyond ends
-    if point.x > end_bounds.trailing {
-        return HitTestPoint::new(text.len(), false);
+    /// Show a hyphen at the end of each line that breaks a word.
+    fn add_hyphens(&mut self) {
+        let ends = self
+            .line_metrics
+            .iter()
+            .map(|lm| lm.end_offset)
+            .filter(|&end| matches!(self.break_at(end), Some(brk) if brk.hyphen))
+            .collect::<Vec<_>>();
+        for end in ends {
+            if let Some(run) = self.run_at_mut(end - 1) {
+                run.add_hyphen(end - 1);
+            }
+        }
     }
-    if point.x <= start_bounds.leading {
-        return HitTestPoint::default();
+
+    /// Widen the lines to `width`, except for the last line of each
+    /// paragraph and a truncated last line, by adding space where the
+    /// justification allows.
+    fn justify(&mut self, width: f64, truncated: bool) {
+        let line_count = self.line_metrics.len();
+        let mut extras = Vec::new();
+        for (line, lm) in self.line_metrics.iter().enumerate() {
+            // the text always ends with a hard break
+            let paragraph_end = self
+                .break_at(lm.end_offset)
+                .map(|brk| brk.hard)
+                .unwrap_or(true);
+            if paragraph_end || (truncated && line + 1 == line_count) {
+                continue;
+            }
+            let content = lm.start_offset..lm.end_offset - lm.trailing_whitespace;
+            let free_space = width - self.advance(content.clone());
+            let points = lines::justification_points(&self.text, content, self.justification);
+            if free_space <= 0.0 || points.is_empty() {
+                continue;
+            }
+            let extra = free_space / points.len() as f64;
+            extras.extend(points.into_iter().map(|pos| (pos, extra)));
+        }
+        for (pos, extra) in extras {
+            if let Some(run) = self.run_at_mut(pos) {
+                run.widen_cluster(pos, extra);
+            }
+        }
+    }
+
+    /// The break at `offset`, if lines can end there.
+    fn break_at(&self, offset: usize) -> Option<&Break> {
+        self.breaks
+            .binary_search_by_key(&offset, |brk| brk.offset)
+            .ok()
+            .map(|idx| &self.breaks[idx])
     }
 
-    // then test the beginning and end (common cases)
-    if let Some(hit) = point_x_in_grapheme(point.x, &start_bounds) {
-        return hit;
+    /// The run of the layout's text that contains `pos`.
+    fn run_at_mut(&mut self, pos: usize) -> Option<&mut StyleRun> {
+        self.runs
+            .iter_mut()
+            .find(|run| !run.is_ellipsis && run.range.contains(&pos))
     }
-    if let Some(hit) = point_x_in_grapheme(point.x, &end_bounds) {
-        return hit;
+
+    /// The runs of the layout's text, without any ellipsis.
+    fn text_runs(&self) -> &[StyleRun] {
+        match self.runs.last() {
+            Some(run) if run.is_ellipsis => &self.runs[..self.runs.len() - 1],
+            _ => &self.runs,
+        }
     }
 
-    // Now that we know it's not beginning or end, begin binary search.
-    // Iterative style
-    let mut left = start;
-    let mut right = end;
-    loop {
-        // pick halfway point
-        let middle = left + ((right - left) / 2);
+    /// Cut off the lines after `max_lines`, and replace the text that is
+    /// hidden on the last line with an ellipsis if the overflow mode asks for
+    /// one; the last line is also cut off if it is wider than `width`.
+    ///
+    /// This returns the pieces of the last line if there is an ellipsis.
+    fn truncate(&mut self, width: f64) -> Option<Vec<LineRun>> {
+        let max_lines = self.max_lines?;
+        if self.line_metrics.len() < max_lines {
+            return None;
+        }
+        let lines_hidden = self.line_metrics.len() > max_lines;
+        self.line_metrics.truncate(max_lines);
+        if self.overflow == TextOverflow::Clip {
+            return None;
+        }
+
+        let text = &self.text;
+        let lm = self.line_metrics[max_lines - 1].clone();
+        let content_end = lm.start_offset + text[lm.range()].trim_end().len();
+        if !lines_hidden && self.advance(lm.start_offset..content_end) <= width {
+            return None;
+        }
 
-        let grapheme_bounds = match get_grapheme_boundaries(font, text, middle) {
-            Some(bounds) => bounds,
-            None => return HitTestPoint::default(),
+        let rtl = self.bidi.paragraph_rtl(lm.start_offset);
+        let level = if rtl { Level::rtl() } else { Level::ltr() };
+        let text_runs = self.text_runs();
+        let (style_run, _) = run_parts(text_runs, content_end.saturating_sub(1)..content_end)
+            .next()
+            .unwrap();
+        let mut ellipsis = StyleRun::ellipsis(&text_runs[style_run], level);
+        let available = width - ellipsis.clusters[0].advance;
+
+        let (hidden, tail) = match self.overflow {
+            // the end of the text is taken from its last paragraph
+            TextOverflow::MiddleEllipsis => {
+                let text_end = text.trim_end().len();
+                let paragraph_start = text[..text_end]
+                    .rfind(['\n', '\r'])
+                    .map(|idx| idx + 1)
+                    .unwrap_or(0)
+                    .max(lm.start_offset);
+                let tail_start = self.fit_end(paragraph_start..text_end, available / 2.0);
+                let tail_width = self.advance(tail_start..text_end);
+                let head_end = content_end.min(tail_start);
+                let head_end = self.fit_start(lm.start_offset..head_end, available - tail_width);
+                (head_end..tail_start, tail_start..text_end)
+            }
+            _ => {
+                let head_end = self.fit_start(lm.start_offset..content_end, available);
+                (head_end..text.len(), text.len()..text.len())
+            }
         };
 
-        if let Some(hit) = point_x_in_grapheme(point.x, &grapheme_bounds) {
-            return hit;
+        let line_start = lm.start_offset;
+        let text_len = text.len();
+        if let Some(lm) = self.line_metrics.last_mut() {
+            lm.end_offset = text_len;
+            lm.trailing_whitespace = text_len - tail.end.max(hidden.end);
         }
 
-        // since it's not a hit, check if closer to start or finish
-        // and move the appropriate search boundary
-        if point.x < grapheme_bounds.leading {
-            right = middle;
-        } else if point.x > grapheme_bounds.trailing {
-            left = middle + 1;
+        ellipsis.hide(hidden.clone());
+        let ellipsis_piece = LineRun {
+            run: self.runs.len(),
+            range: hidden.clone(),
+            x: 0.0,
+            width: ellipsis.clusters[0].advance,
+        };
+        let text_runs = self.text_runs();
+        let head = line_runs(text_runs, &self.text, line_start..hidden.start, rtl);
+        let tail = if tail.is_empty() {
+            Vec::new()
         } else {
-            unreachable!("hit_test_point conditional is exhaustive");
+            line_runs(text_runs, &self.text, tail, rtl)
+        };
+        self.runs.push(ellipsis);
+
+        let mut segments = vec![head, vec![ellipsis_piece], tail];
+        if rtl {
+            segments.reverse();
+        }
+        let mut x = 0.0;
+        let mut pieces = segments.into_iter().flatten().collect::<Vec<_>>();
+        for piece in &mut pieces {
+            piece.x = x;
+            x += piece.width;
+        }
+        Some(pieces)
+    }
+
+    /// The advance width of `range` of the text.
+    fn advance(&self, range: Range<usize>) -> f64 {
+        advance_width(self.text_runs(), &self.text, range)
+    }
+
+    /// The end of the longest start of `range` that is at most `width` wide,
+    /// not counting trailing whitespace, on a grapheme boundary.
+    fn fit_start(&self, range: Range<usize>, width: f64) -> usize {
+        let mut end = range.start;
+        for (idx, grapheme) in self.text[range.clone()].grapheme_indices(true) {
+            let next = range.start + idx + grapheme.len();
+            if self.advance(range.start..next) > width {
+                break;
+            }
+            end = next;
+        }
+        range.start + self.text[range.start..end].trim_end().len()
+    }
+
+    /// The start of the longest end of `range` that is at most `width` wide,
+    /// not counting leading whitespace, on a grapheme boundary.
+    fn fit_end(&self, range: Range<usize>, width: f64) -> usize {
+        let mut start = range.end;
+        for (idx, _) in self.text[range.clone()].grapheme_indices(true).rev() {
+            let prev = range.start + idx;
+            if self.advance(prev..range.end) > width {
+                break;
+            }
+            start = prev;
         }
+        range.end - self.text[start..range.end].trim_start().len()
+    }
+
+    /// The baseline and height of the line in an empty layout.
+    fn empty_line_metrics(&self) -> (f64, f64) {
+        let (baseline, height) = vertical_metrics(self.text_runs(), 0..0);
+        util::apply_line_height(self.line_height, baseline, height)
     }
 }
 
-// NOTE this is the same as the old, non-line-aware version of hit_test_text_position.
-// Future: instead of passing Font, should there be some other line-level text layout?
-fn hit_test_line_position(font: &ScaledFont, text: &str, text_position: usize) -> f64 {
-    // Using substrings with unicode grapheme awareness
+impl fmt::Debug for CairoTextLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CairoTextLayout").finish()
+    }
+}
 
-    let text_len = text.len();
+/// Hit test a point against a line, which is laid out starting at x = 0.
+///
+/// The cluster under the point is found from the line's shaped runs, and then
+/// the point is tested against the graphemes in that cluster.
+fn hit_test_line_point(
+    runs: &[StyleRun],
+    pieces: &[LineRun],
+    text: &str,
+    line: Range<usize>,
+    point: Point,
+) -> HitTestPoint {
+    let line_text = &text[line.clone()];
+    // null case
+    if line_text.is_empty() || pieces.is_empty() {
+        return HitTestPoint::default();
+    }
 
-    if text_position == 0 {
-        return 0.0;
+    // points beyond either end of the line hit the position at that edge,
+    // which depends on the direction of the run there.
+    if point.x <= 0.0 {
+        let piece = &pieces[0];
+        let idx = if runs[piece.run].rtl() {
+            piece.range.end
+        } else {
+            piece.range.start
+        };
+        return HitTestPoint::new(idx - line.start, false);
     }
 
-    if text_position as usize >= text_len {
-        return font.text_extents(&text).x_advance;
+    for piece in pieces {
+        let run = &runs[piece.run];
+        let mut x = piece.x;
+        for cluster in run.clusters_in(piece.range.clone()) {
+            if point.x <= x + cluster.advance {
+                if run.is_ellipsis {
+                    return HitTestPoint::new(piece.range.start - line.start, true);
+                }
+                let start = cluster.range.start.max(line.start);
+                let end = cluster.range.end.min(line.end);
+                let first = line_text[..start - line.start].graphemes(true).count();
+                let count = text[start..end].graphemes(true).count();
+                return (first..first + count)
+                    .filter_map(|idx| get_grapheme_boundaries(run, piece, text, line.clone(), idx))
+                    .find_map(|bounds| point_x_in_grapheme(point.x, &bounds))
+                    .unwrap_or_else(|| HitTestPoint::new(end - line.start, true));
+            }
+            x += cluster.advance;
+        }
     }
 
-    // Already checked that text_position > 0 and text_position < count.
+    let piece = &pieces[pieces.len() - 1];
+    let idx = if runs[piece.run].rtl() {
+        piece.range.start
+    } else {
+        piece.range.end
+    };
+    HitTestPoint::new(idx - line.start, false)
+}
+
+/// The x position of a position in a line, which is laid out starting at x = 0.
+///
+/// A position is placed at the leading edge of the character after it, or at
+/// the trailing edge of the last character on the line.
+fn hit_test_line_position(
+    runs: &[StyleRun],
+    pieces: &[LineRun],
+    text: &str,
+    line: Range<usize>,
+    text_position: usize,
+) -> f64 {
+    let line_text = &text[line.clone()];
+
     // If text position is not at a grapheme boundary, use the text position of current
-    // grapheme cluster. But return the original text position
-    // Use the indices (byte offset, which for our purposes = utf8 code units).
-    let grapheme_indices = UnicodeSegmentation::grapheme_indices(text, true)
-        .take_while(|(byte_idx, _s)| text_position >= *byte_idx);
-
-    grapheme_indices
-        .last()
-        .map(|(idx, _)| font.text_extents(&text[..idx]).x_advance)
-        .unwrap_or_else(|| font.text_extents(&text).x_advance)
+    // grapheme cluster.
+    let text_position = if text_position >= line_text.len() {
+        line_text.len()
+    } else {
+        UnicodeSegmentation::grapheme_indices(line_text, true)
+            .map(|(idx, _)| idx)
+            .take_while(|idx| *idx <= text_position)
+            .last()
+            .unwrap_or(0)
+    };
+    let position = line.start + text_position;
+
+    pieces
+        .iter()
+        .find(|piece| piece.range.contains(&position))
+        .or_else(|| pieces.iter().find(|piece| piece.range.end == position))
+        .map(|piece| {
+            let run = &runs[piece.run];
+            piece.x + run.x_in_part(text, piece.range.clone(), position)
+        })
+        .unwrap_or(0.0)
 }
 
 fn scale_matrix(scale: f64) -> Matrix {
@@ -445,7 +1995,10 @@ fn scale_matrix(scale: f64) -> Matrix {
 #[cfg(test)]
 mod test {
     use super::*;
-    use piet::TextLayout;
+    use piet::{
+        BaselineShift, Color, FontFeature, FontStretch, FontVariation, FontWeight, Hyphens,
+        TabAlignment, TabInterval, TextLayout,
+    };
 
     macro_rules! assert_close {
         ($val:expr, $target:expr, $tolerance:expr) => {{
@@ -631,32 +2184,36 @@ mod test {
         let mut text_layout = CairoText::new();
 
         let layout = text_layout.new_text_layout("piet text!").build().unwrap();
-        println!("text pos 4: {:?}", layout.hit_test_text_position(4)); // 23.0
-        println!("text pos 5: {:?}", layout.hit_test_text_position(5)); // 27.0
+        println!("text pos 4: {:?}", layout.hit_test_text_position(4)); // 23.04
+        println!("text pos 5: {:?}", layout.hit_test_text_position(5)); // 26.85
+
+        let pos_4 = layout.hit_test_text_position(4).point.x;
+        let pos_5 = layout.hit_test_text_position(5).point.x;
 
         // test hit test point
         // all inside
-        let pt = layout.hit_test_point(Point::new(22.5, 0.0));
+        let pt = layout.hit_test_point(Point::new(pos_4 - 0.5, 0.0));
         assert_eq!(pt.idx, 4);
-        let pt = layout.hit_test_point(Point::new(23.0, 0.0));
+        let pt = layout.hit_test_point(Point::new(pos_4, 0.0));
         assert_eq!(pt.idx, 4);
-        let pt = layout.hit_test_point(Point::new(25.0, 0.0));
+        let pt = layout.hit_test_point(Point::new((pos_4 + pos_5) / 2.0, 0.0));
         assert_eq!(pt.idx, 5);
-        let pt = layout.hit_test_point(Point::new(26.0, 0.0));
+        let pt = layout.hit_test_point(Point::new(pos_5 - 1.0, 0.0));
         assert_eq!(pt.idx, 5);
-        let pt = layout.hit_test_point(Point::new(27.0, 0.0));
+        let pt = layout.hit_test_point(Point::new(pos_5, 0.0));
         assert_eq!(pt.idx, 5);
-        let pt = layout.hit_test_point(Point::new(28.0, 0.0));
+        let pt = layout.hit_test_point(Point::new(pos_5 + 1.0, 0.0));
         assert_eq!(pt.idx, 5);
 
         // outside
-        println!("layout_width: {:?}", layout.size().width); // 56.0
+        let width = layout.size().width;
+        println!("layout_width: {:?}", width); // 55.35
 
-        let pt = layout.hit_test_point(Point::new(56.0, 0.0));
+        let pt = layout.hit_test_point(Point::new(width, 0.0));
         assert_eq!(pt.idx, 10); // last text position
         assert_eq!(pt.is_inside, true);
 
-        let pt = layout.hit_test_point(Point::new(57.0, 0.0));
+        let pt = layout.hit_test_point(Point::new(width + 1.0, 0.0));
         assert_eq!(pt.idx, 10); // last text position
         assert_eq!(pt.is_inside, false);
 
@@ -771,36 +2328,24 @@ mod test {
 
         let mut text_layout = CairoText::new();
         let layout = text_layout.new_text_layout(input).build().unwrap();
-        //println!("text pos 2: {:?}", layout.hit_test_text_position(2)); // 6.99999999
-        //println!("text pos 9: {:?}", layout.hit_test_text_position(9)); // 24.0
-        //println!("text pos 10: {:?}", layout.hit_test_text_position(10)); // 32.0
-        //println!("text pos 14: {:?}", layout.hit_test_text_position(14)); // 39.0, line width
-
-        let pt = layout.hit_test_point(Point::new(2.0, 0.0));
-        assert_eq!(pt.idx, 0);
-        let pt = layout.hit_test_point(Point::new(4.0, 0.0));
-        assert_eq!(pt.idx, 2);
-        let pt = layout.hit_test_point(Point::new(7.0, 0.0));
-        assert_eq!(pt.idx, 2);
-        let pt = layout.hit_test_point(Point::new(10.0, 0.0));
-        assert_eq!(pt.idx, 2);
-        let pt = layout.hit_test_point(Point::new(14.0, 0.0));
-        assert_eq!(pt.idx, 2);
-        let pt = layout.hit_test_point(Point::new(18.0, 0.0));
-        assert_eq!(pt.idx, 9);
-        let pt = layout.hit_test_point(Point::new(23.0, 0.0));
-        assert_eq!(pt.idx, 9);
-        let pt = layout.hit_test_point(Point::new(26.0, 0.0));
-        assert_eq!(pt.idx, 9);
-        let pt = layout.hit_test_point(Point::new(29.0, 0.0));
-        assert_eq!(pt.idx, 10);
-        let pt = layout.hit_test_point(Point::new(32.0, 0.0));
-        assert_eq!(pt.idx, 10);
-        let pt = layout.hit_test_point(Point::new(35.5, 0.0));
-        assert_eq!(pt.idx, 14);
-        let pt = layout.hit_test_point(Point::new(38.0, 0.0));
-        assert_eq!(pt.idx, 14);
-        let pt = layout.hit_test_point(Point::new(40.0, 0.0));
+        //println!("text pos 2: {:?}", layout.hit_test_text_position(2)); // 7.38
+        //println!("text pos 9: {:?}", layout.hit_test_text_position(9)); // 24.64
+        //println!("text pos 10: {:?}", layout.hit_test_text_position(10)); // 32.27
+        //println!("text pos 14: {:?}", layout.hit_test_text_position(14)); // 44.43, line width
+
+        // a point in the left half of a grapheme hits its start, and one in
+        // the right half (or on the middle) hits its end
+        let x = |idx| layout.hit_test_text_position(idx).point.x;
+        for &(start, end) in &[(0, 2), (2, 9), (9, 10), (10, 14)] {
+            let (x0, x1) = (x(start), x(end));
+            let pt = layout.hit_test_point(Point::new(x0 + 0.25 * (x1 - x0), 0.0));
+            assert_eq!(pt.idx, start);
+            let pt = layout.hit_test_point(Point::new(x0 + 0.5 * (x1 - x0), 0.0));
+            assert_eq!(pt.idx, end);
+            let pt = layout.hit_test_point(Point::new(x0 + 0.75 * (x1 - x0), 0.0));
+            assert_eq!(pt.idx, end);
+        }
+        let pt = layout.hit_test_point(Point::new(layout.size().width + 1.0, 0.0));
         assert_eq!(pt.idx, 14);
     }
 
@@ -1332,4 +2877,1424 @@ mod test {
         assert_eq!(pt.idx, 5);
         assert_eq!(pt.is_inside, false);
     }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn shaping_applies_kerning() {
+        let mut text = CairoText::new();
+        let sans = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Anaheim-Regular.ttf"
+            ))
+            .unwrap();
+        let width = |text: &mut CairoText, s: &str| {
+            let layout = text
+                .new_text_layout(s.to_owned())
+                .font(sans.clone(), 12.0)
+                .build()
+                .unwrap();
+            layout.size().width
+        };
+        let kerned = width(&mut text, "AV");
+        let separate = width(&mut text, "A") + width(&mut text, "V");
+        assert!(kerned < separate - 0.5, "{} {}", kerned, separate);
+    }
+
+    #[test]
+    fn rtl_run_positions() {
+        let mut text = CairoText::new();
+        let input = "שלום";
+        let layout = text.new_text_layout(input).build().unwrap();
+        let width = layout.size().width;
+
+        // the start of the text is on the right
+        assert_close!(layout.hit_test_text_position(0).point.x, width, 0.01);
+        assert_close!(
+            layout.hit_test_text_position(input.len()).point.x,
+            0.0,
+            0.01
+        );
+        let second = layout.hit_test_text_position(2).point.x;
+        assert!(second > 0.0 && second < width);
+
+        let pt = layout.hit_test_point(Point::new(second + 0.1, 0.0));
+        assert_eq!(pt.idx, 2);
+        let pt = layout.hit_test_point(Point::new(width - 0.1, 0.0));
+        assert_eq!(pt.idx, 0);
+    }
+
+    #[test]
+    fn bidi_visual_order() {
+        let mut text = CairoText::new();
+        let input = "abc אבג def";
+        let layout = text.new_text_layout(input).build().unwrap();
+        let x = |idx| layout.hit_test_text_position(idx).point.x;
+
+        // the hebrew word is reversed, but stays between the latin ones
+        assert!(x(3) < x(8));
+        assert!(x(8) < x(6));
+        assert!(x(6) < x(4));
+        assert!(x(4) < x(11));
+        assert_close!(x(4), x(10), 0.01);
+
+        let pt = layout.hit_test_point(Point::new(x(6) + 0.1, 0.0));
+        assert_eq!(pt.idx, 6);
+        let pt = layout.hit_test_point(Point::new(x(8) - 0.1, 0.0));
+        assert_eq!(pt.idx, 8);
+
+        // a selection across the boundary is split into separate rects
+        let rects = layout.rects_for_range(2..6);
+        assert_eq!(rects.len(), 2);
+        assert_close!(rects[0].x0, x(2), 0.01);
+        assert_close!(rects[1].x1, x(4), 0.01);
+    }
+
+    #[test]
+    fn rtl_paragraph_alignment() {
+        let mut text = CairoText::new();
+        let input = "שלום עולם";
+        let layout = text
+            .new_text_layout(input)
+            .max_width(200.0)
+            .build()
+            .unwrap();
+        // the start of a right-to-left paragraph is on the right
+        assert_close!(layout.hit_test_text_position(0).point.x, 200.0, 0.01);
+        let pt = layout.hit_test_point(Point::new(250.0, 0.0));
+        assert_eq!(pt.idx, 0);
+        assert!(!pt.is_inside);
+        let pt = layout.hit_test_point(Point::new(-10.0, 0.0));
+        assert_eq!(pt.idx, input.len());
+
+        let layout = text
+            .new_text_layout(input)
+            .max_width(200.0)
+            .alignment(TextAlignment::End)
+            .build()
+            .unwrap();
+        assert_close!(
+            layout.hit_test_text_position(input.len()).point.x,
+            0.0,
+            0.01
+        );
+    }
+
+    #[test]
+    fn cluster_positions() {
+        let mut text = CairoText::new();
+        // a combining accent is shaped into the same cluster as its base
+        let input = "ae\u{301}b";
+        let layout = text.new_text_layout(input).build().unwrap();
+        let after_a = layout.hit_test_text_position(1).point.x;
+        let after_e = layout.hit_test_text_position(4).point.x;
+        assert!(after_e > after_a);
+        // a position inside the grapheme resolves to its start
+        assert_eq!(layout.hit_test_text_position(2).point.x, after_a);
+
+        let pt = layout.hit_test_point(Point::new(after_a + 0.1, 0.0));
+        assert_eq!(pt.idx, 1);
+        let pt = layout.hit_test_point(Point::new(after_e - 0.1, 0.0));
+        assert_eq!(pt.idx, 4);
+    }
+
+    #[test]
+    fn load_font_registers_family() {
+        let mut text = CairoText::new();
+        let regular = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Anaheim-Regular.ttf"
+            ))
+            .unwrap();
+        let bold = text
+            .load_font(include_bytes!("../../snapshots/resources/Anaheim-Bold.ttf"))
+            .unwrap();
+        assert_eq!(regular, bold);
+
+        // loading the same data again returns the same family
+        let again = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Anaheim-Regular.ttf"
+            ))
+            .unwrap();
+        assert_eq!(regular, again);
+
+        // lookups are case-insensitive, and return the font's own name
+        let lookup = text.font_family(&regular.name().to_uppercase());
+        assert_eq!(lookup, Some(regular.clone()));
+
+        // the loaded faces are used for layout, with the closest weight
+        let regular_layout = text
+            .new_text_layout("piet text")
+            .font(regular.clone(), 16.0)
+            .build()
+            .unwrap();
+        let bold_layout = text
+            .new_text_layout("piet text")
+            .font(regular, 16.0)
+            .default_attribute(piet::FontWeight::BOLD)
+            .build()
+            .unwrap();
+        assert!(regular_layout.size().width > 0.0);
+        assert!(bold_layout.size().width > regular_layout.size().width);
+    }
+
+    #[test]
+    fn fallback_for_missing_glyphs() {
+        let mut text = CairoText::new();
+        // the monospace font has no Hebrew glyphs
+        let mono = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Inconsolata-variable.ttf"
+            ))
+            .unwrap();
+        let sans = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Anaheim-Regular.ttf"
+            ))
+            .unwrap();
+        let face_of = |run: &StyleRun| unsafe {
+            cairo_sys::cairo_scaled_font_get_font_face(run.font.to_raw_none())
+        };
+        let loaded =
+            |family: &FontFamily| fonts::loaded_face(family, FaceQuery::default()).unwrap().0;
+        let mono_face = loaded(&mono);
+        let sans_face = loaded(&sans);
+
+        let layout = text
+            .new_text_layout("abc def")
+            .font(mono.clone(), 16.0)
+            .build()
+            .unwrap();
+        assert_eq!(layout.runs.len(), 1);
+
+        // loaded fonts are preferred to system fonts as fallbacks
+        let input = "abc שלום def";
+        let layout = text
+            .new_text_layout(input)
+            .font(mono, 16.0)
+            .build()
+            .unwrap();
+        let ranges = layout
+            .runs
+            .iter()
+            .map(|run| run.range.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, vec![0..4, 4..12, 12..input.len()]);
+        assert_eq!(face_of(&layout.runs[0]), mono_face.to_raw_none());
+        assert_eq!(face_of(&layout.runs[1]), sans_face.to_raw_none());
+        assert_eq!(face_of(&layout.runs[2]), mono_face.to_raw_none());
+        assert!(layout.runs[1].glyphs.iter().all(|glyph| glyph.id != 0));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn font_family_stack() {
+        let mut text = CairoText::new();
+        let mono = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Inconsolata-variable.ttf"
+            ))
+            .unwrap();
+        text.load_font(include_bytes!(
+            "../../snapshots/resources/Anaheim-Regular.ttf"
+        ))
+        .unwrap();
+        let face_of = |run: &StyleRun| unsafe {
+            cairo_sys::cairo_scaled_font_get_font_face(run.font.to_raw_none())
+        };
+        let mono_face = fonts::loaded_face(&mono, FaceQuery::default()).unwrap().0;
+
+        // missing families are skipped
+        let css = format!("No Such Family, '{}', serif", mono.name());
+        let stack = FontFamily::from_css(&css).unwrap();
+        let layout = text
+            .new_text_layout("abc")
+            .font(stack, 16.0)
+            .build()
+            .unwrap();
+        assert_eq!(face_of(&layout.runs[0]), mono_face.to_raw_none());
+
+        // characters the first family doesn't support use the next family
+        // in the stack, rather than a fallback font; this needs a system
+        // family that the fallback wouldn't choose
+        if !fonts::system_family_exists("DejaVu Serif") {
+            return;
+        }
+        let serif = CairoFont::new(FontFamily::new_unchecked("DejaVu Serif")).resolve_simple(16.0);
+        let stack = FontFamily::stack(vec![mono, FontFamily::new_unchecked("DejaVu Serif")]);
+        let layout = text
+            .new_text_layout("abc Ⴀ")
+            .font(stack, 16.0)
+            .build()
+            .unwrap();
+        assert_eq!(layout.runs.len(), 2);
+        assert_eq!(face_of(&layout.runs[0]), mono_face.to_raw_none());
+        assert_eq!(face_of(&layout.runs[1]), unsafe {
+            cairo_sys::cairo_scaled_font_get_font_face(serif.scaled.to_raw_none())
+        });
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn system_font_weight_and_stretch() {
+        // this tests fontconfig's matching of system fonts, so it can't use
+        // a bundled font, and is skipped if the faces it expects are missing
+        let faces = fonts::faces(&FontFamily::new_unchecked("DejaVu Sans"));
+        let has_face = |weight: piet::FontWeight, stretch| {
+            faces
+                .iter()
+                .any(|face| face.weight == weight && face.stretch == stretch)
+        };
+        if !has_face(piet::FontWeight::EXTRA_LIGHT, FontStretch::Normal)
+            || !has_face(piet::FontWeight::BOLD, FontStretch::Condensed)
+        {
+            return;
+        }
+
+        let style_of = |weight, stretch, style| {
+            let query = FaceQuery {
+                weight,
+                stretch,
+                style,
+            };
+            let font =
+                CairoFont::new(FontFamily::new_unchecked("DejaVu Sans")).resolve(12.0, query, &[]);
+            fonts::style_name(&font.scaled)
+        };
+        let (normal, condensed) = (FontStretch::Normal, FontStretch::Condensed);
+        let (regular, italic) = (FontStyle::Regular, FontStyle::Italic);
+
+        assert_eq!(style_of(piet::FontWeight::REGULAR, normal, regular), "Book");
+        assert_eq!(style_of(piet::FontWeight::BOLD, normal, regular), "Bold");
+        // weights between those of the available faces go to the nearest
+        assert_eq!(
+            style_of(piet::FontWeight::EXTRA_LIGHT, normal, regular),
+            "ExtraLight"
+        );
+        assert_eq!(
+            style_of(piet::FontWeight::LIGHT, normal, regular),
+            "ExtraLight"
+        );
+        assert_eq!(
+            style_of(piet::FontWeight::EXTRA_BOLD, normal, regular),
+            "Bold"
+        );
+        assert_eq!(
+            style_of(piet::FontWeight::REGULAR, normal, italic),
+            "Oblique"
+        );
+        assert_eq!(
+            style_of(piet::FontWeight::REGULAR, condensed, regular),
+            "Condensed"
+        );
+        assert_eq!(
+            style_of(piet::FontWeight::BOLD, condensed, italic),
+            "Condensed Bold Oblique"
+        );
+    }
+
+    #[test]
+    fn synthesize_missing_styles() {
+        let mut text = CairoText::new();
+        // loaded fonts are shared with the other tests, and some of them load
+        // a bold face for Anaheim's family, so use a family with one face
+        let family = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Inconsolata-variable.ttf"
+            ))
+            .unwrap();
+        let synthesized = |weight, style| {
+            let query = FaceQuery {
+                weight,
+                stretch: FontStretch::Normal,
+                style,
+            };
+            let (face, _) = fonts::loaded_face(&family, query).unwrap();
+            fonts::synthesized(&face)
+        };
+
+        assert_eq!(
+            synthesized(piet::FontWeight::REGULAR, FontStyle::Regular),
+            (false, false)
+        );
+        assert_eq!(
+            synthesized(piet::FontWeight::BOLD, FontStyle::Regular),
+            (true, false)
+        );
+        assert_eq!(
+            synthesized(piet::FontWeight::MEDIUM, FontStyle::Italic),
+            (false, true)
+        );
+        assert_eq!(
+            synthesized(piet::FontWeight::BLACK, FontStyle::Italic),
+            (true, true)
+        );
+        // synthesized faces are reused
+        let query = FaceQuery {
+            weight: piet::FontWeight::BOLD,
+            stretch: FontStretch::Normal,
+            style: FontStyle::Italic,
+        };
+        let first = fonts::loaded_face(&family, query).unwrap().0;
+        let second = fonts::loaded_face(&family, query).unwrap().0;
+        assert_eq!(first.to_raw_none(), second.to_raw_none());
+    }
+
+    #[test]
+    fn system_font_data_is_shared_between_threads() {
+        let data = || {
+            fonts::system_face(&FontFamily::SANS_SERIF, FaceQuery::default()).map(|(_, data)| data)
+        };
+        // without any system fonts there's nothing to share
+        let here = match data() {
+            Some(data) => data,
+            None => return,
+        };
+        let there = std::thread::spawn(data).join().unwrap().unwrap();
+        assert!(here.shares_data(&there));
+    }
+
+    #[test]
+    fn loaded_fonts_are_shared_between_threads() {
+        let family = CairoText::new()
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Inconsolata-variable.ttf"
+            ))
+            .unwrap();
+        std::thread::spawn(move || {
+            let mut text = CairoText::new();
+            assert_eq!(text.font_family(family.name()), Some(family.clone()));
+            let layout = text
+                .new_text_layout("abc")
+                .font(family.clone(), 16.0)
+                .build()
+                .unwrap();
+            let loaded = fonts::loaded_face(&family, FaceQuery::default()).unwrap().0;
+            let face = unsafe {
+                cairo_sys::cairo_scaled_font_get_font_face(layout.runs[0].font.to_raw_none())
+            };
+            assert_eq!(face, loaded.to_raw_none());
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn load_font_rejects_invalid_data() {
+        let mut text = CairoText::new();
+        assert!(text.load_font(b"definitely not a font").is_err());
+        assert!(text.load_font(&[]).is_err());
+    }
+
+    #[test]
+    fn range_attributes_affect_measurement() {
+        let mut text = CairoText::new();
+        let input = "piet text";
+
+        let plain = text.new_text_layout(input).build().unwrap();
+        let large_piet = text
+            .new_text_layout("piet")
+            .font(FontFamily::SANS_SERIF, 24.0)
+            .build()
+            .unwrap();
+        let layout = text
+            .new_text_layout(input)
+            .range_attribute(..4, TextAttribute::FontSize(24.0))
+            .build()
+            .unwrap();
+
+        // the line is tall enough for the larger font
+        let line = layout.line_metric(0).unwrap();
+        assert_close!(line.height, large_piet.size().height, 0.5);
+        assert!(line.height > plain.line_metric(0).unwrap().height);
+
+        // positions after the large run are shifted by its extra width
+        let piet_end = layout.hit_test_text_position(4).point.x;
+        assert_close!(piet_end, large_piet.size().width, 0.5);
+        assert!(layout.size().width > plain.size().width);
+        let pt = layout.hit_test_point(Point::new(piet_end + 0.5, line.baseline));
+        assert_eq!(pt.idx, 4);
+    }
+
+    #[test]
+    fn letter_and_word_spacing() {
+        let mut text = CairoText::new();
+        let plain = text.new_text_layout("ab cd").build().unwrap();
+        let spaced = text
+            .new_text_layout("ab cd")
+            .default_attribute(TextAttribute::LetterSpacing(2.0))
+            .range_attribute(2..3, TextAttribute::WordSpacing(5.0))
+            .build()
+            .unwrap();
+
+        // letter spacing follows each grapheme, and word spacing each space
+        let plain_x = |idx| plain.hit_test_text_position(idx).point.x;
+        let spaced_x = |idx| spaced.hit_test_text_position(idx).point.x;
+        assert_close!(spaced_x(1), plain_x(1) + 2.0, 0.01);
+        assert_close!(spaced_x(2), plain_x(2) + 4.0, 0.01);
+        assert_close!(spaced_x(3), plain_x(3) + 11.0, 0.01);
+        assert_close!(spaced.size().width, plain.size().width + 15.0, 0.01);
+        let pt = spaced.hit_test_point(Point::new(spaced_x(3) + 0.5, 5.0));
+        assert_eq!(pt.idx, 3);
+    }
+
+    #[test]
+    fn line_height() {
+        let mut text = CairoText::new();
+        let input = "one\ntwo\n";
+        let plain = text.new_text_layout(input).build().unwrap();
+        let natural = plain.line_metric(0).unwrap();
+
+        let layout = text
+            .new_text_layout(input)
+            .line_height(LineHeight::Absolute(30.0))
+            .build()
+            .unwrap();
+        assert_eq!(layout.line_count(), 3);
+        for (i, line) in (0..3).map(|i| (i, layout.line_metric(i).unwrap())) {
+            assert_close!(line.height, 30.0, 0.01);
+            assert_close!(line.y_offset, 30.0 * i as f64, 0.01);
+            assert_close!(
+                line.baseline,
+                natural.baseline + (30.0 - natural.height) / 2.0,
+                0.01
+            );
+        }
+        assert_close!(layout.size().height, 90.0, 0.01);
+        let pos = layout.hit_test_text_position(5);
+        assert_close!(
+            pos.point.y,
+            30.0 + layout.line_metric(1).unwrap().baseline,
+            0.01
+        );
+
+        let layout = text
+            .new_text_layout(input)
+            .line_height(LineHeight::Relative(0.8))
+            .build()
+            .unwrap();
+        assert_close!(
+            layout.line_metric(1).unwrap().y_offset,
+            natural.height * 0.8,
+            0.01
+        );
+
+        let empty = text
+            .new_text_layout("")
+            .line_height(LineHeight::Absolute(40.0))
+            .build()
+            .unwrap();
+        assert_close!(empty.size().height, 40.0, 0.01);
+    }
+
+    #[test]
+    fn baseline_shift() {
+        let mut text = CairoText::new();
+        let plain = text
+            .new_text_layout("H2O")
+            .font(FontFamily::SANS_SERIF, 20.0)
+            .build()
+            .unwrap();
+        let plain_line = plain.line_metric(0).unwrap();
+        let mut shifted = |shift: BaselineShift| {
+            text.new_text_layout("H2O")
+                .font(FontFamily::SANS_SERIF, 20.0)
+                .range_attribute(1..2, shift)
+                .build()
+                .unwrap()
+        };
+
+        // scripts are drawn smaller
+        let layout = shifted(BaselineShift::Subscript);
+        let size = |run: &StyleRun| run.font.get_font_matrix().xx;
+        assert_close!(size(&layout.runs[0]), 20.0, 0.01);
+        assert_close!(
+            size(&layout.runs[1]),
+            20.0 * BaselineShift::SCRIPT_SCALE,
+            0.01
+        );
+        let two_width = |layout: &CairoTextLayout| {
+            layout.hit_test_text_position(2).point.x - layout.hit_test_text_position(1).point.x
+        };
+        assert!(two_width(&layout) < two_width(&plain));
+
+        // the line grows to fit lowered and raised text
+        let line = layout.line_metric(0).unwrap();
+        assert_close!(line.baseline, plain_line.baseline, 0.01);
+        assert!(line.height > plain_line.height);
+        let layout = shifted(BaselineShift::Superscript);
+        let line = layout.line_metric(0).unwrap();
+        assert!(line.baseline > plain_line.baseline);
+        assert_close!(
+            line.height - line.baseline,
+            plain_line.height - plain_line.baseline,
+            0.01
+        );
+
+        // explicit offsets keep the size
+        let layout = shifted(BaselineShift::Offset(10.0));
+        assert_close!(size(&layout.runs[1]), 20.0, 0.01);
+        assert_close!(
+            layout.line_metric(0).unwrap().baseline,
+            plain_line.baseline + 10.0,
+            0.01
+        );
+        assert_close!(layout.size().width, plain.size().width, 0.01);
+    }
+
+    #[test]
+    fn font_features_and_variations() {
+        let mut text = CairoText::new();
+        let sans = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Anaheim-Regular.ttf"
+            ))
+            .unwrap();
+        let mut glyph_count = |attrs: Vec<TextAttribute>| {
+            let mut builder = text.new_text_layout("fi").font(sans.clone(), 20.0);
+            for attr in attrs {
+                builder = builder.default_attribute(attr);
+            }
+            let layout = builder.build().unwrap();
+            layout.runs[0].glyphs.len()
+        };
+
+        // the font has an "fi" ligature
+        assert_eq!(glyph_count(vec![]), 1);
+        let no_ligatures = TextAttribute::from(FontFeature::disable(b"liga"));
+        assert_eq!(glyph_count(vec![no_ligatures]), 2);
+        // features from the style take precedence over our own
+        let spaced = TextAttribute::LetterSpacing(1.0);
+        assert_eq!(glyph_count(vec![spaced]), 2);
+        let spaced = TextAttribute::LetterSpacing(1.0);
+        let ligatures = TextAttribute::from(FontFeature::enable(b"liga"));
+        assert_eq!(glyph_count(vec![spaced, ligatures]), 1);
+
+        // axes that the font doesn't have are ignored
+        let plain = text
+            .new_text_layout("fi")
+            .font(sans.clone(), 20.0)
+            .build()
+            .unwrap();
+        let varied = text
+            .new_text_layout("fi")
+            .font(sans, 20.0)
+            .default_attribute(FontVariation::new(b"wght", 700.0))
+            .build()
+            .unwrap();
+        assert_close!(varied.size().width, plain.size().width, 0.001);
+    }
+
+    #[test]
+    fn decorations_use_font_metrics() {
+        let mut text = CairoText::new();
+        let sans = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Anaheim-Regular.ttf"
+            ))
+            .unwrap();
+        let layout = text
+            .new_text_layout("decorated")
+            .font(sans.clone(), 100.0)
+            .default_attribute(TextAttribute::Underline(true))
+            .default_attribute(TextAttribute::Strikethrough(true))
+            .build()
+            .unwrap();
+        // from the font's post and OS/2 tables, which have 2048 units per em
+        let scale = 100.0 / 2048.0;
+        let underline = layout.runs[0].underline().unwrap();
+        assert_close!(underline.top, 40.0 * scale, 0.001);
+        assert_close!(underline.thickness, 90.0 * scale, 0.001);
+        let strikethrough = layout.runs[0].strikethrough().unwrap();
+        assert_close!(strikethrough.top, -530.0 * scale, 0.001);
+        assert_close!(strikethrough.thickness, 102.0 * scale, 0.001);
+        assert!(layout.runs[0].overline().is_none());
+
+        // decorations can fall outside short lines, but are in the image bounds
+        let layout = text
+            .new_text_layout("decorated")
+            .font(sans, 20.0)
+            .line_height(LineHeight::Absolute(10.0))
+            .default_attribute(TextAttribute::Underline(true))
+            .default_attribute(UnderlineStyle::Wavy)
+            .build()
+            .unwrap();
+        let line = layout.line_metric(0).unwrap();
+        let underline = layout.runs[0].underline().unwrap();
+        let underline_bottom = line.baseline + underline.top + underline.height();
+        assert!(underline_bottom > layout.size().height);
+        assert_close!(layout.image_bounds().y1, underline_bottom, 0.001);
+        assert_close!(layout.image_bounds().width(), layout.size().width, 0.001);
+    }
+
+    #[test]
+    fn list_families_and_faces() {
+        let mut text = CairoText::new();
+        assert!(text.font_family("A Quite Unlikely Font Ñame").is_none());
+        let families = text.families();
+        assert!(families
+            .windows(2)
+            .all(|pair| { pair[0].name().to_lowercase() < pair[1].name().to_lowercase() }));
+        // whichever fonts are installed, the families listed can be used
+        if let Some(system) = families.first() {
+            assert_eq!(text.font_family(system.name()).as_ref(), Some(system));
+            assert!(!text.faces(system).is_empty());
+        }
+        assert!(text.faces(&FontFamily::SANS_SERIF).is_empty());
+
+        let anaheim = text
+            .load_font(include_bytes!("../../snapshots/resources/Anaheim-Bold.ttf"))
+            .unwrap();
+        text.load_font(include_bytes!(
+            "../../snapshots/resources/Anaheim-Regular.ttf"
+        ))
+        .unwrap();
+        let inconsolata = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Inconsolata-variable.ttf"
+            ))
+            .unwrap();
+        let families = text.families();
+        assert!(families.contains(&anaheim) && families.contains(&inconsolata));
+        let name = anaheim.name().to_uppercase();
+        assert_eq!(text.font_family(&name), Some(anaheim.clone()));
+        let weights = text
+            .faces(&anaheim)
+            .iter()
+            .map(|face| (face.weight, face.is_variable))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            weights,
+            [(FontWeight::REGULAR, false), (FontWeight::BOLD, false)]
+        );
+        // despite its name, this font has no variation axes
+        assert_eq!(text.faces(&inconsolata).len(), 1);
+        assert!(!text.faces(&inconsolata)[0].is_variable);
+    }
+
+    #[test]
+    fn font_metrics_match_layouts() {
+        let mut text = CairoText::new();
+        let sans = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Anaheim-Regular.ttf"
+            ))
+            .unwrap();
+        let metrics = text.font_metrics(&sans, 100.0, FontWeight::REGULAR, FontStyle::Regular);
+        let layout = text
+            .new_text_layout("Hx")
+            .font(sans, 100.0)
+            .build()
+            .unwrap();
+        let line = layout.line_metric(0).unwrap();
+        assert_close!(metrics.ascent, line.baseline, 0.001);
+        // cairo rounds the font's height separately from its ascent and
+        // descent, so these can be a pixel apart
+        assert_close!(metrics.line_height(), line.height, 1.001);
+
+        let scale = 100.0 / 2048.0;
+        assert_eq!(metrics.units_per_em, Some(2048));
+        assert_close!(metrics.underline_position, 40.0 * scale, 0.001);
+        assert_close!(metrics.strikethrough_position, -530.0 * scale, 0.001);
+        assert!(metrics.x_height > 0.0 && metrics.x_height < metrics.cap_height);
+        assert!(metrics.cap_height < metrics.ascent);
+    }
+
+    #[test]
+    fn glyph_runs() {
+        let mut text = CairoText::new();
+        let mono = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Inconsolata-variable.ttf"
+            ))
+            .unwrap();
+        let sans = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Anaheim-Regular.ttf"
+            ))
+            .unwrap();
+        let input = "abc שלום def\nx\u{301}y";
+        let layout = text
+            .new_text_layout(input)
+            .font(mono.clone(), 16.0)
+            .build()
+            .unwrap();
+        let runs = layout.glyph_runs();
+        let ranges = runs
+            .iter()
+            .map(|run| (run.line, run.range.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ranges,
+            vec![(0, 0..4), (0, 4..12), (0, 12..17), (1, 17..21)]
+        );
+        // the Hebrew uses the fallback font, and its glyphs are in visual order
+        assert_eq!(runs[0].family, mono);
+        assert_eq!(runs[1].family, sans);
+        assert!(runs[1].is_rtl);
+        let clusters = runs[1]
+            .glyphs
+            .iter()
+            .map(|glyph| glyph.cluster)
+            .collect::<Vec<_>>();
+        assert_eq!(clusters, vec![10, 8, 6, 4]);
+        // the line break isn't drawn
+        assert_eq!(runs[2].glyphs.len(), 4);
+
+        // runs are positioned where their text is
+        for run in &runs {
+            let hit = layout.hit_test_text_position(run.range.start);
+            let start = run.glyph_positions().next().unwrap();
+            assert_close!(start.y, hit.point.y, 0.001);
+            if !run.is_rtl {
+                assert_close!(start.x, hit.point.x, 0.001);
+            }
+        }
+        let line_width = runs[..3].iter().map(GlyphRun::width).sum::<f64>();
+        assert_close!(line_width, layout.size().width, 0.001);
+
+        // the combining accent is drawn back over the base letter
+        let accented = &runs[3].glyphs;
+        assert_eq!(accented.len(), 3);
+        assert_eq!(accented[1].cluster, accented[0].cluster);
+        assert_close!(accented[1].offset.x, -accented[0].advance, 0.001);
+    }
+
+    #[test]
+    fn to_path() {
+        use piet::kurbo::Shape;
+
+        let mut text = CairoText::new();
+        let mono = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Inconsolata-variable.ttf"
+            ))
+            .unwrap();
+        let layout = text
+            .new_text_layout("Hello\nworld")
+            .font(mono.clone(), 16.0)
+            .build()
+            .unwrap();
+        let path = layout.to_path(Point::ZERO);
+        let bounds = path.bounding_box();
+        assert!(!bounds.is_empty());
+        let image_bounds = layout.image_bounds().inflate(0.5, 0.5);
+        assert_eq!(image_bounds.union(bounds), image_bounds);
+        // both lines are outlined
+        assert!(bounds.y1 > layout.line_metric(1).unwrap().y_offset);
+
+        let moved = layout.to_path((10.0, 20.0)).bounding_box();
+        assert_close!(moved.x0, bounds.x0 + 10.0, 0.001);
+        assert_close!(moved.y0, bounds.y0 + 20.0, 0.001);
+
+        let empty = text.new_text_layout("").font(mono, 16.0).build().unwrap();
+        assert!(empty.to_path(Point::ZERO).elements().is_empty());
+    }
+
+    #[test]
+    fn draw_text_on_path() {
+        use crate::CairoRenderContext;
+        use cairo::{Context, Format, ImageSurface};
+        use piet::kurbo::Line;
+        use piet::{RenderContext, TextPathOptions};
+
+        let mut text = CairoText::new();
+        let mono = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Inconsolata-variable.ttf"
+            ))
+            .unwrap();
+        let layout = text
+            .new_text_layout("HHHH")
+            .font(mono.clone(), 16.0)
+            .build()
+            .unwrap();
+        let advance = layout.size().width / 4.0;
+
+        // the bounds of the ink drawn along a line
+        let ink_bounds = |line: Line| {
+            let mut surface = ImageSurface::create(Format::ARgb32, 60, 100).unwrap();
+            {
+                let ctx = Context::new(&surface);
+                let mut rc = CairoRenderContext::new(&ctx);
+                rc.draw_text_on_path(&layout, line, &TextPathOptions::new());
+                rc.finish().unwrap();
+            }
+            let stride = surface.get_stride() as usize;
+            let data = surface.get_data().unwrap();
+            let mut bounds: Option<Rect> = None;
+            for y in 0..100 {
+                for x in 0..60 {
+                    if data[y * stride + x * 4 + 3] != 0 {
+                        let pixel = Rect::new(x as f64, y as f64, x as f64 + 1.0, y as f64 + 1.0);
+                        bounds = Some(bounds.map_or(pixel, |b| b.union(pixel)));
+                    }
+                }
+            }
+            bounds.unwrap()
+        };
+
+        // going down, the tops of the glyphs face right
+        let bounds = ink_bounds(Line::new((30.0, 0.0), (30.0, 100.0)));
+        assert!(bounds.x0 >= 29.0 && bounds.x1 <= 30.0 + 16.0);
+        assert!(bounds.y1 <= 4.0 * advance + 1.0);
+        assert!(bounds.y1 > 3.0 * advance);
+
+        // glyphs that don't fit aren't drawn
+        let bounds = ink_bounds(Line::new((30.0, 0.0), (30.0, 2.5 * advance)));
+        assert!(bounds.y1 <= 2.0 * advance + 1.0);
+
+        // an ellipsis is drawn with its own font, although it covers the
+        // range of the text it hides
+        let layout = text
+            .new_text_layout("HHHH HHHH")
+            .font(mono, 16.0)
+            .max_width(6.0 * advance)
+            .max_lines(1)
+            .overflow(TextOverflow::Ellipsis)
+            .build()
+            .unwrap();
+        let runs = layout.glyph_runs_with_fonts();
+        let (ellipsis, run) = runs.last().unwrap();
+        assert!(run.is_ellipsis);
+        assert!(!layout.runs[0].is_ellipsis);
+        assert!(layout.runs[0].range.contains(&ellipsis.range.start));
+    }
+
+    #[test]
+    fn background_follows_line_wrapping() {
+        use crate::CairoRenderContext;
+        use cairo::{Context, Format, ImageSurface};
+        use piet::RenderContext;
+
+        let mut text = CairoText::new();
+        // the text itself is transparent, so only the background is drawn
+        let layout = text
+            .new_text_layout("hello world")
+            .font(FontFamily::SANS_SERIF, 16.0)
+            .text_color(Color::rgba8(0, 0, 0, 0))
+            .max_width(60.0)
+            .range_attribute(3..9, TextAttribute::BackgroundColor(Color::rgb8(255, 0, 0)))
+            .build()
+            .unwrap();
+        assert_eq!(layout.line_count(), 2);
+
+        let mut surface = ImageSurface::create(Format::ARgb32, 100, 60).unwrap();
+        {
+            let ctx = Context::new(&surface);
+            let mut rc = CairoRenderContext::new(&ctx);
+            rc.draw_text(&layout, (0.0, 0.0));
+            rc.finish().unwrap();
+        }
+        let stride = surface.get_stride() as usize;
+        let data = surface.get_data().unwrap();
+        let red_at = |point: Point| {
+            let idx = point.y as usize * stride + point.x as usize * 4;
+            // cairo's ARGB32 is native endian
+            let pixel =
+                u32::from_ne_bytes([data[idx], data[idx + 1], data[idx + 2], data[idx + 3]]);
+            pixel == 0xffff_0000
+        };
+
+        let rects = layout.rects_for_range(3..9);
+        assert_eq!(rects.len(), 2);
+        for rect in rects {
+            assert!(red_at(rect.center()));
+        }
+        // the start of the first line and the end of the second are clear
+        let first = layout.line_metric(0).unwrap();
+        assert!(!red_at(Point::new(
+            1.0,
+            first.y_offset + first.height / 2.0
+        )));
+        let second = layout.line_metric(1).unwrap();
+        let end = layout.hit_test_text_position(11).point.x;
+        assert!(!red_at(Point::new(
+            end - 1.0,
+            second.y_offset + second.height / 2.0
+        )));
+    }
+
+    #[test]
+    fn range_attributes_split_runs() {
+        let layout = CairoText::new()
+            .new_text_layout("some bold text")
+            .default_attribute(TextAttribute::TextColor(Color::WHITE))
+            .range_attribute(5..9, piet::FontWeight::BOLD)
+            .range_attribute(5..14, TextAttribute::Underline(true))
+            .build()
+            .unwrap();
+        let runs = layout
+            .runs
+            .iter()
+            .map(|run| {
+                (
+                    run.range.clone(),
+                    run.style.weight,
+                    run.style.underline,
+                    run.style.fg_color == Color::WHITE,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            runs,
+            vec![
+                (0..5, piet::FontWeight::REGULAR, false, true),
+                (5..9, piet::FontWeight::BOLD, true, true),
+                (9..14, piet::FontWeight::REGULAR, true, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn alignment_end_with_max_width() {
+        let mut text = CairoText::new();
+        let plain = text.new_text_layout("piet text").build().unwrap();
+        let text_width = plain.size().width;
+
+        let layout = text
+            .new_text_layout("piet text")
+            .max_width(200.0)
+            .alignment(TextAlignment::End)
+            .build()
+            .unwrap();
+
+        assert_close!(
+            layout.hit_test_text_position(0).point.x,
+            200.0 - text_width,
+            0.5
+        );
+        assert_close!(layout.hit_test_text_position(9).point.x, 200.0, 0.5);
+        assert_close!(layout.size().width, 200.0, 0.5);
+
+        // hit testing is the inverse of the position
+        let pos = layout.hit_test_text_position(4).point;
+        assert_eq!(layout.hit_test_point(pos).idx, 4);
+        let pt = layout.hit_test_point(Point::new(200.0 - text_width - 1.0, 5.0));
+        assert_eq!(pt.idx, 0);
+        assert!(!pt.is_inside);
+    }
+
+    #[test]
+    fn alignment_center_without_max_width() {
+        let mut text = CairoText::new();
+        let layout = text
+            .new_text_layout("a much longer line\nshort")
+            .alignment(TextAlignment::Center)
+            .build()
+            .unwrap();
+        let long_width = layout.hit_test_text_position(18).point.x;
+        let short_start = layout.hit_test_text_position(19).point.x;
+        let short_end = layout.hit_test_text_position(24).point.x;
+
+        // the widest line is not moved, and the others are centered on it
+        assert_close!(layout.hit_test_text_position(0).point.x, 0.0, 0.01);
+        assert_close!(short_start, long_width - short_end, 0.5);
+        assert!(short_start > 0.0);
+        assert_close!(layout.size().width, long_width, 0.5);
+    }
+
+    #[test]
+    fn vertical_layout() {
+        use piet::kurbo::Shape;
+
+        let mut text = CairoText::new();
+        let mono = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Inconsolata-variable.ttf"
+            ))
+            .unwrap();
+        let input = "縦書き\nabc";
+        let horizontal = text
+            .new_text_layout(input)
+            .font(mono.clone(), 16.0)
+            .build()
+            .unwrap();
+        let layout = text
+            .new_text_layout(input)
+            .font(mono.clone(), 16.0)
+            .writing_mode(WritingMode::VerticalRl)
+            .build()
+            .unwrap();
+
+        // two columns, from right to left
+        let size = layout.size();
+        let first = layout.line_metric(0).unwrap();
+        let second = layout.line_metric(1).unwrap();
+        assert_close!(first.y_offset + first.height, size.width, 1e-6);
+        assert_close!(second.y_offset + second.height, first.y_offset, 1e-6);
+        assert_close!(second.y_offset, 0.0, 1e-6);
+
+        // rotated text is as tall as it is wide in a horizontal layout
+        let h_rect = horizontal.rects_for_range(10..13)[0];
+        let v_rect = layout.rects_for_range(10..13)[0];
+        assert_close!(v_rect.height(), h_rect.width(), 1e-6);
+        assert_close!(v_rect.x0, second.y_offset, 1e-6);
+        assert_close!(v_rect.width(), second.height, 1e-6);
+
+        // positions run down each column
+        let pos = layout.hit_test_text_position(3);
+        let next = layout.hit_test_text_position(6);
+        assert_close!(pos.point.x, next.point.x, 1e-6);
+        assert!(next.point.y > pos.point.y);
+        assert_eq!(layout.hit_test_point(next.point).idx, 6);
+        let hit = layout.hit_test_point(Point::new(second.y_offset + 1.0, v_rect.y1 - 1.0));
+        assert_eq!(hit.idx, 13);
+        assert!(hit.is_inside);
+        // points to the right of the layout hit the first column
+        let hit = layout.hit_test_point(Point::new(size.width + 10.0, 0.0));
+        assert_eq!(hit.idx, 0);
+        assert!(!hit.is_inside);
+
+        let bounds = layout.to_path(Point::ZERO).bounding_box();
+        let image_bounds = layout.image_bounds().inflate(0.5, 0.5);
+        assert!(!bounds.is_empty());
+        assert_eq!(image_bounds.union(bounds), image_bounds);
+        assert!(layout.glyph_runs().is_empty());
+
+        let layout = text
+            .new_text_layout(input)
+            .font(mono, 16.0)
+            .writing_mode(WritingMode::VerticalLr)
+            .build()
+            .unwrap();
+        let first = layout.line_metric(0).unwrap();
+        let second = layout.line_metric(1).unwrap();
+        assert_close!(first.y_offset, 0.0, 1e-6);
+        assert_close!(second.y_offset, first.height, 1e-6);
+        let hit = layout.hit_test_point(Point::new(-10.0, 0.0));
+        assert_eq!(hit.idx, 0);
+    }
+
+    #[test]
+    fn max_lines() {
+        let mut text = CairoText::new();
+        let mono = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Inconsolata-variable.ttf"
+            ))
+            .unwrap();
+        let input = "one two three four five";
+        let build = |text: &mut CairoText, overflow| {
+            text.new_text_layout(input)
+                .font(mono.clone(), 16.0)
+                .max_width(106.0)
+                .max_lines(2)
+                .overflow(overflow)
+                .build()
+                .unwrap()
+        };
+
+        let full = text
+            .new_text_layout(input)
+            .font(mono.clone(), 16.0)
+            .max_width(106.0)
+            .build()
+            .unwrap();
+        assert_eq!(full.line_count(), 3);
+        let clipped = build(&mut text, TextOverflow::Clip);
+        assert_eq!(clipped.line_count(), 2);
+        assert_eq!(clipped.line_metric(1), full.line_metric(1));
+        let second = full.line_metric(1).unwrap();
+        assert_close!(clipped.size().height, second.y_offset + second.height, 1e-6);
+
+        // the ellipsis takes the place of the space after "three four"
+        let layout = build(&mut text, TextOverflow::Ellipsis);
+        assert_eq!(layout.line_count(), 2);
+        let last = layout.line_metric(1).unwrap();
+        assert_eq!(last.range(), 8..input.len());
+        assert!(layout.size().width <= 106.0);
+        let ellipsis = layout.hit_test_text_position(18).point;
+        assert_close!(ellipsis.x, 10.0 * 9.63, 0.5);
+        assert_eq!(layout.hit_test_text_position(21).point, ellipsis);
+        let hit = layout.hit_test_point(Point::new(ellipsis.x + 5.0, ellipsis.y));
+        assert_eq!(hit.idx, 18);
+        assert!(hit.is_inside);
+        let runs = layout.glyph_runs();
+        let ellipsis_run = runs.last().unwrap();
+        assert_eq!(ellipsis_run.range, 18..input.len());
+        assert_eq!(ellipsis_run.glyphs.len(), 1);
+
+        // a single word that is too wide for the line is cut too
+        let layout = text
+            .new_text_layout("abcdefghijklmnop")
+            .font(mono.clone(), 16.0)
+            .max_width(50.0)
+            .max_lines(1)
+            .overflow(TextOverflow::Ellipsis)
+            .build()
+            .unwrap();
+        assert!(layout.size().width <= 50.0);
+        assert_close!(layout.hit_test_text_position(4).point.x, 4.0 * 9.63, 0.5);
+
+        let input = "report-final-version.txt";
+        let layout = text
+            .new_text_layout(input)
+            .font(mono, 16.0)
+            .max_width(100.0)
+            .max_lines(1)
+            .overflow(TextOverflow::MiddleEllipsis)
+            .build()
+            .unwrap();
+        assert!(layout.size().width <= 100.0);
+        let start = layout.hit_test_text_position(5).point.x;
+        assert_close!(start, 5.0 * 9.63, 0.5);
+        assert_close!(layout.hit_test_text_position(12).point.x, start, 1e-6);
+        assert_close!(layout.hit_test_text_position(20).point.x, 6.0 * 9.63, 0.5);
+        let rects = layout.rects_for_range(..);
+        assert_eq!(rects.len(), 1);
+        assert_close!(rects[0].width(), layout.size().width, 1e-6);
+    }
+
+    #[test]
+    fn tab_stops() {
+        let mut text = CairoText::new();
+        let mono = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Inconsolata-variable.ttf"
+            ))
+            .unwrap();
+        let space = 9.63;
+        let build = |text: &mut CairoText, input: &'static str, stops: TabStops| {
+            text.new_text_layout(input)
+                .font(mono.clone(), 16.0)
+                .tab_stops(stops)
+                .build()
+                .unwrap()
+        };
+
+        let layout = build(&mut text, "a\tb", TabStops::new());
+        assert_close!(layout.hit_test_text_position(2).point.x, 8.0 * space, 0.5);
+        let hit = layout.hit_test_point(Point::new(50.0, 5.0));
+        assert_eq!(hit.idx, 2);
+        let layout = build(
+            &mut text,
+            "ab\tc\td",
+            TabStops::new().interval(TabInterval::Points(50.0)),
+        );
+        assert_close!(layout.hit_test_text_position(3).point.x, 50.0, 1e-6);
+        assert_close!(layout.hit_test_text_position(5).point.x, 100.0, 1e-6);
+        assert_close!(layout.size().width, 100.0 + space, 0.5);
+
+        let stops = TabStops::new()
+            .stop(300.0, TabAlignment::Decimal)
+            .stop(200.0, TabAlignment::Right)
+            .stop(250.0, TabAlignment::Center);
+        let layout = build(&mut text, "x\t12\tabcd\t3.25", stops);
+        assert_close!(layout.hit_test_text_position(4).point.x, 200.0, 1e-6);
+        assert_close!(
+            layout.hit_test_text_position(5).point.x,
+            250.0 - 2.0 * space,
+            0.5
+        );
+        assert_close!(layout.hit_test_text_position(11).point.x, 300.0, 1e-6);
+        let rects = layout.rects_for_range(2..4);
+        assert_close!(rects[0].x0, 200.0 - 2.0 * space, 0.5);
+
+        // the tab stops are part of the width that lines are broken at
+        let stops = TabStops::new().interval(TabInterval::Points(100.0));
+        let layout = text
+            .new_text_layout("aaaa\tbbbb")
+            .font(mono, 16.0)
+            .tab_stops(stops)
+            .max_width(120.0)
+            .build()
+            .unwrap();
+        assert_eq!(layout.line_count(), 2);
+    }
+
+    #[test]
+    fn justification_and_hyphens() {
+        let mut text = CairoText::new();
+        let mono = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Inconsolata-variable.ttf"
+            ))
+            .unwrap();
+        let advance = 9.63;
+
+        // the space on each line but the last of the paragraph is widened
+        let input = "aaa bbb ccc\nddd eee";
+        let layout = text
+            .new_text_layout(input)
+            .font(mono.clone(), 16.0)
+            .max_width(100.0)
+            .alignment(TextAlignment::Justified)
+            .build()
+            .unwrap();
+        assert_eq!(layout.line_count(), 3);
+        assert_close!(
+            layout.hit_test_text_position(4).point.x,
+            100.0 - 3.0 * advance,
+            0.5
+        );
+        assert_close!(layout.hit_test_text_position(7).point.x, 100.0, 0.5);
+        assert_close!(layout.hit_test_text_position(9).point.x, advance, 0.5);
+        assert_close!(
+            layout.hit_test_text_position(16).point.x,
+            4.0 * advance,
+            0.5
+        );
+        let rects = layout.rects_for_range(0..7);
+        assert_close!(rects[0].width(), 100.0, 0.5);
+        // the widened space is hit up to the start of the next word
+        let hit = layout.hit_test_point(Point::new(60.0, 5.0));
+        assert_eq!(hit.idx, 4);
+
+        // a soft hyphen is shown when a line ends at it
+        let build = |text: &mut CairoText, input, hyphens| {
+            text.new_text_layout(input)
+                .font(mono.clone(), 16.0)
+                .max_width(70.0)
+                .hyphens(hyphens)
+                .language("en")
+                .build()
+                .unwrap()
+        };
+        let input = "aaaa\u{ad}bbbb";
+        let layout = build(&mut text, input, Hyphens::Manual);
+        assert_eq!(layout.line_count(), 2);
+        assert_eq!(layout.line_text(0), Some("aaaa\u{ad}"));
+        let runs = layout.glyph_runs();
+        assert_eq!(runs[0].glyphs.len(), 6);
+        assert_close!(layout.size().width, 5.0 * advance, 0.5);
+        let layout = build(&mut text, input, Hyphens::None);
+        assert_eq!(layout.line_count(), 1);
+        assert_eq!(layout.glyph_runs()[0].glyphs.len(), 9);
+
+        // with automatic hyphenation, words are also broken between syllables
+        let layout = build(&mut text, "extensive", Hyphens::Auto);
+        assert_eq!(layout.line_count(), 2);
+        assert_eq!(layout.line_text(0), Some("exten"));
+        assert_close!(layout.size().width, 6.0 * advance, 0.5);
+        let layout = build(&mut text, "extensive", Hyphens::Manual);
+        assert_eq!(layout.line_count(), 1);
+
+        let empty = text
+            .new_text_layout("")
+            .max_width(100.0)
+            .alignment(TextAlignment::Justified)
+            .build()
+            .unwrap();
+        assert_eq!(empty.line_count(), 1);
+    }
+
+    #[test]
+    fn inline_objects() {
+        let mut text = CairoText::new();
+        let mono = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Inconsolata-variable.ttf"
+            ))
+            .unwrap();
+        let advance = 9.63;
+        let input = "ab\u{fffc}cd";
+        let plain = text
+            .new_text_layout(input)
+            .font(mono.clone(), 16.0)
+            .build()
+            .unwrap();
+        let layout = text
+            .new_text_layout(input)
+            .font(mono.clone(), 16.0)
+            .inline_object(2..5, Size::new(20.0, 40.0), 32.0)
+            .build()
+            .unwrap();
+
+        // the object is taller than the text, so the line is too
+        let lm = layout.line_metric(0).unwrap();
+        assert_close!(lm.baseline, 32.0, 1e-6);
+        let plain_lm = plain.line_metric(0).unwrap();
+        let descent = f64::max(40.0 - 32.0, plain_lm.height - plain_lm.baseline);
+        assert_close!(lm.height, 32.0 + descent, 1e-6);
+        let rect = layout.inline_object_rect(2).unwrap();
+        assert_close!(rect.x0, 2.0 * advance, 0.5);
+        assert_close!(rect.y0, 0.0, 1e-6);
+        assert_close!(rect.width(), 20.0, 1e-6);
+        assert_close!(rect.height(), 40.0, 1e-6);
+        assert_eq!(layout.inline_object_rect(0), None);
+        assert_close!(layout.hit_test_text_position(5).point.x, rect.x1, 1e-6);
+        assert_eq!(
+            layout.hit_test_point(Point::new(rect.x0 + 5.0, 10.0)).idx,
+            2
+        );
+        let glyphs = layout
+            .glyph_runs()
+            .iter()
+            .map(|run| run.glyphs.len())
+            .sum::<usize>();
+        assert_eq!(glyphs, 4);
+
+        // lines don't break inside an object's text, but can on either side
+        let layout = text
+            .new_text_layout("aaaaxyzbb")
+            .font(mono.clone(), 16.0)
+            .max_width(60.0)
+            .inline_object(4..7, Size::new(40.0, 10.0), 10.0)
+            .build()
+            .unwrap();
+        assert_eq!(layout.line_count(), 2);
+        assert_eq!(layout.line_text(1), Some("xyzbb"));
+        let rect = layout.inline_object_rect(4).unwrap();
+        assert_close!(rect.x0, 0.0, 1e-6);
+        let second = layout.line_metric(1).unwrap();
+        assert_close!(rect.y1, second.y_offset + second.baseline, 1e-6);
+
+        // objects on lines that are cut off have no rect
+        let layout = text
+            .new_text_layout("aaaaxyzbb")
+            .font(mono, 16.0)
+            .max_width(60.0)
+            .max_lines(1)
+            .inline_object(4..7, Size::new(40.0, 10.0), 10.0)
+            .build()
+            .unwrap();
+        assert_eq!(layout.inline_object_rect(4), None);
+    }
+
+    #[test]
+    fn set_max_width() {
+        let mut text = CairoText::new();
+        let mono = text
+            .load_font(include_bytes!(
+                "../../snapshots/resources/Inconsolata-variable.ttf"
+            ))
+            .unwrap();
+        let input = "one two\tthree four-five six";
+        let build = |text: &mut CairoText, width| {
+            text.new_text_layout(input)
+                .font(mono.clone(), 16.0)
+                .alignment(TextAlignment::Justified)
+                .max_width(width)
+                .build()
+                .unwrap()
+        };
+
+        let mut layout = build(&mut text, 106.0);
+        let wide = layout.clone();
+        layout.set_max_width(60.0).unwrap();
+        let fresh = build(&mut text, 60.0);
+        assert!(layout.line_count() > wide.line_count());
+        assert_eq!(layout.line_count(), fresh.line_count());
+        for line in 0..fresh.line_count() {
+            assert_eq!(layout.line_metric(line), fresh.line_metric(line));
+        }
+        assert_eq!(layout.size(), fresh.size());
+        assert_eq!(layout.rects_for_range(..), fresh.rects_for_range(..));
+        assert_eq!(
+            layout.hit_test_text_position(12).point,
+            fresh.hit_test_text_position(12).point
+        );
+
+        // clones keep their own lines
+        assert_eq!(wide.line_count(), build(&mut text, 106.0).line_count());
+
+        layout.set_max_width(f64::INFINITY).unwrap();
+        assert_eq!(layout.line_count(), 1);
+        assert_close!(
+            layout.size().width,
+            layout.trailing_whitespace_width(),
+            1e-6
+        );
+    }
 }
@@ -0,0 +1,247 @@
+//! Resolving range attributes into runs of uniformly styled text.
+
+use std::collections::HashMap;
+use std::mem::{self, Discriminant};
+use std::ops::Range;
+
+use super::fonts::FaceQuery;
+
+use piet::{
+    util, BaselineShift, Color, FontFamily, FontFeature, FontStretch, FontStyle, FontVariation,
+    FontWeight, TextAttribute, UnderlineStyle,
+};
+
+/// The fully resolved style for a run of text.
+#[derive(Clone, PartialEq)]
+pub(crate) struct TextStyle {
+    pub font: FontFamily,
+    /// The font size, before any reduction for a superscript or subscript.
+    pub font_size: f64,
+    pub weight: FontWeight,
+    pub style: FontStyle,
+    pub stretch: FontStretch,
+    pub fg_color: Color,
+    pub underline: bool,
+    pub underline_style: UnderlineStyle,
+    pub overline: bool,
+    pub strikethrough: bool,
+    pub decoration_color: Option<Color>,
+    pub background_color: Option<Color>,
+    pub letter_spacing: f64,
+    pub word_spacing: f64,
+    pub font_features: Vec<FontFeature>,
+    pub font_variations: Vec<FontVariation>,
+    pub baseline_shift: BaselineShift,
+}
+
+/// The attributes that have been applied to ranges of a layout's text.
+///
+/// Following the rules in the piet docs, attributes are expected to be added
+/// in non-decreasing start order, and they do not stack: adding a span
+/// truncates the previous span of the same kind at the start of the new one.
+#[derive(Default)]
+pub(crate) struct AttributeSpans {
+    spans: Vec<(Range<usize>, TextAttribute)>,
+    /// The index in `spans` of the most recent span of each kind.
+    last_of_kind: HashMap<Discriminant<TextAttribute>, usize>,
+}
+
+impl TextStyle {
+    pub(crate) fn new(defaults: &util::LayoutDefaults) -> TextStyle {
+        TextStyle {
+            font: defaults.font.clone(),
+            font_size: defaults.font_size,
+            weight: defaults.weight,
+            style: defaults.style,
+            stretch: defaults.stretch,
+            fg_color: defaults.fg_color.clone(),
+            underline: defaults.underline,
+            underline_style: defaults.underline_style,
+            overline: defaults.overline,
+            strikethrough: defaults.strikethrough,
+            decoration_color: defaults.decoration_color.clone(),
+            background_color: defaults.background_color.clone(),
+            letter_spacing: defaults.letter_spacing,
+            word_spacing: defaults.word_spacing,
+            font_features: defaults.font_features.clone(),
+            font_variations: defaults.font_variations.clone(),
+            baseline_shift: defaults.baseline_shift,
+        }
+    }
+
+    fn apply(&mut self, attr: &TextAttribute) {
+        match attr {
+            TextAttribute::FontFamily(family) => self.font = family.clone(),
+            TextAttribute::FontSize(size) => self.font_size = *size,
+            TextAttribute::Weight(weight) => self.weight = *weight,
+            TextAttribute::Style(style) => self.style = *style,
+            TextAttribute::Stretch(stretch) => self.stretch = *stretch,
+            TextAttribute::TextColor(color) => self.fg_color = color.clone(),
+            TextAttribute::Underline(flag) => self.underline = *flag,
+            TextAttribute::UnderlineStyle(style) => self.underline_style = *style,
+            TextAttribute::Overline(flag) => self.overline = *flag,
+            TextAttribute::Strikethrough(flag) => self.strikethrough = *flag,
+            TextAttribute::DecorationColor(color) => self.decoration_color = Some(color.clone()),
+            TextAttribute::BackgroundColor(color) => self.background_color = Some(color.clone()),
+            TextAttribute::LetterSpacing(spacing) => self.letter_spacing = *spacing,
+            TextAttribute::WordSpacing(spacing) => self.word_spacing = *spacing,
+            TextAttribute::FontFeatures(features) => self.font_features = features.clone(),
+            TextAttribute::FontVariations(variations) => self.font_variations = variations.clone(),
+            TextAttribute::BaselineShift(shift) => self.baseline_shift = *shift,
+        }
+    }
+
+    /// The color of underlines, overlines and strikethroughs.
+    pub(crate) fn decoration_color(&self) -> &Color {
+        self.decoration_color.as_ref().unwrap_or(&self.fg_color)
+    }
+
+    /// The size glyphs are drawn at, after any reduction for a superscript or
+    /// subscript.
+    pub(crate) fn display_size(&self) -> f64 {
+        self.baseline_shift.font_size(self.font_size)
+    }
+
+    /// How far the text is raised above the baseline.
+    pub(crate) fn baseline_offset(&self) -> f64 {
+        self.baseline_shift.offset(self.font_size)
+    }
+
+    /// The properties used to choose a face for this style.
+    pub(crate) fn face_query(&self) -> FaceQuery {
+        FaceQuery {
+            weight: self.weight,
+            stretch: self.stretch,
+            style: self.style,
+        }
+    }
+}
+
+impl AttributeSpans {
+    pub(crate) fn add(&mut self, range: Range<usize>, attr: TextAttribute) {
+        let kind = mem::discriminant(&attr);
+        if let Some(&prev) = self.last_of_kind.get(&kind) {
+            let prev_range = &mut self.spans[prev].0;
+            prev_range.end = prev_range.end.min(range.start).max(prev_range.start);
+        }
+        self.last_of_kind.insert(kind, self.spans.len());
+        self.spans.push((range, attr));
+    }
+
+    /// Split `0..len` into ranges with a uniform style.
+    ///
+    /// The returned ranges are contiguous and in order, and adjacent ranges
+    /// always have different styles. There is always at least one range,
+    /// even if `len` is zero.
+    pub(crate) fn resolve(
+        &self,
+        defaults: &util::LayoutDefaults,
+        len: usize,
+    ) -> Vec<(Range<usize>, TextStyle)> {
+        let mut boundaries = vec![0, len];
+        for (range, _) in &self.spans {
+            boundaries.push(range.start.min(len));
+            boundaries.push(range.end.min(len));
+        }
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        let default_style = TextStyle::new(defaults);
+        let mut result: Vec<(Range<usize>, TextStyle)> = Vec::new();
+        let mut active: Vec<usize> = Vec::new();
+        let mut next_span = 0;
+        for segment in boundaries.windows(2) {
+            let (start, end) = (segment[0], segment[1]);
+            active.retain(|&idx| self.spans[idx].0.end > start);
+            while next_span < self.spans.len() && self.spans[next_span].0.start <= start {
+                if self.spans[next_span].0.end > start {
+                    active.push(next_span);
+                }
+                next_span += 1;
+            }
+
+            let mut style = default_style.clone();
+            for &idx in &active {
+                style.apply(&self.spans[idx].1);
+            }
+
+            match result.last_mut() {
+                Some((prev_range, prev_style)) if *prev_style == style => prev_range.end = end,
+                _ => result.push((start..end, style)),
+            }
+        }
+
+        if result.is_empty() {
+            result.push((0..0, default_style));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ranges(spans: &AttributeSpans, len: usize) -> Vec<(Range<usize>, FontWeight)> {
+        spans
+            .resolve(&util::LayoutDefaults::default(), len)
+            .into_iter()
+            .map(|(range, style)| (range, style.weight))
+            .collect()
+    }
+
+    #[test]
+    fn no_attributes() {
+        let spans = AttributeSpans::default();
+        assert_eq!(ranges(&spans, 10), vec![(0..10, FontWeight::REGULAR)]);
+        assert_eq!(ranges(&spans, 0), vec![(0..0, FontWeight::REGULAR)]);
+    }
+
+    #[test]
+    fn spans_do_not_stack() {
+        let mut spans = AttributeSpans::default();
+        spans.add(0..100, FontWeight::BOLD.into());
+        spans.add(20..50, FontWeight::THIN.into());
+        assert_eq!(
+            ranges(&spans, 100),
+            vec![
+                (0..20, FontWeight::BOLD),
+                (20..50, FontWeight::THIN),
+                (50..100, FontWeight::REGULAR),
+            ]
+        );
+    }
+
+    #[test]
+    fn different_kinds_combine() {
+        let mut spans = AttributeSpans::default();
+        spans.add(0..6, FontWeight::BOLD.into());
+        spans.add(3..9, TextAttribute::Underline(true));
+        let resolved = spans.resolve(&util::LayoutDefaults::default(), 12);
+        let summary = resolved
+            .iter()
+            .map(|(range, style)| (range.clone(), style.weight, style.underline))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (0..3, FontWeight::BOLD, false),
+                (3..6, FontWeight::BOLD, true),
+                (6..9, FontWeight::REGULAR, true),
+                (9..12, FontWeight::REGULAR, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn equal_styles_are_merged() {
+        let mut spans = AttributeSpans::default();
+        spans.add(0..4, FontWeight::BOLD.into());
+        spans.add(4..8, FontWeight::BOLD.into());
+        spans.add(8..10, FontWeight::REGULAR.into());
+        assert_eq!(
+            ranges(&spans, 10),
+            vec![(0..8, FontWeight::BOLD), (8..10, FontWeight::REGULAR)]
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Bidirectional text support: resolving embedding levels, and reordering
+//! the runs on a line into visual order.
+
+use std::ops::Range;
+
+use unic_bidi::{BidiInfo, Level, ParagraphInfo};
+
+/// The embedding levels of a text, as runs of a single level, along with its
+/// paragraphs.
+#[derive(Clone)]
+pub(crate) struct BidiLevels {
+    pub(crate) runs: Vec<(Range<usize>, Level)>,
+    pub(crate) paragraphs: Vec<ParagraphInfo>,
+}
+
+impl BidiLevels {
+    /// Resolve the levels of `text`; the direction of each paragraph is
+    /// determined by its first strong character.
+    pub(crate) fn new(text: &str) -> BidiLevels {
+        let info = BidiInfo::new(text, None);
+        let mut runs: Vec<(Range<usize>, Level)> = Vec::new();
+        for (idx, _) in text.char_indices() {
+            let level = info.levels[idx];
+            if runs.last().map(|(_, prev)| *prev) != Some(level) {
+                if let Some((range, _)) = runs.last_mut() {
+                    range.end = idx;
+                }
+                runs.push((idx..text.len(), level));
+            }
+        }
+        BidiLevels {
+            runs,
+            paragraphs: info.paragraphs,
+        }
+    }
+
+    /// Split `range` into ranges with a single level.
+    pub(crate) fn split(&self, range: Range<usize>) -> Vec<(Range<usize>, Level)> {
+        if range.is_empty() {
+            return vec![(range, Level::ltr())];
+        }
+        self.runs
+            .iter()
+            .filter(|(run, _)| run.start < range.end && run.end > range.start)
+            .map(|(run, level)| (run.start.max(range.start)..run.end.min(range.end), *level))
+            .collect()
+    }
+
+    /// Whether the paragraph containing `pos` is right-to-left.
+    pub(crate) fn paragraph_rtl(&self, pos: usize) -> bool {
+        self.paragraphs
+            .iter()
+            .find(|para| para.range.contains(&pos))
+            .or_else(|| self.paragraphs.last())
+            .map(|para| para.level.is_rtl())
+            .unwrap_or(false)
+    }
+}
+
+/// The visual order of items on a line with the given levels, following rule
+/// L2 of the bidi algorithm: from the highest level down to the lowest odd
+/// level, every sequence of items at that level or higher is reversed.
+pub(crate) fn visual_order(levels: &[Level]) -> Vec<usize> {
+    let mut order = (0..levels.len()).collect::<Vec<_>>();
+    let max = match levels.iter().max() {
+        Some(max) => max.number(),
+        None => return order,
+    };
+    let min_odd = match levels.iter().filter(|level| level.is_rtl()).min() {
+        Some(min) => min.number(),
+        None => return order,
+    };
+
+    for level in (min_odd..=max).rev() {
+        let mut idx = 0;
+        while idx < order.len() {
+            if levels[order[idx]].number() < level {
+                idx += 1;
+                continue;
+            }
+            let start = idx;
+            while idx < order.len() && levels[order[idx]].number() >= level {
+                idx += 1;
+            }
+            order[start..idx].reverse();
+        }
+    }
+    order
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn level_runs() {
+        let text = "abc אבג def";
+        let levels = BidiLevels::new(text);
+        let runs = levels
+            .runs
+            .iter()
+            .map(|(range, level)| (range.clone(), level.number()))
+            .collect::<Vec<_>>();
+        assert_eq!(runs, vec![(0..4, 0), (4..10, 1), (10..14, 0)]);
+        assert!(!levels.paragraph_rtl(0));
+
+        let text = "אבג abc\nabc";
+        let levels = BidiLevels::new(text);
+        assert!(levels.paragraph_rtl(0));
+        assert!(!levels.paragraph_rtl(text.len() - 1));
+    }
+
+    #[test]
+    fn split_levels() {
+        let text = "abc אבג def";
+        let levels = BidiLevels::new(text);
+        let split = levels
+            .split(2..6)
+            .into_iter()
+            .map(|(range, level)| (range, level.number()))
+            .collect::<Vec<_>>();
+        assert_eq!(split, vec![(2..4, 0), (4..6, 1)]);
+        assert_eq!(levels.split(0..0), vec![(0..0, Level::ltr())]);
+    }
+
+    #[test]
+    fn reorder() {
+        let levels = Level::vec(&[0, 1, 1, 0]);
+        assert_eq!(visual_order(&levels), vec![0, 2, 1, 3]);
+        // numbers in right-to-left text
+        let levels = Level::vec(&[1, 2, 2, 1]);
+        assert_eq!(visual_order(&levels), vec![3, 1, 2, 0]);
+        let levels = Level::vec(&[0, 0]);
+        assert_eq!(visual_order(&levels), vec![0, 1]);
+        assert_eq!(visual_order(&[]), Vec::<usize>::new());
+    }
+}
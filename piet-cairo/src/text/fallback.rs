@@ -0,0 +1,114 @@
+//! Splitting text into runs by the fonts that can display it.
+
+use std::ops::Range;
+
+use cairo::FontFace;
+use piet::FontFamily;
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::attributes::TextStyle;
+use super::fonts::{self, FontData};
+use super::ResolvedFont;
+
+/// Split `range` of `text` into runs that each use a single font.
+///
+/// `fonts` are the fonts for the available families of the style's family
+/// stack, in order of preference; there must be at least one. Each grapheme
+/// uses the first of these fonts with glyphs for all of its characters.
+/// Otherwise, it uses a fallback font with a glyph for the first character
+/// that the first font is missing; if there is none, it stays with the first
+/// font. Characters that don't need a glyph, such as whitespace and controls,
+/// stay in the run before them.
+pub(crate) fn font_runs(
+    text: &str,
+    range: Range<usize>,
+    fonts: &[(FontFamily, ResolvedFont)],
+    style: &TextStyle,
+) -> Vec<(Range<usize>, ResolvedFont)> {
+    let faces = fonts
+        .iter()
+        .map(|(_, font)| font.data.as_ref().and_then(FontData::face))
+        .collect::<Vec<_>>();
+    let covers = |font_idx: usize, c: char| match &faces[font_idx] {
+        Some(face) => face.glyph_index(c).is_some(),
+        None => {
+            let (glyphs, _) =
+                fonts[font_idx]
+                    .1
+                    .scaled
+                    .text_to_glyphs(0.0, 0.0, c.encode_utf8(&mut [0; 4]));
+            glyphs.iter().all(|glyph| glyph.index != 0)
+        }
+    };
+    let (family, _) = &fonts[0];
+
+    // the fallback fonts used so far; runs refer to fonts by index, with
+    // the fallbacks following `fonts`
+    let mut fallbacks: Vec<(FontFace, ResolvedFont)> = Vec::new();
+    let mut runs: Vec<(Range<usize>, usize)> = Vec::new();
+    for (idx, grapheme) in text[range.clone()].grapheme_indices(true) {
+        let start = range.start + idx;
+        let chars = grapheme
+            .chars()
+            .filter(|c| needs_glyph(*c))
+            .collect::<Vec<_>>();
+        if chars.is_empty() {
+            let prev = runs.last().map(|(_, font)| *font).unwrap_or(0);
+            push_grapheme(&mut runs, start..start + grapheme.len(), prev);
+            continue;
+        }
+        let font_idx = (0..fonts.len())
+            .find(|&font_idx| chars.iter().all(|c| covers(font_idx, *c)))
+            .or_else(|| {
+                let c = chars.iter().copied().find(|c| !covers(0, *c))?;
+                let (face, data) = fonts::fallback_face(family, style.face_query(), c)?;
+                let existing = fallbacks
+                    .iter()
+                    .position(|(other, _)| other.to_raw_none() == face.to_raw_none());
+                let idx = existing.unwrap_or_else(|| {
+                    let resolved = ResolvedFont::new(
+                        &face,
+                        Some(data),
+                        style.display_size(),
+                        &style.font_variations,
+                    );
+                    fallbacks.push((face, resolved));
+                    fallbacks.len() - 1
+                });
+                Some(fonts.len() + idx)
+            })
+            .unwrap_or(0);
+        push_grapheme(&mut runs, start..start + grapheme.len(), font_idx);
+    }
+
+    if runs.is_empty() {
+        return vec![(range, fonts[0].1.clone())];
+    }
+    runs.into_iter()
+        .map(|(range, idx)| match fonts.get(idx) {
+            Some((_, font)) => (range, font.clone()),
+            None => (range, fallbacks[idx - fonts.len()].1.clone()),
+        })
+        .collect()
+}
+
+/// Add a grapheme to the last run if it uses the same font, or start a new run.
+fn push_grapheme(runs: &mut Vec<(Range<usize>, usize)>, range: Range<usize>, font_idx: usize) {
+    match runs.last_mut() {
+        Some((run, font)) if *font == font_idx => run.end = range.end,
+        _ => runs.push((range, font_idx)),
+    }
+}
+
+/// Whether `c` is displayed with a glyph from the font.
+fn needs_glyph(c: char) -> bool {
+    !(c.is_whitespace()
+        || c.is_control()
+        // zero-width spaces and joiners, and directional marks
+        || ('\u{200B}'..='\u{200F}').contains(&c)
+        || ('\u{2028}'..='\u{202E}').contains(&c)
+        || ('\u{2060}'..='\u{206F}').contains(&c)
+        // variation selectors
+        || ('\u{FE00}'..='\u{FE0F}').contains(&c)
+        || c == '\u{FEFF}')
+}
@@ -0,0 +1,1028 @@
+//! Access to font data, matching system fonts with fontconfig, fonts loaded
+//! from memory with `Text::load_font`, and fallback fonts.
+//!
+//! Font data is parsed with FreeType, and the resulting faces are handed to
+//! cairo. Each cairo face owns its FreeType face and a reference to the font
+//! data, and frees them once cairo is finished with it. Cairo faces are cached
+//! per thread, but the FreeType library and the data of system font files are
+//! shared by every thread.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::ptr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+
+use cairo::{FontFace, FontOptions, FontType, ScaledFont};
+use fontconfig_sys as fc;
+use fontconfig_sys::constants::{
+    FC_CHARSET, FC_FAMILY, FC_FILE, FC_INDEX, FC_SLANT, FC_VARIABLE, FC_WEIGHT, FC_WIDTH,
+};
+use freetype_sys as ft;
+
+use piet::{
+    Error, FontFaceInfo, FontFamily, FontFamilyInner, FontStretch, FontStyle, FontVariation,
+    FontWeight,
+};
+
+extern "C" {
+    // Part of cairo's FreeType backend, which cairo-rs only exposes with
+    // its `freetype` feature.
+    fn cairo_ft_font_face_create_for_ft_face(
+        face: ft::FT_Face,
+        load_flags: c_int,
+    ) -> *mut cairo_sys::cairo_font_face_t;
+    fn cairo_ft_font_face_set_synthesize(face: *mut cairo_sys::cairo_font_face_t, flags: c_uint);
+    fn cairo_ft_font_face_get_synthesize(face: *mut cairo_sys::cairo_font_face_t) -> c_uint;
+    fn cairo_ft_scaled_font_lock_face(font: *mut cairo_sys::cairo_scaled_font_t) -> ft::FT_Face;
+    fn cairo_ft_scaled_font_unlock_face(font: *mut cairo_sys::cairo_scaled_font_t);
+
+    // Added in cairo 1.16, which cairo-rs only exposes with its `v1_16`
+    // feature.
+    fn cairo_font_options_set_variations(
+        options: *mut cairo_sys::cairo_font_options_t,
+        variations: *const c_char,
+    );
+
+    // Not bound by freetype-sys.
+    fn FT_Load_Sfnt_Table(
+        face: ft::FT_Face,
+        tag: ft::FT_ULong,
+        offset: ft::FT_Long,
+        buffer: *mut ft::FT_Byte,
+        length: *mut ft::FT_ULong,
+    ) -> ft::FT_Error;
+}
+
+const CAIRO_FT_SYNTHESIZE_BOLD: c_uint = 1;
+const CAIRO_FT_SYNTHESIZE_OBLIQUE: c_uint = 2;
+
+/// CSS weights and the fontconfig weights they correspond to; these are the
+/// points fontconfig's `FcWeightFromOpenType` interpolates between.
+const FC_WEIGHTS: [(u16, c_int); 12] = [
+    (100, 0),
+    (200, 40),
+    (300, 50),
+    (350, 55),
+    (380, 75),
+    (400, 80),
+    (500, 100),
+    (600, 180),
+    (700, 200),
+    (800, 205),
+    (900, 210),
+    (1000, 215),
+];
+
+/// The FreeType library, or null if it hasn't been initialized.
+///
+/// FreeType needs faces to be created and freed one at a time, so this is
+/// locked while doing either.
+static FT_LIBRARY: Mutex<FtLibrary> = Mutex::new(FtLibrary(ptr::null_mut()));
+
+/// The data of system font files that are in use, keyed by their path.
+///
+/// The data is only held weakly, so it is freed along with the last face
+/// that uses it.
+static SYSTEM_FILES: Mutex<Option<HashMap<CString, Weak<[u8]>>>> = Mutex::new(None);
+
+/// The data of each font loaded with `load_font`, in the order they were
+/// loaded, along with the family of its first face.
+///
+/// Loaded fonts are shared by every thread, but cairo faces aren't, so each
+/// thread creates its own faces for them when it first needs them.
+static LOADED_FILES: Mutex<Vec<(Arc<[u8]>, String)>> = Mutex::new(Vec::new());
+
+/// The key of the `FaceOwner` attached to each cairo face we create.
+static FACE_OWNER_KEY: cairo_sys::cairo_user_data_key_t =
+    cairo_sys::cairo_user_data_key_t { unused: 0 };
+
+thread_local! {
+    /// The faces this thread has created for loaded fonts and system fonts.
+    static LOADED_FONTS: RefCell<LoadedFonts> = RefCell::new(LoadedFonts::default());
+    /// The data of system fonts, keyed by the family name, italic and bold
+    /// flags they were requested with.
+    static SYSTEM_FONT_DATA: RefCell<HashMap<(String, bool, bool), Option<FontData>>> =
+        RefCell::new(HashMap::new());
+    /// The file and index of the system font fontconfig matches for a family
+    /// name and query.
+    static SYSTEM_MATCHES: RefCell<HashMap<(String, FaceQuery), Option<FontFile>>> =
+        RefCell::new(HashMap::new());
+    /// The system fonts fontconfig suggests for a family, keyed by the family
+    /// name and query.
+    static FALLBACK_SETS: RefCell<HashMap<(String, FaceQuery), FallbackSet>> =
+        RefCell::new(HashMap::new());
+    /// Whether fontconfig knows of a family, keyed by its name.
+    static SYSTEM_FAMILIES: RefCell<HashMap<String, bool>> = RefCell::new(HashMap::new());
+}
+
+/// The path of a system font file, and the index of a face within it.
+type FontFile = (CString, c_int);
+
+/// The properties used to choose a face within a family.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) struct FaceQuery {
+    pub(crate) weight: FontWeight,
+    pub(crate) stretch: FontStretch,
+    pub(crate) style: FontStyle,
+}
+
+/// The data of a font file, and the index of a face within it.
+#[derive(Clone)]
+pub(crate) struct FontData {
+    data: Arc<[u8]>,
+    index: u32,
+}
+
+struct FtLibrary(ft::FT_Library);
+
+// FreeType libraries can be used from any thread, as long as faces aren't
+// created or freed on two threads at once.
+unsafe impl Send for FtLibrary {}
+
+/// What a cairo face owns: its FreeType face, and the data that face reads.
+struct FaceOwner {
+    ft_face: ft::FT_Face,
+    _data: Arc<[u8]>,
+}
+
+#[derive(Default)]
+struct LoadedFonts {
+    /// How many of the fonts in `LOADED_FILES` have faces in `faces`.
+    synced: usize,
+    /// The faces of loaded fonts, in the order they were loaded.
+    faces: Vec<LoadedFace>,
+    /// System fonts that have been matched, keyed by their file and index;
+    /// `None` if the font couldn't be loaded.
+    system: HashMap<FontFile, Option<LoadedFace>>,
+}
+
+struct LoadedFace {
+    family: String,
+    weight: FontWeight,
+    stretch: FontStretch,
+    style: FontStyle,
+    is_variable: bool,
+    face: FontFace,
+    /// The FreeType face, which is owned by `face`.
+    ft_face: ft::FT_Face,
+    data: FontData,
+    /// Copies of the face with synthesized styles, keyed by the cairo
+    /// synthesis flags.
+    synthesized: RefCell<Vec<(c_uint, FontFace)>>,
+}
+
+/// The fonts fontconfig suggests for a pattern, best match first.
+struct FallbackSet(*mut fc::FcFontSet);
+
+/// Load a font (or every font in a collection) from `data`, returning the
+/// family of the first face.
+pub(crate) fn load_font(data: &[u8]) -> Result<FontFamily, Error> {
+    LOADED_FONTS.with(|fonts| fonts.borrow_mut().load(data))
+}
+
+/// Call `f` with this thread's fonts, once it has faces for every font
+/// loaded so far on any thread.
+fn with_loaded_fonts<T>(f: impl FnOnce(&mut LoadedFonts) -> T) -> T {
+    LOADED_FONTS.with(|fonts| {
+        let mut fonts = fonts.borrow_mut();
+        let files = loaded_files();
+        fonts.sync(&files);
+        drop(files);
+        f(&mut fonts)
+    })
+}
+
+fn loaded_files() -> MutexGuard<'static, Vec<(Arc<[u8]>, String)>> {
+    LOADED_FILES.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The family of a loaded font, if one matches `name`.
+///
+/// Family names are matched case-insensitively, and the returned family
+/// uses the name from the font itself.
+pub(crate) fn loaded_family(name: &str) -> Option<FontFamily> {
+    with_loaded_fonts(|fonts| {
+        fonts
+            .faces
+            .iter()
+            .find(|face| face.family.eq_ignore_ascii_case(name))
+            .map(|face| FontFamily::new_unchecked(face.family.as_str()))
+    })
+}
+
+/// The families of loaded fonts and of the fonts fontconfig lists, sorted
+/// case-insensitively by name.
+///
+/// If a loaded family has the same name as a system family, only the loaded
+/// family is included.
+pub(crate) fn families() -> Vec<FontFamily> {
+    let mut names = with_loaded_fonts(|fonts| {
+        fonts
+            .faces
+            .iter()
+            .map(|face| face.family.clone())
+            .collect::<Vec<_>>()
+    });
+    names.extend(fc_list(None, &[FC_FAMILY], |pattern| unsafe {
+        fc_string(pattern, FC_FAMILY)
+    }));
+    // the sort is stable, so loaded families come first among equal names
+    names.sort_by_key(|name| name.to_lowercase());
+    names.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+    names.into_iter().map(FontFamily::new_unchecked).collect()
+}
+
+/// The faces of `family`; these are the loaded faces if it is a loaded
+/// family, and otherwise the system faces fontconfig lists for it.
+pub(crate) fn faces(family: &FontFamily) -> Vec<FontFaceInfo> {
+    if family.is_generic() || family.is_stack() {
+        return Vec::new();
+    }
+    let loaded = with_loaded_fonts(|fonts| {
+        fonts
+            .faces
+            .iter()
+            .filter(|face| face.family.eq_ignore_ascii_case(family.name()))
+            .map(LoadedFace::info)
+            .collect::<Vec<_>>()
+    });
+    let mut faces = if loaded.is_empty() {
+        system_faces(family.name())
+    } else {
+        loaded
+    };
+    faces.sort_by_key(|face| {
+        (
+            face.stretch,
+            face.style == FontStyle::Italic,
+            face.weight.to_raw(),
+            face.is_variable,
+        )
+    });
+    faces.dedup();
+    faces
+}
+
+fn system_faces(family: &str) -> Vec<FontFaceInfo> {
+    let objects = [FC_WEIGHT, FC_SLANT, FC_WIDTH, FC_INDEX, FC_VARIABLE];
+    fc_list(Some(family), &objects, |pattern| unsafe {
+        // the weight and width of a variable font are ranges, which can't be
+        // read as integers and are left at their defaults
+        let mut weight = fc::constants::FC_WEIGHT_REGULAR;
+        let mut slant = fc::constants::FC_SLANT_ROMAN;
+        let mut width = fc::constants::FC_WIDTH_NORMAL;
+        let mut index = 0;
+        let mut variable = 0;
+        fc::FcPatternGetInteger(pattern, FC_WEIGHT.as_ptr(), 0, &mut weight);
+        fc::FcPatternGetInteger(pattern, FC_SLANT.as_ptr(), 0, &mut slant);
+        fc::FcPatternGetInteger(pattern, FC_WIDTH.as_ptr(), 0, &mut width);
+        fc::FcPatternGetInteger(pattern, FC_INDEX.as_ptr(), 0, &mut index);
+        fc::FcPatternGetBool(pattern, FC_VARIABLE.as_ptr(), 0, &mut variable);
+        Some(FontFaceInfo {
+            weight: css_weight(weight),
+            style: if slant == fc::constants::FC_SLANT_ROMAN {
+                FontStyle::Regular
+            } else {
+                FontStyle::Italic
+            },
+            stretch: FontStretch::from_percentage(width.into()),
+            // the upper bits of the index select a named instance
+            is_variable: variable != 0 || index >> 16 != 0,
+        })
+    })
+}
+
+/// The families of `family` that are available, in order of preference.
+///
+/// For a stack, these are the generic families and the families that are
+/// either loaded or installed on the system. If none of them are, or if
+/// `family` isn't a stack, this is the first family, and it is left to
+/// fontconfig to pick a default font.
+pub(crate) fn available_families(family: &FontFamily) -> Vec<FontFamily> {
+    let families = family.families();
+    if !family.is_stack() {
+        return families.to_vec();
+    }
+    let mut available = families
+        .iter()
+        .filter_map(|family| {
+            if family.is_generic() {
+                Some(family.clone())
+            } else if let Some(loaded) = loaded_family(family.name()) {
+                Some(loaded)
+            } else if system_family_exists(family.name()) {
+                Some(family.clone())
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    if available.is_empty() {
+        available.push(families[0].clone());
+    }
+    available
+}
+
+/// Whether fontconfig lists any fonts in the family `name`.
+pub(crate) fn system_family_exists(name: &str) -> bool {
+    SYSTEM_FAMILIES.with(|families| {
+        *families
+            .borrow_mut()
+            .entry(name.to_owned())
+            .or_insert_with(|| fontconfig_has_family(name))
+    })
+}
+
+fn fontconfig_has_family(name: &str) -> bool {
+    !fc_list(Some(name), &[FC_FAMILY], |_| Some(())).is_empty()
+}
+
+/// List the system fonts, or those in `family`, with fontconfig, calling
+/// `f` with a pattern containing `objects` for each of them.
+fn fc_list<T>(
+    family: Option<&str>,
+    objects: &[&CStr],
+    mut f: impl FnMut(*mut fc::FcPattern) -> Option<T>,
+) -> Vec<T> {
+    let family = match family.map(CString::new).transpose() {
+        Ok(family) => family,
+        Err(_) => return Vec::new(),
+    };
+    let mut found = Vec::new();
+    unsafe {
+        let pattern = fc::FcPatternCreate();
+        let object_set = fc::FcObjectSetCreate();
+        if !pattern.is_null() && !object_set.is_null() {
+            if let Some(family) = &family {
+                fc::FcPatternAddString(pattern, FC_FAMILY.as_ptr(), family.as_ptr() as *const _);
+            }
+            for object in objects {
+                fc::FcObjectSetAdd(object_set, object.as_ptr());
+            }
+            let set = fc::FcFontList(ptr::null_mut(), pattern, object_set);
+            if !set.is_null() {
+                let fonts = (0..(*set).nfont as usize).map(|idx| *(*set).fonts.add(idx));
+                found.extend(fonts.filter_map(&mut f));
+                fc::FcFontSetDestroy(set);
+            }
+        }
+        if !object_set.is_null() {
+            fc::FcObjectSetDestroy(object_set);
+        }
+        if !pattern.is_null() {
+            fc::FcPatternDestroy(pattern);
+        }
+    }
+    found
+}
+
+/// The first value of a string property of a fontconfig pattern.
+unsafe fn fc_string(pattern: *mut fc::FcPattern, object: &CStr) -> Option<String> {
+    let mut value = ptr::null_mut();
+    if fc::FcPatternGetString(pattern, object.as_ptr(), 0, &mut value) != fc::FcResultMatch {
+        return None;
+    }
+    Some(
+        CStr::from_ptr(value as *const _)
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+/// The loaded face in `family` that best matches `query`, along with its
+/// data.
+pub(crate) fn loaded_face(family: &FontFamily, query: FaceQuery) -> Option<(FontFace, FontData)> {
+    with_loaded_fonts(|fonts| {
+        fonts
+            .faces
+            .iter()
+            .filter(|face| face.family.eq_ignore_ascii_case(family.name()))
+            .min_by_key(|face| face.distance(query))
+            .map(|face| (face.font_face(query), face.data.clone()))
+    })
+}
+
+/// The system font that fontconfig matches for `family` and `query`, along
+/// with its data.
+///
+/// Fontconfig always finds a font, even if `family` isn't installed; this
+/// only returns `None` if that font couldn't be loaded.
+pub(crate) fn system_face(family: &FontFamily, query: FaceQuery) -> Option<(FontFace, FontData)> {
+    let name = fontconfig_name(family);
+    let (file, index) = SYSTEM_MATCHES.with(|matches| {
+        matches
+            .borrow_mut()
+            .entry((name.to_owned(), query))
+            .or_insert_with(|| fontconfig_match(name, query))
+            .clone()
+    })?;
+    LOADED_FONTS.with(|fonts| {
+        let mut fonts = fonts.borrow_mut();
+        let face = fonts.system_face(file, index)?;
+        Some((face.font_face(query), face.data.clone()))
+    })
+}
+
+/// A font that has a glyph for `c`, for use when the font chosen for `family`
+/// doesn't.
+///
+/// Fonts loaded with `load_font` are tried first, in the order they were
+/// loaded, so that the result doesn't depend on the fonts installed on the
+/// system. If none of them support `c`, the system fonts that fontconfig
+/// suggests for `family` are tried in order.
+pub(crate) fn fallback_face(
+    family: &FontFamily,
+    query: FaceQuery,
+    c: char,
+) -> Option<(FontFace, FontData)> {
+    with_loaded_fonts(|fonts| {
+        if let Some(face) = fonts.loaded_fallback(query, c) {
+            return Some((face.font_face(query), face.data.clone()));
+        }
+
+        let name = fontconfig_name(family);
+        let (file, index) = FALLBACK_SETS.with(|sets| {
+            sets.borrow_mut()
+                .entry((name.to_owned(), query))
+                .or_insert_with(|| FallbackSet::new(name, query))
+                .font_for_char(c)
+        })?;
+        let face = fonts.system_face(file, index)?;
+        Some((face.font_face(query), face.data.clone()))
+    })
+}
+
+/// The data of a system font created with cairo's toy font API.
+///
+/// The result is cached using the parameters the font was requested with.
+/// This is `None` if cairo isn't using FreeType for the font.
+pub(crate) fn system_font_data(
+    family: &str,
+    italic: bool,
+    bold: bool,
+    font: &ScaledFont,
+) -> Option<FontData> {
+    SYSTEM_FONT_DATA.with(|cache| {
+        cache
+            .borrow_mut()
+            .entry((family.to_owned(), italic, bold))
+            .or_insert_with(|| scaled_font_data(font))
+            .clone()
+    })
+}
+
+fn scaled_font_data(font: &ScaledFont) -> Option<FontData> {
+    if font.get_type() != FontType::FontTypeFt {
+        return None;
+    }
+    unsafe {
+        let face = cairo_ft_scaled_font_lock_face(font.to_raw_none());
+        if face.is_null() {
+            return None;
+        }
+        // a tag of zero loads the whole font file
+        let mut len = 0;
+        let mut data = Vec::new();
+        let mut err = FT_Load_Sfnt_Table(face, 0, 0, ptr::null_mut(), &mut len);
+        if err == 0 {
+            data.resize(len as usize, 0);
+            err = FT_Load_Sfnt_Table(face, 0, 0, data.as_mut_ptr(), &mut len);
+        }
+        let index = (*face).face_index;
+        cairo_ft_scaled_font_unlock_face(font.to_raw_none());
+        if err != 0 {
+            return None;
+        }
+        Some(FontData::new(data.into(), index))
+    }
+}
+
+/// The style name of the face used by `font`, such as "Bold Oblique".
+#[cfg(test)]
+pub(crate) fn style_name(font: &ScaledFont) -> String {
+    unsafe {
+        let face = cairo_ft_scaled_font_lock_face(font.to_raw_none());
+        let name = CStr::from_ptr((*face).style_name)
+            .to_string_lossy()
+            .into_owned();
+        cairo_ft_scaled_font_unlock_face(font.to_raw_none());
+        name
+    }
+}
+
+/// Font options that set the axes of variable fonts to `variations`.
+pub(crate) fn font_options(variations: &[FontVariation]) -> FontOptions {
+    let options = FontOptions::default();
+    if !variations.is_empty() {
+        let settings = variations
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        if let Ok(settings) = CString::new(settings) {
+            unsafe { cairo_font_options_set_variations(options.to_raw_none(), settings.as_ptr()) };
+        }
+    }
+    options
+}
+
+/// Whether cairo synthesizes bold and oblique styles for `face`.
+#[cfg(test)]
+pub(crate) fn synthesized(face: &FontFace) -> (bool, bool) {
+    let flags = unsafe { cairo_ft_font_face_get_synthesize(face.to_raw_none()) };
+    (
+        flags & CAIRO_FT_SYNTHESIZE_BOLD != 0,
+        flags & CAIRO_FT_SYNTHESIZE_OBLIQUE != 0,
+    )
+}
+
+impl FontData {
+    fn new(data: Arc<[u8]>, index: ft::FT_Long) -> FontData {
+        // the upper bits of the index select a named instance of a variable font
+        let index = (index & 0xFFFF) as u32;
+        FontData { data, index }
+    }
+
+    /// Parse the face for shaping.
+    pub(crate) fn face(&self) -> Option<rustybuzz::Face<'_>> {
+        rustybuzz::Face::from_slice(&self.data, self.index)
+    }
+
+    /// Whether `self` and `other` share the same copy of their data.
+    #[cfg(test)]
+    pub(crate) fn shares_data(&self, other: &FontData) -> bool {
+        Arc::ptr_eq(&self.data, &other.data)
+    }
+}
+
+fn lock_ft_library() -> MutexGuard<'static, FtLibrary> {
+    // nothing we do while holding the lock can leave the library in a bad state
+    FT_LIBRARY.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Lock the FreeType library, initializing it if that hasn't been done yet.
+fn ft_library() -> Result<MutexGuard<'static, FtLibrary>, Error> {
+    let mut library = lock_ft_library();
+    if library.0.is_null() {
+        let mut new = ptr::null_mut();
+        if unsafe { ft::FT_Init_FreeType(&mut new) } != 0 {
+            return Err(Error::FontLoadingFailed);
+        }
+        library.0 = new;
+    }
+    Ok(library)
+}
+
+/// Create a cairo face for the face at `index` in `data`, returning it along
+/// with its FreeType face.
+///
+/// The cairo face owns the FreeType face and a reference to `data`, and
+/// frees them once cairo is finished with it.
+fn new_face(data: &Arc<[u8]>, index: ft::FT_Long) -> Option<(FontFace, ft::FT_Face)> {
+    let library = ft_library().ok()?;
+    let mut ft_face = ptr::null_mut();
+    let err = unsafe {
+        ft::FT_New_Memory_Face(
+            library.0,
+            data.as_ptr(),
+            data.len() as ft::FT_Long,
+            index,
+            &mut ft_face,
+        )
+    };
+    // cairo can call free_face_owner from here on, which takes the lock
+    drop(library);
+    if err != 0 {
+        return None;
+    }
+
+    unsafe {
+        let raw = cairo_ft_font_face_create_for_ft_face(ft_face, 0);
+        let owner = Box::into_raw(Box::new(FaceOwner {
+            ft_face,
+            _data: data.clone(),
+        }));
+        let mut status = cairo_sys::cairo_font_face_status(raw);
+        if status == cairo_sys::STATUS_SUCCESS {
+            status = cairo_sys::cairo_font_face_set_user_data(
+                raw,
+                &FACE_OWNER_KEY,
+                owner as *mut c_void,
+                Some(free_face_owner),
+            );
+        }
+        if status != cairo_sys::STATUS_SUCCESS {
+            cairo_sys::cairo_font_face_destroy(raw);
+            free_face_owner(owner as *mut c_void);
+            return None;
+        }
+        Some((FontFace::from_raw_full(raw), ft_face))
+    }
+}
+
+/// Called by cairo when it is finished with a face we created.
+///
+/// This can happen on any thread, as cairo keeps a global cache of recently
+/// used fonts.
+unsafe extern "C" fn free_face_owner(owner: *mut c_void) {
+    let owner = Box::from_raw(owner as *mut FaceOwner);
+    let _library = lock_ft_library();
+    ft::FT_Done_Face(owner.ft_face);
+}
+
+/// The data of the system font `file`, which is only read if no other face
+/// is using it.
+fn system_file_data(file: &CStr) -> Option<Arc<[u8]>> {
+    let mut files = SYSTEM_FILES.lock().unwrap_or_else(PoisonError::into_inner);
+    let files = files.get_or_insert_with(HashMap::new);
+    if let Some(data) = files.get(file).and_then(Weak::upgrade) {
+        return Some(data);
+    }
+    let data: Arc<[u8]> = std::fs::read(file.to_str().ok()?).ok()?.into();
+    files.retain(|_, data| data.strong_count() > 0);
+    files.insert(file.to_owned(), Arc::downgrade(&data));
+    Some(data)
+}
+
+/// The name fontconfig knows `family` by.
+fn fontconfig_name(family: &FontFamily) -> &str {
+    match family.inner() {
+        // fontconfig has no configuration for system-ui
+        FontFamilyInner::SystemUi => "sans-serif",
+        _ => family.name(),
+    }
+}
+
+/// The fontconfig weight for a CSS weight.
+///
+/// This interpolates between the same points as fontconfig's
+/// `FcWeightFromOpenType`, so that every weight is distinct.
+fn fc_weight(weight: FontWeight) -> c_int {
+    let weight = c_int::from(weight.to_raw().max(100));
+    FC_WEIGHTS
+        .windows(2)
+        .find(|pair| weight <= c_int::from(pair[1].0))
+        .map(|pair| {
+            let (css0, fc0) = (c_int::from(pair[0].0), pair[0].1);
+            let (css1, fc1) = (c_int::from(pair[1].0), pair[1].1);
+            fc0 + (weight - css0) * (fc1 - fc0) / (css1 - css0)
+        })
+        .unwrap_or(215)
+}
+
+/// The CSS weight for a fontconfig weight; the inverse of `fc_weight`.
+fn css_weight(weight: c_int) -> FontWeight {
+    let weight = weight.max(0);
+    let css = FC_WEIGHTS
+        .windows(2)
+        .find(|pair| weight <= pair[1].1)
+        .map(|pair| {
+            let (css0, fc0) = (c_int::from(pair[0].0), pair[0].1);
+            let (css1, fc1) = (c_int::from(pair[1].0), pair[1].1);
+            css0 + (weight - fc0) * (css1 - css0) / (fc1 - fc0)
+        })
+        .unwrap_or(1000);
+    FontWeight::new(css as u16)
+}
+
+/// The fontconfig width for a stretch, which is a percentage.
+fn fc_width(stretch: FontStretch) -> c_int {
+    stretch.to_percentage().round() as c_int
+}
+
+/// Create a fontconfig pattern for `family` and `query`, with the default
+/// substitutions applied.
+unsafe fn fc_pattern(family: &str, query: FaceQuery) -> *mut fc::FcPattern {
+    let family = CString::new(family).unwrap_or_default();
+    let slant = match query.style {
+        FontStyle::Regular => fc::constants::FC_SLANT_ROMAN,
+        // fontconfig prefers an oblique face to a roman one
+        FontStyle::Italic => fc::constants::FC_SLANT_ITALIC,
+    };
+    let pattern = fc::FcPatternCreate();
+    if pattern.is_null() {
+        return pattern;
+    }
+    fc::FcPatternAddString(pattern, FC_FAMILY.as_ptr(), family.as_ptr() as *const _);
+    fc::FcPatternAddInteger(pattern, FC_WEIGHT.as_ptr(), fc_weight(query.weight));
+    fc::FcPatternAddInteger(pattern, FC_WIDTH.as_ptr(), fc_width(query.stretch));
+    fc::FcPatternAddInteger(pattern, FC_SLANT.as_ptr(), slant);
+    fc::FcConfigSubstitute(ptr::null_mut(), pattern, fc::FcMatchPattern);
+    fc::FcDefaultSubstitute(pattern);
+    pattern
+}
+
+/// The file and index of a font pattern.
+unsafe fn fc_file(pattern: *mut fc::FcPattern) -> Option<FontFile> {
+    let mut file = ptr::null_mut();
+    let mut index = 0;
+    if fc::FcPatternGetString(pattern, FC_FILE.as_ptr(), 0, &mut file) != fc::FcResultMatch {
+        return None;
+    }
+    fc::FcPatternGetInteger(pattern, FC_INDEX.as_ptr(), 0, &mut index);
+    Some((CStr::from_ptr(file as *const _).to_owned(), index))
+}
+
+fn fontconfig_match(family: &str, query: FaceQuery) -> Option<FontFile> {
+    unsafe {
+        let pattern = fc_pattern(family, query);
+        if pattern.is_null() {
+            return None;
+        }
+        let mut result = fc::FcResultMatch;
+        let matched = fc::FcFontMatch(ptr::null_mut(), pattern, &mut result);
+        fc::FcPatternDestroy(pattern);
+        if matched.is_null() {
+            return None;
+        }
+        let file = fc_file(matched);
+        fc::FcPatternDestroy(matched);
+        file
+    }
+}
+
+impl FallbackSet {
+    fn new(family: &str, query: FaceQuery) -> FallbackSet {
+        unsafe {
+            let pattern = fc_pattern(family, query);
+            if pattern.is_null() {
+                return FallbackSet(ptr::null_mut());
+            }
+            let mut result = fc::FcResultMatch;
+            // trimming drops fonts that wouldn't add any characters
+            let set = fc::FcFontSort(ptr::null_mut(), pattern, 1, ptr::null_mut(), &mut result);
+            fc::FcPatternDestroy(pattern);
+            FallbackSet(set)
+        }
+    }
+
+    /// The file and index of the first font in the set with a glyph for `c`.
+    fn font_for_char(&self, c: char) -> Option<FontFile> {
+        if self.0.is_null() {
+            return None;
+        }
+        unsafe {
+            let set = &*self.0;
+            (0..set.nfont as usize)
+                .map(|idx| *set.fonts.add(idx))
+                .find(|&pattern| {
+                    let mut charset = ptr::null_mut();
+                    fc::FcPatternGetCharSet(pattern, FC_CHARSET.as_ptr(), 0, &mut charset)
+                        == fc::FcResultMatch
+                        && fc::FcCharSetHasChar(charset, c as u32) != 0
+                })
+                .and_then(|pattern| fc_file(pattern))
+        }
+    }
+}
+
+impl Drop for FallbackSet {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { fc::FcFontSetDestroy(self.0) }
+        }
+    }
+}
+
+impl LoadedFonts {
+    /// The best match for `query` in the first loaded family with a glyph
+    /// for `c`.
+    fn loaded_fallback(&self, query: FaceQuery, c: char) -> Option<&LoadedFace> {
+        let family = &self.faces.iter().find(|face| face.has_glyph(c))?.family;
+        self.faces
+            .iter()
+            .filter(|face| face.family == *family && face.has_glyph(c))
+            .min_by_key(|face| face.distance(query))
+    }
+
+    /// Load the face at `index` in the system font `file`, if it hasn't been
+    /// loaded already.
+    fn system_face(&mut self, file: CString, index: c_int) -> Option<&LoadedFace> {
+        let key = (file, index);
+        if !self.system.contains_key(&key) {
+            let face = load_system_face(&key.0, key.1);
+            self.system.insert(key.clone(), face);
+        }
+        self.system[&key].as_ref()
+    }
+
+    /// Create faces for the fonts in `files` that were loaded since the last
+    /// call.
+    fn sync(&mut self, files: &[(Arc<[u8]>, String)]) {
+        for (data, _) in &files[self.synced..] {
+            self.faces.extend(load_faces(data));
+        }
+        self.synced = files.len();
+    }
+
+    fn load(&mut self, data: &[u8]) -> Result<FontFamily, Error> {
+        // the registry stays locked until the font is added, so that two
+        // threads loading the same data register it once
+        let mut files = loaded_files();
+        self.sync(&files);
+        if let Some((_, family)) = files.iter().find(|(loaded, _)| **loaded == *data) {
+            return Ok(FontFamily::new_unchecked(family.as_str()));
+        }
+
+        drop(ft_library()?);
+        let data: Arc<[u8]> = data.into();
+        let faces = load_faces(&data);
+        let family = match faces.first() {
+            Some(face) => face.family.clone(),
+            None => return Err(Error::FontLoadingFailed),
+        };
+        files.push((data, family.clone()));
+        self.synced = files.len();
+        self.faces.extend(faces);
+        Ok(FontFamily::new_unchecked(family))
+    }
+}
+
+/// Create every face in a loaded font.
+fn load_faces(data: &Arc<[u8]>) -> Vec<LoadedFace> {
+    let mut faces = Vec::new();
+    let mut num_faces = 1;
+    let mut index = 0;
+    while index < num_faces {
+        if let Some((face, count)) = LoadedFace::load(data, index) {
+            faces.push(face);
+            num_faces = count;
+        } else if index == 0 {
+            break;
+        }
+        index += 1;
+    }
+    faces
+}
+
+fn load_system_face(file: &CStr, index: c_int) -> Option<LoadedFace> {
+    let data = system_file_data(file)?;
+    let (face, _) = LoadedFace::load(&data, index as ft::FT_Long)?;
+    Some(face)
+}
+
+impl LoadedFace {
+    /// Create the face at `index` in `data`, returning it along with the
+    /// number of faces in `data`.
+    fn load(data: &Arc<[u8]>, index: ft::FT_Long) -> Option<(LoadedFace, ft::FT_Long)> {
+        let (face, ft_face) = new_face(data, index)?;
+        unsafe {
+            let num_faces = (*ft_face).num_faces;
+            let loaded = LoadedFace::new(face, ft_face, FontData::new(data.clone(), index))?;
+            Some((loaded, num_faces))
+        }
+    }
+
+    /// How far this face is from `query`, for choosing the best face in a
+    /// family.
+    ///
+    /// As in CSS, the stretch is considered first, followed by the style and
+    /// then the weight.
+    fn distance(&self, query: FaceQuery) -> (u16, bool, i32) {
+        let stretch = self.stretch.to_width_class();
+        let stretch_distance = (stretch as i16 - query.stretch.to_width_class() as i16).abs();
+        let weight_distance =
+            (i32::from(self.weight.to_raw()) - i32::from(query.weight.to_raw())).abs();
+        (
+            stretch_distance as u16,
+            self.style != query.style,
+            weight_distance,
+        )
+    }
+
+    /// The cairo face to use for `query`.
+    ///
+    /// If `query` is bold or italic and this face isn't, a copy of the face
+    /// with synthesized emboldening or slant is returned.
+    fn font_face(&self, query: FaceQuery) -> FontFace {
+        let mut flags = 0;
+        if query.weight.to_raw() >= 600 && self.weight.to_raw() < 600 {
+            flags |= CAIRO_FT_SYNTHESIZE_BOLD;
+        }
+        if query.style == FontStyle::Italic && self.style == FontStyle::Regular {
+            flags |= CAIRO_FT_SYNTHESIZE_OBLIQUE;
+        }
+        if flags == 0 {
+            return reference(&self.face);
+        }
+
+        let mut synthesized = self.synthesized.borrow_mut();
+        if let Some((_, face)) = synthesized.iter().find(|(other, _)| *other == flags) {
+            return reference(face);
+        }
+        // cairo keeps one face per FreeType face and set of options, so the
+        // copy needs its own FreeType face
+        match new_face(&self.data.data, self.data.index.into()) {
+            Some((face, _)) => {
+                unsafe { cairo_ft_font_face_set_synthesize(face.to_raw_none(), flags) };
+                synthesized.push((flags, face));
+                reference(&synthesized.last().unwrap().1)
+            }
+            None => reference(&self.face),
+        }
+    }
+
+    fn info(&self) -> FontFaceInfo {
+        FontFaceInfo {
+            weight: self.weight,
+            style: self.style,
+            stretch: self.stretch,
+            is_variable: self.is_variable,
+        }
+    }
+
+    fn has_glyph(&self, c: char) -> bool {
+        unsafe { ft::FT_Get_Char_Index(self.ft_face, c as ft::FT_ULong) != 0 }
+    }
+
+    /// Wrap a cairo face and the FreeType face it owns.
+    ///
+    /// Returns `None` if the face has no family name.
+    unsafe fn new(face: FontFace, ft_face: ft::FT_Face, data: FontData) -> Option<LoadedFace> {
+        let (family, weight, stretch, style) = face_properties(ft_face)?;
+        let is_variable = ft::FT_HAS_MULTIPLE_MASTERS(ft_face);
+        Some(LoadedFace {
+            family,
+            weight,
+            stretch,
+            style,
+            is_variable,
+            face,
+            ft_face,
+            data,
+            synthesized: RefCell::new(Vec::new()),
+        })
+    }
+}
+
+/// The family name, weight, stretch and style of a FreeType face, or `None`
+/// if it has no family name.
+unsafe fn face_properties(
+    face: ft::FT_Face,
+) -> Option<(String, FontWeight, FontStretch, FontStyle)> {
+    let family_name = (*face).family_name;
+    if family_name.is_null() {
+        return None;
+    }
+    let family = CStr::from_ptr(family_name).to_string_lossy().into_owned();
+
+    let os2 = ft::FT_Get_Sfnt_Table(face, ft::ft_sfnt_os2) as *const ft::TT_OS2;
+    let weight = if !os2.is_null() && (*os2).usWeightClass != 0 {
+        FontWeight::new((*os2).usWeightClass)
+    } else if (*face).style_flags & ft::FT_STYLE_FLAG_BOLD != 0 {
+        FontWeight::BOLD
+    } else {
+        FontWeight::REGULAR
+    };
+    let stretch = if !os2.is_null() && (*os2).usWidthClass != 0 {
+        FontStretch::from_width_class((*os2).usWidthClass)
+    } else {
+        FontStretch::Normal
+    };
+    let style = if (*face).style_flags & ft::FT_STYLE_FLAG_ITALIC != 0 {
+        FontStyle::Italic
+    } else {
+        FontStyle::Regular
+    };
+    Some((family, weight, stretch, style))
+}
+
+/// The family, weight and style of `font`, including any styles that cairo
+/// synthesizes for it.
+pub(crate) fn font_description(font: &ScaledFont) -> Option<(FontFamily, FontWeight, FontStyle)> {
+    if font.get_type() != FontType::FontTypeFt {
+        return None;
+    }
+    unsafe {
+        let face = cairo_ft_scaled_font_lock_face(font.to_raw_none());
+        let properties = if face.is_null() {
+            None
+        } else {
+            face_properties(face)
+        };
+        cairo_ft_scaled_font_unlock_face(font.to_raw_none());
+        let (family, mut weight, _, mut style) = properties?;
+
+        let font_face = cairo_sys::cairo_scaled_font_get_font_face(font.to_raw_none());
+        let synthesize = cairo_ft_font_face_get_synthesize(font_face);
+        if synthesize & CAIRO_FT_SYNTHESIZE_BOLD != 0 && weight.to_raw() < 700 {
+            weight = FontWeight::BOLD;
+        }
+        if synthesize & CAIRO_FT_SYNTHESIZE_OBLIQUE != 0 {
+            style = FontStyle::Italic;
+        }
+        Some((FontFamily::new_unchecked(family), weight, style))
+    }
+}
+
+/// A new reference to `face`.
+fn reference(face: &FontFace) -> FontFace {
+    // Without glib, cairo-rs doesn't take a new reference when cloning a
+    // `FontFace`, but still releases one when it is dropped.
+    unsafe {
+        let raw = cairo_sys::cairo_font_face_reference(face.to_raw_none());
+        FontFace::from_raw_full(raw)
+    }
+}
@@ -1,28 +1,52 @@
-use cairo::ScaledFont;
+use std::ops::Range;
+
 use piet::HitTestPoint;
 use unicode_segmentation::UnicodeSegmentation;
 
-use super::hit_test_line_position;
+use super::{LineRun, StyleRun};
 
 /// get grapheme boundaries, intended to act on a line of text, not a full text layout that has
 /// both horizontal and vertial components
+///
+/// `line` is the range of the line within `text`, and the grapheme is measured within `piece`, a
+/// part of `run` on that line. The returned positions are relative to the start of the line.
+/// The boundaries are in visual order, so in right-to-left text `curr_idx` is the end of the
+/// grapheme.
 pub(crate) fn get_grapheme_boundaries(
-    font: &ScaledFont,
+    run: &StyleRun,
+    piece: &LineRun,
     text: &str,
+    line: Range<usize>,
     grapheme_position: usize,
 ) -> Option<GraphemeBoundaries> {
-    let mut graphemes = UnicodeSegmentation::grapheme_indices(text, true);
+    let line_text = &text[line.clone()];
+    let mut graphemes = UnicodeSegmentation::grapheme_indices(line_text, true);
     let (text_position, _) = graphemes.nth(grapheme_position)?;
-    let (next_text_position, _) = graphemes.next().unwrap_or_else(|| (text.len(), ""));
+    let (next_text_position, _) = graphemes.next().unwrap_or_else(|| (line_text.len(), ""));
 
-    let curr_edge = hit_test_line_position(font, text, text_position);
-    let next_edge = hit_test_line_position(font, text, next_text_position);
+    let edge = |pos: usize| {
+        let pos = (line.start + pos)
+            .max(piece.range.start)
+            .min(piece.range.end);
+        piece.x + run.x_in_part(text, piece.range.clone(), pos)
+    };
+    let curr_edge = edge(text_position);
+    let next_edge = edge(next_text_position);
 
-    let res = GraphemeBoundaries {
-        curr_idx: text_position,
-        next_idx: next_text_position,
-        leading: curr_edge,
-        trailing: next_edge,
+    let res = if curr_edge <= next_edge {
+        GraphemeBoundaries {
+            curr_idx: text_position,
+            next_idx: next_text_position,
+            leading: curr_edge,
+            trailing: next_edge,
+        }
+    } else {
+        GraphemeBoundaries {
+            curr_idx: next_text_position,
+            next_idx: text_position,
+            leading: next_edge,
+            trailing: curr_edge,
+        }
     };
 
     Some(res)
@@ -71,6 +95,9 @@ mod test {
         let text = "piet";
 
         let font = CairoFont::new(FontFamily::SANS_SERIF).resolve_simple(12.0);
+        let runs = [StyleRun::with_font(text, 0..text.len(), &font)];
+        let pieces = line_runs(&runs, text, 0..text.len(), false);
+        let (run, piece) = (&runs[0], &pieces[0]);
 
         let expected_3 = GraphemeBoundaries {
             curr_idx: 3,
@@ -81,14 +108,21 @@ mod test {
 
         // test grapheme boundaries
         assert_eq!(
-            get_grapheme_boundaries(&font, text, 3).unwrap().curr_idx,
+            get_grapheme_boundaries(run, piece, text, 0..text.len(), 3)
+                .unwrap()
+                .curr_idx,
             expected_3.curr_idx
         );
         assert_eq!(
-            get_grapheme_boundaries(&font, text, 3).unwrap().next_idx,
+            get_grapheme_boundaries(run, piece, text, 0..text.len(), 3)
+                .unwrap()
+                .next_idx,
             expected_3.next_idx
         );
-        assert_eq!(get_grapheme_boundaries(&font, text, 4), None);
+        assert_eq!(
+            get_grapheme_boundaries(run, piece, text, 0..text.len(), 4),
+            None
+        );
     }
 
     #[test]
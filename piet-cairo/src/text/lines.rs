@@ -1,9 +1,113 @@
-use cairo::ScaledFont;
+use std::ops::Range;
+
+use hypher::Lang;
+use piet::{Hyphens, Justification, TabAlignment, TabStops};
+use unicode_script::{Script, UnicodeScript};
+use unicode_segmentation::UnicodeSegmentation;
 use xi_unicode::LineBreakIterator;
 
-use super::LineMetric;
+use super::{advance_width, runs_in_range, vertical_metrics, LineMetric, StyleRun};
+
+const SOFT_HYPHEN: char = '\u{ad}';
+
+/// A layout's tab stops, along with the width of a space in its default
+/// font, for intervals that are measured in spaces.
+#[derive(Clone)]
+pub(crate) struct Tabs {
+    pub(crate) stops: TabStops,
+    pub(crate) space_width: f64,
+}
 
-pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64) -> Vec<LineMetric> {
+/// A place where a line can end.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Break {
+    pub(crate) offset: usize,
+    /// Whether the line must end here.
+    pub(crate) hard: bool,
+    /// Whether a line that ends here ends in the middle of a word, and so
+    /// is shown with a hyphen.
+    pub(crate) hyphen: bool,
+}
+
+/// The places where lines in `text` can end, in order.
+///
+/// Soft hyphens are break opportunities unless `hyphens` is `None`; with
+/// `Auto`, so are the places that the hyphenation rules of `lang` allow.
+pub(crate) fn line_breaks(text: &str, hyphens: Hyphens, lang: Option<Lang>) -> Vec<Break> {
+    let mut breaks = LineBreakIterator::new(text)
+        .filter_map(|(offset, hard)| {
+            let hyphen = text[..offset].ends_with(SOFT_HYPHEN);
+            if hyphen && hyphens == Hyphens::None {
+                return None;
+            }
+            Some(Break {
+                offset,
+                hard,
+                hyphen,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if let (Hyphens::Auto, Some(lang)) = (hyphens, lang) {
+        for (start, word) in text.split_word_bound_indices() {
+            if !word.chars().all(char::is_alphabetic) {
+                continue;
+            }
+            let mut offset = start;
+            for syllable in hypher::hyphenate(word, lang) {
+                if offset > start {
+                    breaks.push(Break {
+                        offset,
+                        hard: false,
+                        hyphen: true,
+                    });
+                }
+                offset += syllable.len();
+            }
+        }
+        breaks.sort_by_key(|brk| brk.offset);
+    }
+    breaks
+}
+
+/// Stop lines from ending inside `range`, and let them end on either side
+/// of it, as for an inline object.
+pub(crate) fn break_around(breaks: &mut Vec<Break>, range: Range<usize>) {
+    breaks.retain(|brk| brk.offset <= range.start || brk.offset >= range.end);
+    for offset in [range.start, range.end] {
+        let idx = match breaks.binary_search_by_key(&offset, |brk| brk.offset) {
+            Ok(_) => continue,
+            Err(idx) => idx,
+        };
+        if offset > 0 {
+            let brk = Break {
+                offset,
+                hard: false,
+                hyphen: false,
+            };
+            breaks.insert(idx, brk);
+        }
+    }
+}
+
+/// The hyphenation rules for a BCP 47 language tag, if there are any.
+pub(crate) fn hyphenation_lang(tag: &str) -> Option<Lang> {
+    let primary = tag.split(['-', '_']).next()?;
+    match primary.to_ascii_lowercase().as_bytes() {
+        &[a, b] => Lang::from_iso([a, b]),
+        _ => None,
+    }
+}
+
+/// Break `text` into lines that fit within `width` where possible, at the
+/// given `breaks`.
+pub(crate) fn calculate_line_metrics(
+    text: &str,
+    runs: &[StyleRun],
+    breaks: &[Break],
+    width: f64,
+    tabs: Option<&Tabs>,
+) -> Vec<LineMetric> {
     // first pass, completely naive and inefficient. Check at every break to see if line longer
     // than width.
     //
@@ -15,8 +119,8 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
     //
     // So, every time there's a a hard break, must break.
     //
-    // soft-hyphen, don't need to deal with this round. Looks like automatic hyphenation, but
-    // with unicode codepoint. Don't special case, let if break for now.
+    // soft-hyphen, and automatic hyphenation: these are breaks too, but a line that ends at
+    // one is shown with a hyphen, so its width includes the hyphen's.
     //
     // For soft breaks, then I need to check line widths etc.
     //
@@ -24,7 +128,8 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
     // One word is considered the smallest unit, don't break below words for now.
     //
     // Use font extents height (it's different from text extents height,
-    // which relates to bounding box)
+    // which relates to bounding box). When a line mixes fonts, the line is made tall enough for
+    // the largest ascent and the largest descent.
     //
     // For baseline, use use `FontExtent.ascent`. Needs to be positive?
     // see https://glyphsapp.com/tutorials/vertical-metrics
@@ -35,15 +140,17 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
     let mut prev_break = 0;
     let mut y_offset = 0.0;
 
-    // vertical measures constant across all lines for now (cairo toy text)
-    let height = font.extents().height;
-    let baseline = font.extents().ascent;
-
-    for (line_break, is_hard_break) in LineBreakIterator::new(text) {
-        if !is_hard_break {
+    for brk in breaks {
+        let line_break = brk.offset;
+        let hyphen = if brk.hyphen {
+            hyphen_width(runs, line_break)
+        } else {
+            0.0
+        };
+        if !brk.hard {
             // this section is for soft breaks
-            let curr_str = &text[line_start..line_break];
-            let curr_width = font.text_extents(curr_str).x_advance;
+            let curr_range = line_start..line_break;
+            let curr_width = line_width(runs, text, curr_range, tabs) + hyphen;
 
             if curr_width > width {
                 // since curr_width is longer than desired line width, it's time to break ending
@@ -64,8 +171,7 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
                     text,
                     line_start,
                     prev_break,
-                    baseline,
-                    height,
+                    runs,
                     &mut y_offset,
                     &mut line_metrics,
                 );
@@ -76,16 +182,15 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
                 //
                 // If it's shorter than desired width, just continue.
 
-                let curr_str = &text[prev_break..line_break];
-                let curr_width = font.text_extents(curr_str).x_advance;
+                let curr_range = prev_break..line_break;
+                let curr_width = line_width(runs, text, curr_range, tabs) + hyphen;
 
                 if curr_width > width {
                     add_line_metric(
                         text,
                         prev_break,
                         line_break,
-                        baseline,
-                        height,
+                        runs,
                         &mut y_offset,
                         &mut line_metrics,
                     );
@@ -106,8 +211,8 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
 
             // even when there's a hard break, need to check first to see if width is too wide. If
             // it is, need to break at the previous soft break first.
-            let curr_str = &text[line_start..line_break];
-            let curr_width = font.text_extents(curr_str).x_advance;
+            let curr_range = line_start..line_break;
+            let curr_width = line_width(runs, text, curr_range, tabs);
 
             if curr_width > width {
                 // if line is too wide but can't break down anymore, just skip to the next
@@ -121,8 +226,7 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
                         text,
                         line_start,
                         prev_break,
-                        baseline,
-                        height,
+                        runs,
                         &mut y_offset,
                         &mut line_metrics,
                     );
@@ -136,8 +240,7 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
                 text,
                 line_start,
                 line_break,
-                baseline,
-                height,
+                runs,
                 &mut y_offset,
                 &mut line_metrics,
             );
@@ -149,17 +252,117 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
     line_metrics
 }
 
+/// The width of `range` of `text` laid out as a line, with its tabs
+/// advancing to `tabs` if there are any.
+fn line_width(runs: &[StyleRun], text: &str, range: Range<usize>, tabs: Option<&Tabs>) -> f64 {
+    let width = advance_width(runs, text, range.clone());
+    match tabs {
+        Some(tabs) => tab_advances(runs, text, range, tabs)
+            .into_iter()
+            .fold(width, |width, (tab, advance)| {
+                width - advance_width(runs, text, tab..tab + 1) + advance
+            }),
+        None => width,
+    }
+}
+
+/// The width of the hyphen shown when a line ends at `offset`.
+fn hyphen_width(runs: &[StyleRun], offset: usize) -> f64 {
+    runs_in_range(runs, offset.saturating_sub(1)..offset)
+        .next()
+        .map(|(run, _)| run.hyphen.iter().map(|glyph| glyph.advance).sum())
+        .unwrap_or(0.0)
+}
+
+/// The positions in `range` of `text` that extra space is added after when
+/// the line is justified; these are the starts of the graphemes that the
+/// space follows.
+pub(crate) fn justification_points(
+    text: &str,
+    range: Range<usize>,
+    justification: Justification,
+) -> Vec<usize> {
+    let is_word_separator = |c| matches!(c, ' ' | '\u{a0}' | '\u{3000}');
+    let is_cjk = |c: char| {
+        matches!(
+            c.script(),
+            Script::Han | Script::Hiragana | Script::Katakana | Script::Bopomofo
+        )
+    };
+    let mut graphemes = text[range.clone()].grapheme_indices(true).peekable();
+    let mut points = Vec::new();
+    while let Some((idx, grapheme)) = graphemes.next() {
+        // there is no space after the last grapheme
+        let last = graphemes.peek().is_none();
+        let c = grapheme.chars().next().unwrap();
+        let point = match justification {
+            Justification::InterWord => is_word_separator(c),
+            Justification::InterCharacter => !last,
+            Justification::Auto => is_word_separator(c) || (is_cjk(c) && !last),
+        };
+        if point {
+            points.push(range.start + idx);
+        }
+    }
+    points
+}
+
+/// The position and advance of each tab on the `line` of `text`, when tabs
+/// advance to `tabs`.
+pub(crate) fn tab_advances(
+    runs: &[StyleRun],
+    text: &str,
+    line: Range<usize>,
+    tabs: &Tabs,
+) -> Vec<(usize, f64)> {
+    let positions = text[line.clone()]
+        .match_indices('\t')
+        .map(|(idx, _)| line.start + idx)
+        .collect::<Vec<_>>();
+    let mut advances = Vec::with_capacity(positions.len());
+    let mut x = 0.0;
+    let mut segment_start = line.start;
+    for (i, &tab) in positions.iter().enumerate() {
+        x += advance_width(runs, text, segment_start..tab);
+        segment_start = tab + 1;
+
+        // the text aligned to the stop runs up to the next tab, or to the
+        // end of the line without its trailing whitespace
+        let segment_end = match positions.get(i + 1) {
+            Some(&next) => next,
+            None => segment_start + text[segment_start..line.end].trim_end().len(),
+        };
+        let stop = tabs.stops.next_stop(x, tabs.space_width);
+        let aligned = match stop.alignment {
+            TabAlignment::Left => 0.0,
+            TabAlignment::Right => advance_width(runs, text, segment_start..segment_end),
+            TabAlignment::Center => advance_width(runs, text, segment_start..segment_end) / 2.0,
+            TabAlignment::Decimal => {
+                let point = text[segment_start..segment_end]
+                    .find('.')
+                    .map(|idx| segment_start + idx)
+                    .unwrap_or(segment_end);
+                advance_width(runs, text, segment_start..point)
+            }
+        };
+        let advance = (stop.position - x - aligned).max(0.0);
+        advances.push((tab, advance));
+        x += advance;
+    }
+    advances
+}
+
 fn add_line_metric(
     text: &str,
     start_offset: usize,
     end_offset: usize,
-    baseline: f64,
-    height: f64,
+    runs: &[StyleRun],
     y_offset: &mut f64,
     line_metrics: &mut Vec<LineMetric>,
 ) {
     let line = &text[start_offset..end_offset];
     let trailing_whitespace = count_trailing_whitespace(line);
+    let (baseline, height) = vertical_metrics(runs, start_offset..end_offset);
 
     let line_metric = LineMetric {
         start_offset,
@@ -188,9 +391,11 @@ mod test {
         width: f64,
         expected: Vec<LineMetric>,
         input: &str,
-        font: &ScaledFont,
+        font: &ResolvedFont,
     ) {
-        let line_metrics = calculate_line_metrics(input, &font, width);
+        let runs = [StyleRun::with_font(input, 0..input.len(), font)];
+        let breaks = line_breaks(input, Hyphens::Manual, None);
+        let line_metrics = calculate_line_metrics(input, &runs, &breaks, width, None);
 
         for (i, (metric, exp)) in line_metrics.iter().zip(expected).enumerate() {
             println!("calculated: {:?}\nexpected: {:?}", metric, exp);
@@ -216,19 +421,21 @@ mod test {
         let width = 50.0;
 
         let font = CairoFont::new(FontFamily::SANS_SERIF).resolve_simple(12.0);
-        let line_metrics = calculate_line_metrics(input, &font, width);
+        let runs = [StyleRun::with_font(input, 0..input.len(), &font)];
+        let breaks = line_breaks(input, Hyphens::Manual, None);
+        let line_metrics = calculate_line_metrics(input, &runs, &breaks, width, None);
 
         // Some print debugging, in case font size/width needs to be changed in future because of
         // brittle tests
         println!(
             "{}: \"piet text \"",
-            font.text_extents("piet text ").x_advance
+            font.scaled.text_extents("piet text ").x_advance
         );
         for lm in &line_metrics {
             let line_text = &input[lm.start_offset..lm.end_offset];
             println!(
                 "{}: {:?}",
-                font.text_extents(line_text).x_advance,
+                font.scaled.text_extents(line_text).x_advance,
                 line_text
             );
         }
@@ -246,19 +453,21 @@ mod test {
         let width = 50.0;
 
         let font = CairoFont::new(FontFamily::SANS_SERIF).resolve_simple(14.0);
-        let line_metrics = calculate_line_metrics(input, &font, width);
+        let runs = [StyleRun::with_font(input, 0..input.len(), &font)];
+        let breaks = line_breaks(input, Hyphens::Manual, None);
+        let line_metrics = calculate_line_metrics(input, &runs, &breaks, width, None);
 
         // Some print debugging, in case font size/width needs to be changed in future because of
         // brittle tests
         println!(
             "{}: \"piet text \"",
-            font.text_extents("piet text ").x_advance
+            font.scaled.text_extents("piet text ").x_advance
         );
         for lm in &line_metrics {
             let line_text = &input[lm.range()];
             println!(
                 "{}: {:?}",
-                font.text_extents(line_text).x_advance,
+                font.scaled.text_extents(line_text).x_advance,
                 line_text
             );
         }
@@ -274,17 +483,22 @@ mod test {
         let width = 10.0;
 
         let font = CairoFont::new(FontFamily::SANS_SERIF).resolve_simple(12.0);
-        let line_metrics = calculate_line_metrics(input, &font, width);
+        let runs = [StyleRun::with_font(input, 0..input.len(), &font)];
+        let breaks = line_breaks(input, Hyphens::Manual, None);
+        let line_metrics = calculate_line_metrics(input, &runs, &breaks, width, None);
 
         // Some print debugging, in case font size/width needs to be changed in future because of
         // brittle tests
-        println!("{}: \"piet\n\"", font.text_extents("piet\n").x_advance);
-        println!("{}: \"text\"", font.text_extents("text").x_advance);
+        println!(
+            "{}: \"piet\n\"",
+            font.scaled.text_extents("piet\n").x_advance
+        );
+        println!("{}: \"text\"", font.scaled.text_extents("text").x_advance);
         for lm in &line_metrics {
             let line_text = &input[lm.range()];
             println!(
                 "{}: {:?}",
-                font.text_extents(line_text).x_advance,
+                font.scaled.text_extents(line_text).x_advance,
                 line_text
             );
         }
@@ -397,15 +611,15 @@ mod test {
 
         println!(
             "piet text width: {}",
-            font.text_extents("piet text").x_advance
+            font.scaled.text_extents("piet text").x_advance
         ); // 55
         println!(
             "most best width: {}",
-            font.text_extents("most best").x_advance
+            font.scaled.text_extents("most best").x_advance
         ); // 65
         println!(
             "piet text most best width: {}",
-            font.text_extents("piet text most best").x_advance
+            font.scaled.text_extents("piet text most best").x_advance
         ); // 124
 
         test_metrics_with_width(width_small, expected_small, input, &font);
@@ -475,4 +689,37 @@ mod test {
         assert_eq!(count_trailing_whitespace(" 2  "), 2);
         assert_eq!(count_trailing_whitespace(" 3  \n"), 3);
     }
+
+    #[test]
+    fn test_line_breaks_hyphens() {
+        let offsets = |breaks: Vec<Break>| {
+            breaks
+                .into_iter()
+                .map(|brk| (brk.offset, brk.hyphen))
+                .collect::<Vec<_>>()
+        };
+        let input = "co\u{ad}op extensive";
+        let none = line_breaks(input, Hyphens::None, None);
+        assert_eq!(offsets(none), vec![(7, false), (16, false)]);
+        let manual = line_breaks(input, Hyphens::Manual, hyphenation_lang("en-US"));
+        assert_eq!(offsets(manual), vec![(4, true), (7, false), (16, false)]);
+        let auto = line_breaks(input, Hyphens::Auto, hyphenation_lang("en-US"));
+        assert_eq!(
+            offsets(auto),
+            vec![(4, true), (7, false), (9, true), (12, true), (16, false)]
+        );
+        assert_eq!(hyphenation_lang("tlh"), None);
+    }
+
+    #[test]
+    fn test_justification_points() {
+        let input = "ab cd\u{65e5}\u{672c}\u{8a9e}";
+        let points = |justification| justification_points(input, 0..input.len(), justification);
+        assert_eq!(points(Justification::InterWord), vec![2]);
+        assert_eq!(points(Justification::Auto), vec![2, 5, 8]);
+        assert_eq!(
+            points(Justification::InterCharacter),
+            vec![0, 1, 2, 3, 4, 5, 8]
+        );
+    }
 }
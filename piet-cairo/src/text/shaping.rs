@@ -0,0 +1,334 @@
+//! Shaping text into positioned glyphs.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use cairo::ScaledFont;
+use piet::util;
+use rustybuzz::ttf_parser::Tag;
+use rustybuzz::{Direction, Feature, UnicodeBuffer, Variation};
+use unicode_script::{Script, UnicodeScript};
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::attributes::TextStyle;
+use super::fonts::FontData;
+
+/// A glyph produced by shaping.
+#[derive(Clone, Debug)]
+pub(crate) struct ShapedGlyph {
+    /// The glyph's index in the font.
+    pub(crate) id: u32,
+    /// The start of the cluster this glyph belongs to, as an offset into the
+    /// layout's text.
+    pub(crate) cluster: usize,
+    pub(crate) advance: f64,
+    /// The offset of the glyph from its pen position; positive `y` is down.
+    pub(crate) x_offset: f64,
+    pub(crate) y_offset: f64,
+}
+
+/// A range of text that was shaped as a unit, such as a ligature, or a base
+/// character with its combining marks.
+#[derive(Clone, Debug)]
+pub(crate) struct Cluster {
+    pub(crate) range: Range<usize>,
+    /// The total advance of the clusters before this one, in logical order.
+    pub(crate) x: f64,
+    pub(crate) advance: f64,
+}
+
+/// The glyphs for a range of text with a single font, script and direction.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Shaped {
+    /// The glyphs, in visual order.
+    pub(crate) glyphs: Vec<ShapedGlyph>,
+    /// The clusters, in logical order.
+    pub(crate) clusters: Vec<Cluster>,
+}
+
+/// Split `range` of `text` into runs with a single script.
+///
+/// Characters that don't belong to a particular script, such as spaces,
+/// punctuation and combining marks, join the run before them (or the run
+/// after them, if they start the range).
+pub(crate) fn script_runs(text: &str, range: Range<usize>) -> Vec<Range<usize>> {
+    let mut runs = Vec::new();
+    let mut run_start = range.start;
+    let mut run_script = None;
+    for (idx, c) in text[range.clone()].char_indices() {
+        let script = c.script();
+        if matches!(script, Script::Common | Script::Inherited | Script::Unknown) {
+            continue;
+        }
+        match run_script {
+            Some(prev) if prev != script => {
+                let idx = range.start + idx;
+                runs.push(run_start..idx);
+                run_start = idx;
+            }
+            _ => (),
+        }
+        run_script = Some(script);
+    }
+    runs.push(run_start..range.end);
+    runs
+}
+
+/// Split `range` of `text` into runs that are drawn upright in vertical
+/// text, and runs that are rotated; the flag is `true` for upright runs.
+///
+/// Combining marks join the run of the character before them.
+pub(crate) fn orientation_runs(text: &str, range: Range<usize>) -> Vec<(Range<usize>, bool)> {
+    let mut runs = Vec::new();
+    let mut run_start = range.start;
+    let mut run_upright = None;
+    for (idx, c) in text[range.clone()].char_indices() {
+        if c.script() == Script::Inherited {
+            continue;
+        }
+        let upright = is_upright(c);
+        match run_upright {
+            Some(prev) if prev != upright => {
+                let idx = range.start + idx;
+                runs.push((run_start..idx, prev));
+                run_start = idx;
+            }
+            _ => (),
+        }
+        run_upright = Some(upright);
+    }
+    runs.push((run_start..range.end, run_upright.unwrap_or(false)));
+    runs
+}
+
+/// Whether `c` is drawn upright in vertical text.
+///
+/// This approximates the `U` and `Tu` values of the `Vertical_Orientation`
+/// property from UAX #50: CJK, kana, Hangul and Yi, and the symbols that
+/// are used with them.
+fn is_upright(c: char) -> bool {
+    matches!(
+        c as u32,
+        0xA7 | 0xA9
+            | 0xAE
+            | 0xB1
+            | 0xBC..=0xBE
+            | 0xD7
+            | 0xF7
+            | 0x1100..=0x11FF
+            | 0x2460..=0x24FF
+            | 0x25A0..=0x27BF
+            | 0x2E80..=0xA4CF
+            | 0xA960..=0xA97F
+            | 0xAC00..=0xD7FF
+            | 0xF900..=0xFAFF
+            | 0xFE10..=0xFE1F
+            | 0xFE30..=0xFE4F
+            | 0xFF01..=0xFF60
+            | 0xFFE0..=0xFFE6
+            | 0x1F000..=0x1FAFF
+            | 0x20000..=0x3FFFD
+    )
+}
+
+/// Shape `range` of `text`, which should have a single script, direction and
+/// orientation.
+///
+/// Text is shaped using the font's data if it is available; otherwise glyphs
+/// are looked up by cairo, without any shaping. The style's letter and word
+/// spacing are added to the advances of the glyphs.
+///
+/// Text shaped top to bottom is upright in a vertical line; its advances are
+/// down the line, and its offsets are from a pen position in the middle of it.
+pub(crate) fn shape(
+    text: &str,
+    range: Range<usize>,
+    direction: Direction,
+    font: &ScaledFont,
+    data: Option<&FontData>,
+    style: &TextStyle,
+) -> Shaped {
+    if range.is_empty() {
+        return Shaped::default();
+    }
+
+    let face = data.and_then(FontData::face);
+    let mut glyphs = match face {
+        Some(mut face) => {
+            let variations = style
+                .font_variations
+                .iter()
+                .map(|variation| Variation {
+                    tag: Tag::from_bytes(&variation.tag()),
+                    value: variation.value() as f32,
+                })
+                .collect::<Vec<_>>();
+            face.set_variations(&variations);
+            let mut buffer = UnicodeBuffer::new();
+            buffer.push_str(&text[range.clone()]);
+            buffer.set_direction(direction);
+            buffer.guess_segment_properties();
+            let scale = style.display_size() / f64::from(face.units_per_em());
+            // as in CSS, optional ligatures are disabled when letters are
+            // spaced; features from the style come later, so they win
+            let mut features = if style.letter_spacing != 0.0 {
+                vec![
+                    Feature::new(Tag::from_bytes(b"liga"), 0, ..),
+                    Feature::new(Tag::from_bytes(b"clig"), 0, ..),
+                ]
+            } else {
+                Vec::new()
+            };
+            features.extend(style.font_features.iter().map(|feature| {
+                let tag = Tag::from_bytes(&feature.tag());
+                Feature::new(tag, feature.value(), ..)
+            }));
+            let output = rustybuzz::shape(&face, &features, buffer);
+            let vertical = direction == Direction::TopToBottom;
+            output
+                .glyph_infos()
+                .iter()
+                .zip(output.glyph_positions())
+                .map(|(info, pos)| ShapedGlyph {
+                    id: info.glyph_id,
+                    cluster: range.start + info.cluster as usize,
+                    advance: if vertical {
+                        -f64::from(pos.y_advance) * scale
+                    } else {
+                        f64::from(pos.x_advance) * scale
+                    },
+                    x_offset: f64::from(pos.x_offset) * scale,
+                    y_offset: -f64::from(pos.y_offset) * scale,
+                })
+                .collect()
+        }
+        None => {
+            let mut glyphs = cairo_glyphs(text, range.clone(), font);
+            match direction {
+                Direction::RightToLeft => glyphs.reverse(),
+                // without vertical metrics, each glyph is centered in a
+                // cell as tall as the font
+                Direction::TopToBottom => {
+                    let extents = font.extents();
+                    for glyph in &mut glyphs {
+                        glyph.x_offset = -glyph.advance / 2.0;
+                        glyph.y_offset = extents.ascent;
+                        glyph.advance = extents.ascent + extents.descent;
+                    }
+                }
+                _ => (),
+            }
+            glyphs
+        }
+    };
+
+    add_spacing(text, &mut glyphs, range.end, style);
+    let clusters = clusters(&glyphs, range);
+    Shaped { glyphs, clusters }
+}
+
+/// Add letter and word spacing to the advance of the last glyph of each
+/// cluster, in visual order.
+///
+/// Letter spacing is added once for each grapheme in a cluster, and word
+/// spacing once for each word separator.
+fn add_spacing(text: &str, glyphs: &mut [ShapedGlyph], end: usize, style: &TextStyle) {
+    if style.letter_spacing == 0.0 && style.word_spacing == 0.0 {
+        return;
+    }
+    let mut last_glyphs = BTreeMap::new();
+    for (idx, glyph) in glyphs.iter().enumerate() {
+        last_glyphs.insert(glyph.cluster, idx);
+    }
+    let starts = last_glyphs.keys().copied().collect::<Vec<_>>();
+    for (i, start) in starts.iter().enumerate() {
+        let cluster_end = starts.get(i + 1).copied().unwrap_or(end);
+        let cluster_text = &text[*start..cluster_end];
+        let graphemes = cluster_text.graphemes(true).count();
+        let separators = cluster_text
+            .chars()
+            .filter(|c| util::is_word_separator(*c))
+            .count();
+        let spacing =
+            style.letter_spacing * graphemes as f64 + style.word_spacing * separators as f64;
+        glyphs[last_glyphs[start]].advance += spacing;
+    }
+}
+
+/// Map text to glyphs with cairo; this doesn't apply kerning or ligatures.
+fn cairo_glyphs(text: &str, range: Range<usize>, font: &ScaledFont) -> Vec<ShapedGlyph> {
+    let (glyphs, clusters) = font.text_to_glyphs(0.0, 0.0, &text[range.clone()]);
+    let mut result = Vec::with_capacity(glyphs.len());
+    let mut glyphs = glyphs.iter();
+    let mut cluster_start = range.start;
+    for cluster in clusters {
+        for glyph in glyphs.by_ref().take(cluster.num_glyphs as usize) {
+            result.push(ShapedGlyph {
+                id: glyph.index as u32,
+                cluster: cluster_start,
+                advance: font.glyph_extents(&[*glyph]).x_advance,
+                x_offset: 0.0,
+                y_offset: 0.0,
+            });
+        }
+        cluster_start += cluster.num_bytes as usize;
+    }
+    result
+}
+
+fn clusters(glyphs: &[ShapedGlyph], range: Range<usize>) -> Vec<Cluster> {
+    let mut advances = BTreeMap::new();
+    for glyph in glyphs {
+        *advances.entry(glyph.cluster).or_insert(0.0) += glyph.advance;
+    }
+
+    let mut clusters: Vec<Cluster> = Vec::with_capacity(advances.len());
+    let mut x = 0.0;
+    for (start, advance) in advances {
+        if let Some(prev) = clusters.last_mut() {
+            prev.range.end = start;
+        }
+        clusters.push(Cluster {
+            range: start..range.end,
+            x,
+            advance,
+        });
+        x += advance;
+    }
+    clusters
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn split_scripts() {
+        let text = "abc שלום, def";
+        assert_eq!(
+            script_runs(text, 0..text.len()),
+            vec![0..4, 4..14, 14..text.len()]
+        );
+        // leading punctuation joins the first run with a script
+        let text = "(שלום)";
+        assert_eq!(script_runs(text, 0..text.len()), vec![0..text.len()]);
+        assert_eq!(script_runs("", 0..0), vec![0..0]);
+    }
+
+    #[test]
+    fn split_orientations() {
+        let text = "縦書き (text) の例";
+        assert_eq!(
+            orientation_runs(text, 0..text.len()),
+            vec![(0..9, true), (9..17, false), (17..text.len(), true)]
+        );
+        // combining marks stay with their base
+        let text = "ab\u{301}漢";
+        assert_eq!(
+            orientation_runs(text, 0..text.len()),
+            vec![(0..4, false), (4..text.len(), true)]
+        );
+        assert!(is_upright('あ') && is_upright('한') && is_upright('、'));
+        assert!(!is_upright('a') && !is_upright('1') && !is_upright('('));
+    }
+}
@@ -64,3 +64,31 @@ fn empty_image_area_should_not_panic() {
         Ok(())
     })
 }
+
+#[test]
+fn high_quality_minification_averages_pixels() {
+    // A one-pixel checkerboard should come out as a flat mid gray when drawn
+    // much smaller, rather than picking up whichever pixels happen to be sampled.
+    let size = 64;
+    let pixels = (0..size * size)
+        .map(|i| if (i % size + i / size) % 2 == 0 { 0 } else { 255 })
+        .collect::<Vec<u8>>();
+    let mut device = Device::new().unwrap();
+    let mut target = device.bitmap_target(5, 5, 1.0).unwrap();
+    {
+        let mut ctx = target.render_context();
+        let image = ctx
+            .make_image(size, size, &pixels, ImageFormat::Grayscale)
+            .unwrap();
+        ctx.draw_image(
+            &image,
+            Rect::new(0., 0., 5., 5.),
+            InterpolationMode::HighQuality,
+        );
+        ctx.finish().unwrap();
+    }
+    let buf = target.to_image_buf(ImageFormat::RgbaPremul).unwrap();
+    for px in buf.raw_pixels().chunks(4) {
+        assert!(px[0] > 96 && px[0] < 160, "pixel {:?} is not gray", px);
+    }
+}
@@ -1,12 +1,13 @@
 //! Wrappers around CF/CT types, with nice interfaces.
 
+use std::borrow::Cow;
 use std::ffi::c_void;
 use std::rc::Rc;
 
 use core_foundation::{
     array::{CFArray, CFArrayRef, CFIndex},
     attributed_string::CFMutableAttributedString,
-    base::{CFTypeID, TCFType},
+    base::{CFType, CFTypeID, TCFType},
     declare_TCFType,
     dictionary::{CFDictionary, CFDictionaryRef},
     impl_TCFType,
@@ -19,7 +20,7 @@ use core_graphics::{
     color::CGColor,
     context::CGContextRef,
     data_provider::CGDataProvider,
-    font::CGFont,
+    font::{CGFont, CGGlyph},
     geometry::{CGAffineTransform, CGPoint, CGRect, CGSize},
     path::CGPathRef,
 };
@@ -33,12 +34,16 @@ use core_text::{
     frame::CTFrame,
     framesetter::CTFramesetter,
     line::{CTLine, CTLineRef, TypographicBounds},
+    run::{CTRun, CTRunRef},
     string_attributes,
 };
-use foreign_types::ForeignType;
+use foreign_types::{ForeignType, ForeignTypeRef};
 
 use piet::kurbo::{Affine, Rect};
-use piet::{util, Color, FontFamily, FontFamilyInner, TextAlignment};
+use piet::{
+    util, Color, FontFamily, FontFamilyInner, FontFeature, LineHeight, TextAlignment,
+    UnderlineStyle,
+};
 
 #[derive(Clone)]
 pub(crate) struct AttributedString {
@@ -57,6 +62,10 @@ pub(crate) struct Frame {
 #[derive(Debug, Clone)]
 pub(crate) struct Line(CTLine);
 
+/// A run of glyphs in a line that share their attributes.
+#[derive(Debug, Clone)]
+pub(crate) struct Run(CTRun);
+
 #[derive(Debug, Clone)]
 pub(crate) struct FontCollection(CTFontCollection);
 
@@ -79,6 +88,9 @@ enum CTParagraphStyleSpecifier {
     //TabStops = 4,
     //TabInterval = 5,
     //LineBreakMode = 6,
+    LineHeightMultiple = 7,
+    MaximumLineHeight = 8,
+    MinimumLineHeight = 9,
     // there are many more of these
 }
 
@@ -120,6 +132,15 @@ impl CTParagraphStyleSetting {
             value_size: std::mem::size_of::<CTTextAlignment>(),
         }
     }
+
+    /// A setting with a `CGFloat` value; `value` must outlive the setting.
+    fn float(spec: CTParagraphStyleSpecifier, value: &CGFloat) -> Self {
+        CTParagraphStyleSetting {
+            spec,
+            value: value as *const CGFloat as *const c_void,
+            value_size: std::mem::size_of::<CGFloat>(),
+        }
+    }
 }
 
 impl AttributedString {
@@ -132,11 +153,36 @@ impl AttributedString {
         AttributedString { inner, rtl }
     }
 
-    pub(crate) fn set_alignment(&mut self, alignment: TextAlignment) {
-        let alignment = CTParagraphStyleSetting::alignment(alignment, self.rtl);
-        let settings = [alignment];
+    pub(crate) fn set_paragraph_style(
+        &mut self,
+        alignment: TextAlignment,
+        line_height: LineHeight,
+    ) {
+        let mut settings = vec![CTParagraphStyleSetting::alignment(alignment, self.rtl)];
+        let (height, multiple): (CGFloat, CGFloat) = match line_height {
+            LineHeight::Normal => (0.0, 0.0),
+            LineHeight::Absolute(height) => (height.max(0.0), 0.0),
+            LineHeight::Relative(multiple) => (0.0, multiple.max(0.0)),
+        };
+        match line_height {
+            LineHeight::Normal => (),
+            LineHeight::Absolute(_) => {
+                settings.push(CTParagraphStyleSetting::float(
+                    CTParagraphStyleSpecifier::MinimumLineHeight,
+                    &height,
+                ));
+                settings.push(CTParagraphStyleSetting::float(
+                    CTParagraphStyleSpecifier::MaximumLineHeight,
+                    &height,
+                ));
+            }
+            LineHeight::Relative(_) => settings.push(CTParagraphStyleSetting::float(
+                CTParagraphStyleSpecifier::LineHeightMultiple,
+                &multiple,
+            )),
+        }
         unsafe {
-            let style = CTParagraphStyleCreate(settings.as_ptr(), 1);
+            let style = CTParagraphStyleCreate(settings.as_ptr(), settings.len());
             let style = CTParagraphStyle::wrap_under_create_rule(style);
             self.inner.set_attribute(
                 self.range(),
@@ -153,15 +199,21 @@ impl AttributedString {
         }
     }
 
+    /// Underline `range` with the given style, or remove its underline.
     #[allow(non_upper_case_globals)]
-    pub(crate) fn set_underline(&mut self, range: CFRange, underline: bool) {
+    pub(crate) fn set_underline(&mut self, range: CFRange, style: Option<UnderlineStyle>) {
         const kCTUnderlineStyleNone: i32 = 0x00;
         const kCTUnderlineStyleSingle: i32 = 0x01;
-
-        let value = if underline {
-            kCTUnderlineStyleSingle
-        } else {
-            kCTUnderlineStyleNone
+        const kCTUnderlineStyleDouble: i32 = 0x09;
+        const kCTUnderlinePatternDot: i32 = 0x0100;
+
+        let value = match style {
+            None => kCTUnderlineStyleNone,
+            Some(UnderlineStyle::Single) => kCTUnderlineStyleSingle,
+            Some(UnderlineStyle::Double) => kCTUnderlineStyleDouble,
+            Some(UnderlineStyle::Dotted) => kCTUnderlineStyleSingle | kCTUnderlinePatternDot,
+            // CoreText has no wavy underline
+            Some(UnderlineStyle::Wavy) => kCTUnderlineStyleSingle,
         };
         unsafe {
             self.inner.set_attribute(
@@ -172,6 +224,40 @@ impl AttributedString {
         }
     }
 
+    pub(crate) fn set_underline_color(&mut self, range: CFRange, color: &Color) {
+        let (r, g, b, a) = color.as_rgba();
+        let color = CGColor::rgb(r, g, b, a);
+        unsafe {
+            self.inner.set_attribute(
+                range,
+                string_attributes::kCTUnderlineColorAttributeName,
+                &color.as_CFType(),
+            )
+        }
+    }
+
+    /// Add `spacing` after each character in `range`.
+    pub(crate) fn set_letter_spacing(&mut self, range: CFRange, spacing: f64) {
+        unsafe {
+            self.inner.set_attribute(
+                range,
+                string_attributes::kCTKernAttributeName,
+                &CFNumber::from(spacing).as_CFType(),
+            )
+        }
+    }
+
+    /// Raise the text in `range` by `offset` points.
+    pub(crate) fn set_baseline_offset(&mut self, range: CFRange, offset: f64) {
+        unsafe {
+            self.inner.set_attribute(
+                range,
+                kCTBaselineOffsetAttributeName,
+                &CFNumber::from(offset).as_CFType(),
+            )
+        }
+    }
+
     pub(crate) fn set_fg_color(&mut self, range: CFRange, color: &Color) {
         let (r, g, b, a) = color.as_rgba();
         let color = CGColor::rgb(r, g, b, a);
@@ -268,6 +354,69 @@ impl Line {
     pub(crate) fn get_offset_for_string_index(&self, index: CFIndex) -> CGFloat {
         self.0.get_string_offset_for_string_index(index)
     }
+
+    pub(crate) fn runs(&self) -> Vec<Run> {
+        self.0
+            .glyph_runs()
+            .iter()
+            .map(|run| Run(run.clone()))
+            .collect()
+    }
+}
+
+impl Run {
+    /// The font used for this run's glyphs.
+    pub(crate) fn font(&self) -> Option<CTFont> {
+        self.0
+            .attributes()?
+            .find(unsafe { string_attributes::kCTFontAttributeName })
+            .and_then(|font| font.downcast::<CTFont>())
+    }
+
+    /// The color of this run's glyphs, if it has one.
+    pub(crate) fn fg_color(&self) -> Option<CGColor> {
+        self.0
+            .attributes()?
+            .find(unsafe { string_attributes::kCTForegroundColorAttributeName })
+            .and_then(|color| color.downcast::<CGColor>())
+    }
+
+    /// The range of the attributed string that this run covers.
+    pub(crate) fn string_range(&self) -> CFRange {
+        unsafe { CTRunGetStringRange(self.0.as_concrete_TypeRef()) }
+    }
+
+    pub(crate) fn is_rtl(&self) -> bool {
+        unsafe { CTRunGetStatus(self.0.as_concrete_TypeRef()) & K_CT_RUN_STATUS_RIGHT_TO_LEFT != 0 }
+    }
+
+    pub(crate) fn glyphs(&self) -> Cow<[CGGlyph]> {
+        self.0.glyphs()
+    }
+
+    /// The position of each glyph, relative to the line's origin.
+    pub(crate) fn positions(&self) -> Cow<[CGPoint]> {
+        self.0.positions()
+    }
+
+    /// The index in the attributed string of the start of each glyph's cluster.
+    pub(crate) fn string_indices(&self) -> Cow<[CFIndex]> {
+        self.0.string_indices()
+    }
+
+    pub(crate) fn advances(&self) -> Vec<CGSize> {
+        let count = self.0.glyph_count() as usize;
+        let mut advances = vec![CGSize::new(0.0, 0.0); count];
+        unsafe {
+            // a range of length 0 copies to the end of the run
+            CTRunGetAdvances(
+                self.0.as_concrete_TypeRef(),
+                CFRange::init(0, 0),
+                advances.as_mut_ptr(),
+            );
+        }
+        advances
+    }
 }
 
 /// The apple system fonts can resolve to different concrete families at
@@ -275,10 +424,23 @@ impl Line {
 pub(crate) fn ct_family_name(family: &FontFamily, size: f64) -> CFString {
     match &family.inner() {
         FontFamilyInner::Named(name) => CFString::new(name),
+        FontFamilyInner::Stack(_) => ct_family_name(first_available_family(family), size),
         other => system_font_family_name(other, size),
     }
 }
 
+/// The first family in a stack that is either generic or installed, or the
+/// first family if there are none.
+fn first_available_family(family: &FontFamily) -> &FontFamily {
+    let families = family.families();
+    families
+        .iter()
+        .find(|family| {
+            family.is_generic() || font_collection::create_for_family(family.name()).is_some()
+        })
+        .unwrap_or(&families[0])
+}
+
 /// Create a generic system font.
 fn system_font_family_name(family: &FontFamilyInner, size: f64) -> CFString {
     let font = system_font_impl(family, size).unwrap_or_else(create_font_comma_never_fail_period);
@@ -324,6 +486,27 @@ fn create_font_comma_never_fail_period() -> CTFont {
     font::new_from_descriptor(&descriptor, 0.0)
 }
 
+/// The value of a font descriptor's feature settings attribute that applies
+/// these OpenType features.
+pub(crate) fn feature_settings(
+    features: &[FontFeature],
+) -> CFArray<CFDictionary<CFString, CFType>> {
+    let settings = features
+        .iter()
+        .map(|feature| unsafe {
+            let tag_key = CFString::wrap_under_get_rule(kCTFontOpenTypeFeatureTag);
+            let value_key = CFString::wrap_under_get_rule(kCTFontOpenTypeFeatureValue);
+            let tag = CFString::new(&String::from_utf8_lossy(&feature.tag()));
+            let value = CFNumber::from(feature.value() as i32);
+            CFDictionary::from_CFType_pairs(&[
+                (tag_key, tag.as_CFType()),
+                (value_key, value.as_CFType()),
+            ])
+        })
+        .collect::<Vec<_>>();
+    CFArray::from_CFTypes(&settings)
+}
+
 pub(crate) fn add_font(font_data: &[u8]) -> Result<String, ()> {
     unsafe {
         let data = CGDataProvider::from_slice(font_data);
@@ -367,6 +550,25 @@ impl FontCollection {
 
 // the version of this in the coretext crate doesn't let you supply an affine
 #[allow(clippy::many_single_char_names)]
+/// Draw glyphs of `font` at positions in the context's text space.
+pub(crate) fn draw_glyphs(
+    font: &CTFont,
+    glyphs: &[CGGlyph],
+    positions: &[CGPoint],
+    ctx: &mut CGContextRef,
+) {
+    assert_eq!(glyphs.len(), positions.len());
+    unsafe {
+        CTFontDrawGlyphs(
+            font.as_concrete_TypeRef(),
+            glyphs.as_ptr(),
+            positions.as_ptr(),
+            glyphs.len(),
+            ctx.as_ptr(),
+        )
+    }
+}
+
 pub(crate) fn make_font(desc: &CTFontDescriptor, pt_size: f64, affine: Affine) -> CTFont {
     let [a, b, c, d, e, f] = affine.as_coeffs();
     let affine = CGAffineTransform::new(a, b, c, d, e, f);
@@ -382,11 +584,16 @@ pub(crate) fn make_font(desc: &CTFontDescriptor, pt_size: f64, affine: Affine) -
     }
 }
 
+const K_CT_RUN_STATUS_RIGHT_TO_LEFT: u32 = 1;
+
 #[link(name = "CoreText", kind = "framework")]
 extern "C" {
     static kCTFontFamilyNameKey: CFStringRef;
 
     pub static kCTFontVariationAxisIdentifierKey: CFStringRef;
+    static kCTBaselineOffsetAttributeName: CFStringRef;
+    static kCTFontOpenTypeFeatureTag: CFStringRef;
+    static kCTFontOpenTypeFeatureValue: CFStringRef;
     //static kCTFontVariationAxisMinimumValueKey: CFStringRef;
     //static kCTFontVariationAxisMaximumValueKey: CFStringRef;
     //static kCTFontVariationAxisDefaultValueKey: CFStringRef;
@@ -404,6 +611,16 @@ extern "C" {
     ) -> CTParagraphStyleRef;
     fn CTLineGetImageBounds(line: CTLineRef, ctx: *mut c_void) -> CGRect;
     fn CTLineGetTrailingWhitespaceWidth(line: CTLineRef) -> f64;
+    fn CTRunGetStringRange(run: CTRunRef) -> CFRange;
+    fn CTRunGetStatus(run: CTRunRef) -> u32;
+    fn CTRunGetAdvances(run: CTRunRef, range: CFRange, buffer: *mut CGSize);
+    fn CTFontDrawGlyphs(
+        font: CTFontRef,
+        glyphs: *const CGGlyph,
+        positions: *const CGPoint,
+        count: usize,
+        context: core_graphics::sys::CGContextRef,
+    );
     fn CTFontCollectionCreateMatchingFontDescriptorsForFamily(
         collection: CTFontCollectionRef,
         family: CFStringRef,
@@ -12,19 +12,21 @@ use std::sync::Arc;
 use core_graphics::base::{
     kCGImageAlphaLast, kCGImageAlphaPremultipliedLast, kCGRenderingIntentDefault, CGFloat,
 };
+use core_graphics::color::CGColor;
 use core_graphics::color_space::CGColorSpace;
 use core_graphics::context::{CGContextRef, CGInterpolationQuality, CGLineCap, CGLineJoin};
 use core_graphics::data_provider::CGDataProvider;
+use core_graphics::font::CGGlyph;
 use core_graphics::geometry::{CGAffineTransform, CGPoint, CGRect, CGSize};
 use core_graphics::gradient::CGGradientDrawingOptions;
 use core_graphics::image::CGImage;
 
 use piet::kurbo::{Affine, PathEl, Point, QuadBez, Rect, Shape, Size};
 
-use piet::util::unpremul;
+use piet::util::{self, unpremul};
 use piet::{
     Color, Error, FixedGradient, ImageFormat, InterpolationMode, IntoBrush, LineCap, LineJoin,
-    RenderContext, RoundInto, StrokeStyle,
+    RenderContext, RoundInto, StrokeStyle, TextLayout, TextPathOptions,
 };
 
 pub use crate::text::{CoreGraphicsText, CoreGraphicsTextLayout, CoreGraphicsTextLayoutBuilder};
@@ -224,6 +226,11 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
 
     fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
         let pos = pos.into();
+        for (range, color) in layout.backgrounds.iter() {
+            for rect in layout.rects_for_range(range.clone()) {
+                self.fill(rect + pos.to_vec2(), color);
+            }
+        }
         self.ctx.save();
         // inverted coordinate system; text is drawn from bottom left corner,
         // and (0, 0) in context is also bottom left.
@@ -233,6 +240,51 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
         self.ctx.restore();
     }
 
+    fn draw_text_on_path(
+        &mut self,
+        layout: &Self::TextLayout,
+        path: impl Shape,
+        options: &TextPathOptions,
+    ) {
+        let baseline = match layout.line_metric(0) {
+            Some(lm) => lm.y_offset + lm.baseline,
+            None => return,
+        };
+        let (glyph_runs, fonts): (Vec<_>, Vec<_>) = layout
+            .glyph_runs_with_fonts()
+            .into_iter()
+            .map(|(run, font, color)| (run, (font, color)))
+            .unzip();
+        let transforms = util::glyph_run_transforms(path, &glyph_runs, baseline, options);
+        let black = CGColor::rgb(0.0, 0.0, 0.0, 1.0);
+        for ((glyph_run, (font, color)), transforms) in
+            glyph_runs.iter().zip(&fonts).zip(transforms)
+        {
+            let glyphs = glyph_run.glyphs.iter().zip(glyph_run.glyph_positions());
+            for ((glyph, pos), transform) in glyphs.zip(transforms) {
+                let transform = match transform {
+                    Some(transform) => transform,
+                    None => continue,
+                };
+                self.ctx.save();
+                self.ctx.concat_ctm(to_cgaffine(transform));
+                // as in draw_text, glyphs are drawn in an inverted coordinate system
+                self.ctx.translate(pos.x, pos.y);
+                self.ctx.scale(1.0, -1.0);
+                self.ctx
+                    .set_text_matrix(&CGAffineTransform::new(1.0, 0.0, 0.0, 1.0, 0.0, 0.0));
+                self.ctx.set_fill_color(color.as_ref().unwrap_or(&black));
+                ct_helpers::draw_glyphs(
+                    font,
+                    &[glyph.id as CGGlyph],
+                    &[CGPoint::new(0.0, 0.0)],
+                    self.ctx,
+                );
+                self.ctx.restore();
+            }
+        }
+    }
+
     fn save(&mut self) -> Result<(), Error> {
         self.ctx.save();
         let state = self.transform_stack.last().copied().unwrap_or_default();
@@ -327,6 +379,8 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
                 CGInterpolationQuality::CGInterpolationQualityNone
             }
             InterpolationMode::Bilinear => CGInterpolationQuality::CGInterpolationQualityDefault,
+            InterpolationMode::Bicubic => CGInterpolationQuality::CGInterpolationQualityMedium,
+            InterpolationMode::HighQuality => CGInterpolationQuality::CGInterpolationQualityHigh,
         };
         self.ctx.set_interpolation_quality(quality);
         let rect = rect.into();
@@ -1,5 +1,6 @@
 //! Text related stuff for the coregraphics backend
 
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt;
 use std::ops::{Range, RangeBounds};
@@ -12,20 +13,25 @@ use core_foundation::number::CFNumber;
 use core_foundation::string::CFString;
 use core_foundation_sys::base::CFRange;
 use core_graphics::base::CGFloat;
+use core_graphics::color::CGColor;
 use core_graphics::context::CGContextRef;
-use core_graphics::geometry::{CGPoint, CGRect, CGSize};
-use core_graphics::path::CGPath;
+use core_graphics::geometry::{CGAffineTransform, CGPoint, CGRect, CGSize};
+use core_graphics::path::{CGPath, CGPathElementRef, CGPathElementType};
 use core_text::{
     font,
     font::CTFont,
-    font_descriptor::{self, SymbolicTraitAccessors},
-    string_attributes,
+    font_collection,
+    font_descriptor::{self, SymbolicTraitAccessors, TraitAccessors},
+    font_manager, string_attributes,
 };
 
-use piet::kurbo::{Affine, Point, Rect, Size};
+use piet::kurbo::{Affine, BezPath, Point, Rect, Size, Vec2};
 use piet::{
-    util, Error, FontFamily, FontStyle, FontWeight, HitTestPoint, HitTestPosition, LineMetric,
-    Text, TextAlignment, TextAttribute, TextLayout, TextLayoutBuilder, TextStorage,
+    util, BaselineShift, Color, Error, FontFaceInfo, FontFamily, FontFeature, FontMetrics,
+    FontStretch, FontStyle, FontVariation, FontWeight, Glyph, GlyphRun, HitTestPoint,
+    HitTestPosition, Hyphens, Justification, LineHeight, LineMetric, TabStops, Text, TextAlignment,
+    TextAttribute, TextLayout, TextLayoutBuilder, TextOverflow, TextStorage, UnderlineStyle,
+    WritingMode,
 };
 
 use crate::ct_helpers::{self, AttributedString, FontCollection, Frame, Framesetter, Line};
@@ -63,18 +69,25 @@ pub struct CoreGraphicsTextLayout {
     bonus_height: f64,
     image_bounds: Rect,
     width_constraint: f64,
+    line_height: LineHeight,
     // these two are stored values we use to determine cursor extents when the layout is empty.
     default_baseline: f64,
     default_line_height: f64,
     line_metrics: Rc<[LineMetric]>,
     x_offsets: Rc<[f64]>,
     trailing_ws_width: f64,
+    /// CoreText doesn't draw backgrounds, so we paint these before the text.
+    pub(crate) backgrounds: Rc<[(Range<usize>, Color)]>,
 }
 
 /// Building text layouts for `CoreGraphics`.
 pub struct CoreGraphicsTextLayoutBuilder {
     width: f64,
     alignment: TextAlignment,
+    line_height: LineHeight,
+    writing_mode: WritingMode,
+    max_lines: Option<usize>,
+    has_inline_objects: bool,
     text: Rc<dyn TextStorage>,
     /// the end bound up to which we have already added attrs to our AttributedString
     last_resolved_pos: usize,
@@ -87,6 +100,7 @@ pub struct CoreGraphicsTextLayoutBuilder {
     default_baseline: f64,
     default_line_height: f64,
     attrs: Attributes,
+    backgrounds: Vec<(Range<usize>, Color)>,
 }
 
 /// A helper type for storing and resolving attributes
@@ -97,6 +111,12 @@ struct Attributes {
     size: Option<Span<f64>>,
     weight: Option<Span<FontWeight>>,
     style: Option<Span<FontStyle>>,
+    stretch: Option<Span<FontStretch>>,
+    baseline_shift: Option<Span<BaselineShift>>,
+    underline: Option<Span<bool>>,
+    underline_style: Option<Span<UnderlineStyle>>,
+    features: Option<Span<Vec<FontFeature>>>,
+    variations: Option<Span<Vec<FontVariation>>>,
 }
 
 /// during construction, `Span`s represent font attributes that have been applied
@@ -141,7 +161,15 @@ impl CoreGraphicsTextLayoutBuilder {
         }
         // Some attributes are 'standalone' and can just be added to the attributed string
         // immediately.
-        if matches!(&attr, TextAttribute::TextColor(_) | TextAttribute::Underline(_)) {
+        if matches!(
+            &attr,
+            TextAttribute::TextColor(_)
+                | TextAttribute::DecorationColor(_)
+                | TextAttribute::BackgroundColor(_)
+                | TextAttribute::Overline(_)
+                | TextAttribute::LetterSpacing(_)
+                | TextAttribute::WordSpacing(_)
+        ) {
             return self.add_immediately(attr, range);
         }
 
@@ -159,20 +187,36 @@ impl CoreGraphicsTextLayoutBuilder {
     fn set_default_attrs(&mut self) {
         self.has_set_default_attrs = true;
         let whole_range = self.attr_string.range();
-        let font = self.current_font();
+        let font = self.attrs.current_font();
         let ascent = (font.ascent() + 0.5).floor();
         let descent = (font.descent() + 0.5).floor();
         let leading = (font.leading() + 0.5).floor();
         self.default_line_height = ascent + descent + leading;
         self.default_baseline = ascent;
         self.attr_string.set_font(whole_range, &font);
+        self.attr_string
+            .set_baseline_offset(whole_range, self.attrs.baseline_offset());
         self.attr_string
             .set_fg_color(whole_range, &self.attrs.defaults.fg_color);
         self.attr_string
-            .set_underline(whole_range, self.attrs.defaults.underline);
+            .set_underline(whole_range, self.attrs.underline_style());
+        if let Some(color) = &self.attrs.defaults.decoration_color {
+            self.attr_string.set_underline_color(whole_range, color);
+        }
+        self.attr_string
+            .set_letter_spacing(whole_range, self.attrs.defaults.letter_spacing);
     }
 
     fn add_immediately(&mut self, attr: TextAttribute, range: Range<usize>) {
+        if let TextAttribute::BackgroundColor(color) = attr {
+            // attributes don't stack, so the previous background ends where
+            // this one starts.
+            if let Some((prev, _)) = self.backgrounds.last_mut() {
+                prev.end = prev.end.min(range.start).max(prev.start);
+            }
+            self.backgrounds.push((range, color));
+            return;
+        }
         let utf16_start = util::count_utf16(&self.text[..range.start]);
         let utf16_len = util::count_utf16(&self.text[range]);
         let range = CFRange::init(utf16_start as isize, utf16_len as isize);
@@ -180,7 +224,16 @@ impl CoreGraphicsTextLayoutBuilder {
             TextAttribute::TextColor(color) => {
                 self.attr_string.set_fg_color(range, &color);
             }
-            TextAttribute::Underline(flag) => self.attr_string.set_underline(range, flag),
+            TextAttribute::DecorationColor(color) => {
+                self.attr_string.set_underline_color(range, &color)
+            }
+            // CoreText has no overline
+            TextAttribute::Overline(_) => (),
+            TextAttribute::LetterSpacing(spacing) => {
+                self.attr_string.set_letter_spacing(range, spacing)
+            }
+            // CoreText has no equivalent of word spacing
+            TextAttribute::WordSpacing(_) => (),
             _ => unreachable!(),
         }
     }
@@ -202,7 +255,7 @@ impl CoreGraphicsTextLayoutBuilder {
                     util::count_utf16(&self.text[self.last_resolved_pos..next_span_end]);
                 let range =
                     CFRange::init(self.last_resolved_utf16 as isize, range_end_utf16 as isize);
-                let font = self.current_font();
+                let font = self.attrs.current_font();
                 unsafe {
                     self.attr_string.inner.set_attribute(
                         range,
@@ -210,6 +263,10 @@ impl CoreGraphicsTextLayoutBuilder {
                         &font,
                     );
                 }
+                self.attr_string
+                    .set_baseline_offset(range, self.attrs.baseline_offset());
+                self.attr_string
+                    .set_underline(range, self.attrs.underline_style());
                 self.last_resolved_pos = next_span_end;
                 self.last_resolved_utf16 += range_end_utf16;
                 self.update_after_adding_span();
@@ -228,10 +285,37 @@ impl CoreGraphicsTextLayoutBuilder {
         self.attrs.next_span_end(max)
     }
 
-    /// returns the fully constructed font object, including weight and size.
+    /// After we have added a span, check to see if any of our attributes are no
+    /// longer active.
     ///
-    /// This is stateful; it depends on the current attributes being correct
-    /// for the range that begins at `self.last_resolved_pos`.
+    /// This is stateful; it requires that `self.last_resolved_pos` has been just updated
+    /// to reflect the end of the span just added.
+    fn update_after_adding_span(&mut self) {
+        self.attrs.clear_up_to(self.last_resolved_pos)
+    }
+}
+
+impl Attributes {
+    fn add(&mut self, range: Range<usize>, attr: TextAttribute) {
+        match attr {
+            TextAttribute::FontFamily(font) => self.font = Some(Span::new(font, range)),
+            TextAttribute::Weight(w) => self.weight = Some(Span::new(w, range)),
+            TextAttribute::FontSize(s) => self.size = Some(Span::new(s, range)),
+            TextAttribute::Style(s) => self.style = Some(Span::new(s, range)),
+            TextAttribute::Stretch(s) => self.stretch = Some(Span::new(s, range)),
+            TextAttribute::BaselineShift(s) => self.baseline_shift = Some(Span::new(s, range)),
+            TextAttribute::Underline(u) => self.underline = Some(Span::new(u, range)),
+            TextAttribute::UnderlineStyle(s) => self.underline_style = Some(Span::new(s, range)),
+            TextAttribute::FontFeatures(f) => self.features = Some(Span::new(f, range)),
+            TextAttribute::FontVariations(v) => self.variations = Some(Span::new(v, range)),
+            TextAttribute::Strikethrough(_) => { /* Unimplemented for now as coregraphics doesn't have native strikethrough support. */
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// returns the fully constructed font object for the active attributes,
+    /// including weight and size.
     fn current_font(&self) -> CTFont {
         //TODO: this is where caching would happen, if we were implementing caching;
         //store a tuple of attributes resolved to a generated CTFont.
@@ -245,29 +329,40 @@ impl CoreGraphicsTextLayoutBuilder {
         unsafe {
             let family_key =
                 CFString::wrap_under_create_rule(font_descriptor::kCTFontFamilyNameAttribute);
-            let family_name = ct_helpers::ct_family_name(self.attrs.font(), self.attrs.size());
+            let family_name = ct_helpers::ct_family_name(self.font(), self.size());
             let weight_key = CFString::wrap_under_create_rule(font_descriptor::kCTFontWeightTrait);
-            let weight = convert_to_coretext(self.attrs.weight());
+            let weight = convert_to_coretext(self.weight());
+
+            let width_key = CFString::wrap_under_create_rule(font_descriptor::kCTFontWidthTrait);
+            let width = convert_stretch_to_coretext(self.stretch());
 
             let traits_key =
                 CFString::wrap_under_create_rule(font_descriptor::kCTFontTraitsAttribute);
             let mut traits = CFMutableDictionary::new();
             traits.set(weight_key, weight.as_CFType());
-            if self.attrs.italic() {
+            traits.set(width_key, width.as_CFType());
+            if self.italic() {
                 let symbolic_traits_key =
                     CFString::wrap_under_create_rule(font_descriptor::kCTFontSymbolicTrait);
                 let symbolic_traits = CFNumber::from(font_descriptor::kCTFontItalicTrait as i32);
                 traits.set(symbolic_traits_key, symbolic_traits.as_CFType());
             }
 
-            let attributes = CFDictionary::from_CFType_pairs(&[
+            let mut attributes = vec![
                 (family_key, family_name.as_CFType()),
                 (traits_key, traits.as_CFType()),
-            ]);
+            ];
+            if !self.features().is_empty() {
+                let features_key =
+                    CFString::wrap_under_get_rule(font_descriptor::kCTFontFeatureSettingsAttribute);
+                let features = ct_helpers::feature_settings(self.features());
+                attributes.push((features_key, features.as_CFType()));
+            }
+            let attributes = CFDictionary::from_CFType_pairs(&attributes);
             let descriptor = font_descriptor::new_from_attributes(&attributes);
-            let font = font::new_from_descriptor(&descriptor, self.attrs.size());
+            let font = font::new_from_descriptor(&descriptor, self.size());
 
-            let needs_synthetic_ital = self.attrs.italic() && !font.symbolic_traits().is_italic();
+            let needs_synthetic_ital = self.italic() && !font.symbolic_traits().is_italic();
             let has_var_axes = font.get_variation_axes().is_some();
 
             if !(needs_synthetic_ital | has_var_axes) {
@@ -295,53 +390,80 @@ impl CoreGraphicsTextLayoutBuilder {
                 .unwrap_or_default();
 
             // only set weight axis if it exists, and we're not a system font (things get weird)
-            let descriptor =
-                if variation_axes.contains(&WEIGHT_AXIS_TAG) && !self.attrs.font().is_generic() {
+            let mut descriptor =
+                if variation_axes.contains(&WEIGHT_AXIS_TAG) && !self.font().is_generic() {
                     let weight_axis_id: CFNumber = WEIGHT_AXIS_TAG.into();
                     let descriptor = font_descriptor::CTFontDescriptorCreateCopyWithVariation(
                         descriptor.as_concrete_TypeRef(),
                         weight_axis_id.as_concrete_TypeRef(),
-                        self.attrs.weight().to_raw() as _,
+                        self.weight().to_raw() as _,
                     );
                     font_descriptor::CTFontDescriptor::wrap_under_create_rule(descriptor)
                 } else {
                     descriptor
                 };
 
-            ct_helpers::make_font(&descriptor, self.attrs.size(), affine)
-        }
-    }
-
-    /// After we have added a span, check to see if any of our attributes are no
-    /// longer active.
-    ///
-    /// This is stateful; it requires that `self.last_resolved_pos` has been just updated
-    /// to reflect the end of the span just added.
-    fn update_after_adding_span(&mut self) {
-        self.attrs.clear_up_to(self.last_resolved_pos)
-    }
-}
-
-impl Attributes {
-    fn add(&mut self, range: Range<usize>, attr: TextAttribute) {
-        match attr {
-            TextAttribute::FontFamily(font) => self.font = Some(Span::new(font, range)),
-            TextAttribute::Weight(w) => self.weight = Some(Span::new(w, range)),
-            TextAttribute::FontSize(s) => self.size = Some(Span::new(s, range)),
-            TextAttribute::Style(s) => self.style = Some(Span::new(s, range)),
-            TextAttribute::Strikethrough(_) => { /* Unimplemented for now as coregraphics doesn't have native strikethrough support. */
+            // explicit variations are applied last, so they take precedence over weight
+            for variation in self.variations() {
+                let tag = u32::from_be_bytes(variation.tag()) as i32;
+                if variation_axes.contains(&tag) {
+                    let axis_id: CFNumber = tag.into();
+                    let with_variation = font_descriptor::CTFontDescriptorCreateCopyWithVariation(
+                        descriptor.as_concrete_TypeRef(),
+                        axis_id.as_concrete_TypeRef(),
+                        variation.value() as _,
+                    );
+                    descriptor =
+                        font_descriptor::CTFontDescriptor::wrap_under_create_rule(with_variation);
+                }
             }
-            _ => unreachable!(),
+
+            ct_helpers::make_font(&descriptor, self.size(), affine)
         }
     }
 
+    /// The font size, including any reduction for a superscript or subscript.
     fn size(&self) -> f64 {
+        self.baseline_shift().font_size(self.unshifted_size())
+    }
+
+    fn unshifted_size(&self) -> f64 {
         self.size
             .as_ref()
             .map(|s| s.payload)
             .unwrap_or(self.defaults.font_size)
     }
 
+    fn baseline_shift(&self) -> BaselineShift {
+        self.baseline_shift
+            .as_ref()
+            .map(|s| s.payload)
+            .unwrap_or(self.defaults.baseline_shift)
+    }
+
+    fn baseline_offset(&self) -> f64 {
+        self.baseline_shift().offset(self.unshifted_size())
+    }
+
+    /// The style of the underline, if the text is underlined.
+    fn underline_style(&self) -> Option<UnderlineStyle> {
+        let underline = self
+            .underline
+            .as_ref()
+            .map(|u| u.payload)
+            .unwrap_or(self.defaults.underline);
+        let style = self
+            .underline_style
+            .as_ref()
+            .map(|s| s.payload)
+            .unwrap_or(self.defaults.underline_style);
+        if underline {
+            Some(style)
+        } else {
+            None
+        }
+    }
+
     fn weight(&self) -> FontWeight {
         self.weight
             .as_ref()
@@ -349,6 +471,13 @@ impl Attributes {
             .unwrap_or(self.defaults.weight)
     }
 
+    fn stretch(&self) -> FontStretch {
+        self.stretch
+            .as_ref()
+            .map(|s| s.payload)
+            .unwrap_or(self.defaults.stretch)
+    }
+
     fn italic(&self) -> bool {
         matches!(
             self.style
@@ -366,6 +495,20 @@ impl Attributes {
             .unwrap_or_else(|| &self.defaults.font)
     }
 
+    fn features(&self) -> &[FontFeature] {
+        self.features
+            .as_ref()
+            .map(|f| f.payload.as_slice())
+            .unwrap_or(&self.defaults.font_features)
+    }
+
+    fn variations(&self) -> &[FontVariation] {
+        self.variations
+            .as_ref()
+            .map(|v| v.payload.as_slice())
+            .unwrap_or(&self.defaults.font_variations)
+    }
+
     fn next_span_end(&self, max: usize) -> usize {
         self.font
             .as_ref()
@@ -374,6 +517,22 @@ impl Attributes {
             .min(self.size.as_ref().map(Span::range_end).unwrap_or(max))
             .min(self.weight.as_ref().map(Span::range_end).unwrap_or(max))
             .min(self.style.as_ref().map(Span::range_end).unwrap_or(max))
+            .min(self.stretch.as_ref().map(Span::range_end).unwrap_or(max))
+            .min(
+                self.baseline_shift
+                    .as_ref()
+                    .map(Span::range_end)
+                    .unwrap_or(max),
+            )
+            .min(self.underline.as_ref().map(Span::range_end).unwrap_or(max))
+            .min(
+                self.underline_style
+                    .as_ref()
+                    .map(Span::range_end)
+                    .unwrap_or(max),
+            )
+            .min(self.features.as_ref().map(Span::range_end).unwrap_or(max))
+            .min(self.variations.as_ref().map(Span::range_end).unwrap_or(max))
             .min(max)
     }
 
@@ -391,6 +550,24 @@ impl Attributes {
         if self.size.as_ref().map(Span::range_end) == Some(last_pos) {
             self.size = None;
         }
+        if self.stretch.as_ref().map(Span::range_end) == Some(last_pos) {
+            self.stretch = None;
+        }
+        if self.baseline_shift.as_ref().map(Span::range_end) == Some(last_pos) {
+            self.baseline_shift = None;
+        }
+        if self.underline.as_ref().map(Span::range_end) == Some(last_pos) {
+            self.underline = None;
+        }
+        if self.underline_style.as_ref().map(Span::range_end) == Some(last_pos) {
+            self.underline_style = None;
+        }
+        if self.features.as_ref().map(Span::range_end) == Some(last_pos) {
+            self.features = None;
+        }
+        if self.variations.as_ref().map(Span::range_end) == Some(last_pos) {
+            self.variations = None;
+        }
     }
 }
 
@@ -414,6 +591,67 @@ fn convert_to_coretext(weight: FontWeight) -> CFNumber {
     .into()
 }
 
+/// The CSS weight that `convert_to_coretext` maps closest to a coretext weight.
+fn convert_from_coretext(weight: f64) -> FontWeight {
+    const WEIGHTS: [(u16, f64); 9] = [
+        (100, -0.8),
+        (200, -0.6),
+        (300, -0.4),
+        (400, 0.0),
+        (500, 0.23),
+        (600, 0.3),
+        (700, 0.4),
+        (800, 0.56),
+        (900, 0.62),
+    ];
+    let distance = |(_, other): (u16, f64)| (other - weight).abs();
+    let closest = WEIGHTS.iter().copied().fold(WEIGHTS[3], |best, item| {
+        if distance(item) < distance(best) {
+            item
+        } else {
+            best
+        }
+    });
+    FontWeight::new(closest.0)
+}
+
+/// coretext uses a float in the range -1.0..=1.0 for width, with 0.0 as normal;
+/// we map ultra-condensed (50%) to -1.0 and ultra-expanded (200%) to 1.0.
+fn convert_stretch_to_coretext(stretch: FontStretch) -> CFNumber {
+    let percentage = stretch.to_percentage();
+    let width = if percentage < 100.0 {
+        (percentage - 100.0) / 50.0
+    } else {
+        (percentage - 100.0) / 100.0
+    };
+    width.into()
+}
+
+/// The inverse of `convert_stretch_to_coretext`.
+fn convert_stretch_from_coretext(width: f64) -> FontStretch {
+    let percentage = if width < 0.0 {
+        100.0 + width * 50.0
+    } else {
+        100.0 + width * 100.0
+    };
+    FontStretch::from_percentage(percentage)
+}
+
+/// The properties of a CoreText font.
+fn face_info(font: &CTFont) -> FontFaceInfo {
+    let traits = font.all_traits();
+    FontFaceInfo {
+        weight: convert_from_coretext(traits.normalized_weight()),
+        style: if traits.symbolic_traits().is_italic() {
+            FontStyle::Italic
+        } else {
+            FontStyle::Regular
+        },
+        stretch: convert_stretch_from_coretext(traits.normalized_width()),
+        is_variable: font.get_variation_axes().is_some(),
+    }
+}
+
 impl CoreGraphicsText {
     /// Create a new factory that satisfies the piet `Text` trait.
     ///
@@ -447,6 +685,49 @@ impl Text for CoreGraphicsText {
         self.shared.get_font(family_name)
     }
 
+    // fonts added with `load_font` are registered with the font manager, so
+    // they are included in both of these.
+    fn families(&mut self) -> Vec<FontFamily> {
+        let mut names = font_manager::copy_available_font_family_names()
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        names.sort_by_key(|name| name.to_lowercase());
+        names.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+        names.into_iter().map(FontFamily::new_unchecked).collect()
+    }
+
+    fn faces(&mut self, family: &FontFamily) -> Vec<FontFaceInfo> {
+        if family.is_generic() || family.is_stack() {
+            return Vec::new();
+        }
+        let descriptors = match font_collection::create_for_family(family.name())
+            .and_then(|collection| collection.get_descriptors())
+        {
+            Some(descriptors) => descriptors,
+            None => return Vec::new(),
+        };
+        let mut faces = descriptors
+            .iter()
+            .map(|descriptor| {
+                face_info(&font::new_from_descriptor(
+                    &descriptor,
+                    util::DEFAULT_FONT_SIZE,
+                ))
+            })
+            .collect::<Vec<_>>();
+        faces.sort_by_key(|face| {
+            (
+                face.stretch,
+                face.style == FontStyle::Italic,
+                face.weight.to_raw(),
+                face.is_variable,
+            )
+        });
+        faces.dedup();
+        faces
+    }
+
     fn new_text_layout(&mut self, text: impl TextStorage) -> Self::TextLayoutBuilder {
         CoreGraphicsTextLayoutBuilder::new(text)
     }
@@ -456,6 +737,36 @@ impl Text for CoreGraphicsText {
             .map(FontFamily::new_unchecked)
             .map_err(|_| Error::MissingFont)
     }
+
+    fn font_metrics(
+        &mut self,
+        family: &FontFamily,
+        size: f64,
+        weight: FontWeight,
+        style: FontStyle,
+    ) -> FontMetrics {
+        let mut attrs = Attributes::default();
+        attrs.defaults.font = family.clone();
+        attrs.defaults.font_size = size;
+        attrs.defaults.weight = weight;
+        attrs.defaults.style = style;
+        let font = attrs.current_font();
+        // CoreText positions are y-up, and it has no strikethrough metrics,
+        // so a strikethrough is centered on the x-height.
+        let thickness = font.underline_thickness();
+        FontMetrics {
+            ascent: font.ascent(),
+            descent: font.descent(),
+            line_gap: font.leading(),
+            x_height: font.x_height(),
+            cap_height: font.cap_height(),
+            underline_position: -font.underline_position(),
+            underline_thickness: thickness,
+            strikethrough_position: -(font.x_height() + thickness) / 2.0,
+            strikethrough_thickness: thickness,
+            units_per_em: Some(font.units_per_em() as u16),
+        }
+    }
 }
 
 impl SharedTextState {
@@ -484,6 +795,10 @@ impl CoreGraphicsTextLayoutBuilder {
         CoreGraphicsTextLayoutBuilder {
             width: f64::INFINITY,
             alignment: TextAlignment::default(),
+            line_height: LineHeight::default(),
+            writing_mode: WritingMode::default(),
+            max_lines: None,
+            has_inline_objects: false,
             attrs: Default::default(),
             text,
             last_resolved_pos: 0,
@@ -492,6 +807,7 @@ impl CoreGraphicsTextLayoutBuilder {
             has_set_default_attrs: false,
             default_baseline: 0.0,
             default_line_height: 0.0,
+            backgrounds: Vec::new(),
         }
     }
 }
@@ -515,6 +831,51 @@ impl TextLayoutBuilder for CoreGraphicsTextLayoutBuilder {
         self
     }
 
+    fn line_height(mut self, height: LineHeight) -> Self {
+        self.line_height = height;
+        self
+    }
+
+    fn writing_mode(mut self, mode: WritingMode) -> Self {
+        self.writing_mode = mode;
+        self
+    }
+
+    fn max_lines(mut self, lines: usize) -> Self {
+        self.max_lines = Some(lines);
+        self
+    }
+
+    fn overflow(self, _overflow: TextOverflow) -> Self {
+        self
+    }
+
+    fn tab_stops(self, _stops: TabStops) -> Self {
+        self
+    }
+
+    fn hyphens(self, _hyphens: Hyphens) -> Self {
+        self
+    }
+
+    fn language(self, _language: &str) -> Self {
+        self
+    }
+
+    fn justification(self, _justification: Justification) -> Self {
+        self
+    }
+
+    fn inline_object(
+        mut self,
+        _range: impl RangeBounds<usize>,
+        _size: Size,
+        _baseline: f64,
+    ) -> Self {
+        self.has_inline_objects = true;
+        self
+    }
+
     fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
         debug_assert!(
             !self.has_set_default_attrs,
@@ -537,14 +898,35 @@ impl TextLayoutBuilder for CoreGraphicsTextLayoutBuilder {
     }
 
     fn build(mut self) -> Result<Self::Out, Error> {
+        // vertical text, truncation and inline objects aren't supported yet
+        if self.writing_mode.is_vertical() || self.max_lines.is_some() || self.has_inline_objects {
+            return Err(Error::NotSupported);
+        }
         self.finalize();
-        self.attr_string.set_alignment(self.alignment);
+        self.attr_string
+            .set_paragraph_style(self.alignment, self.line_height);
+        let (default_baseline, default_line_height) = util::apply_line_height(
+            self.line_height,
+            self.default_baseline,
+            self.default_line_height,
+        );
+        let len = self.text.len();
+        let backgrounds = self
+            .attrs
+            .defaults
+            .background_color
+            .map(|color| (0..len, color))
+            .into_iter()
+            .chain(self.backgrounds)
+            .collect();
         Ok(CoreGraphicsTextLayout::new(
             self.text,
             self.attr_string,
             self.width,
-            self.default_baseline,
-            self.default_line_height,
+            self.line_height,
+            default_baseline,
+            default_line_height,
+            backgrounds,
         ))
     }
 }
@@ -556,6 +938,10 @@ impl fmt::Debug for CoreGraphicsTextLayout {
 }
 
 impl TextLayout for CoreGraphicsTextLayout {
+    fn set_max_width(&mut self, width: f64) -> Result<(), Error> {
+        self.update_width(width)
+    }
+
     fn size(&self) -> Size {
         Size::new(
             self.frame_size.width,
@@ -670,6 +1056,63 @@ impl TextLayout for CoreGraphicsTextLayout {
         let y_pos = metric.y_offset + metric.baseline;
         HitTestPosition::new(Point::new(x_pos, y_pos), line_num)
     }
+
+    fn glyph_runs(&self) -> Vec<GlyphRun> {
+        self.glyph_runs_with_fonts()
+            .into_iter()
+            .map(|(run, _, _)| run)
+            .collect()
+    }
+
+    fn to_path(&self, origin: impl Into<Point>) -> BezPath {
+        let origin = origin.into();
+        let path = RefCell::new(BezPath::new());
+        for (line_num, line) in self.unwrap_frame().lines().iter().enumerate() {
+            let metric = &self.line_metrics[line_num];
+            let x = origin.x + self.x_offsets[line_num];
+            let y = origin.y + metric.y_offset + metric.baseline;
+            for run in line.runs() {
+                let font = match run.font() {
+                    Some(font) => font,
+                    None => continue,
+                };
+                for (glyph, pos) in run.glyphs().iter().zip(run.positions().iter()) {
+                    // glyph outlines are y-up, relative to the glyph's origin
+                    let transform =
+                        CGAffineTransform::new(1.0, 0.0, 0.0, -1.0, x + pos.x, y - pos.y);
+                    // glyphs such as spaces have no outline
+                    let glyph_path = match font.create_path_for_glyph(*glyph, &transform) {
+                        Ok(glyph_path) => glyph_path,
+                        Err(_) => continue,
+                    };
+                    glyph_path.apply(&|element: CGPathElementRef| {
+                        let points = element
+                            .points()
+                            .iter()
+                            .map(|p| Point::new(p.x, p.y))
+                            .collect::<Vec<_>>();
+                        let mut path = path.borrow_mut();
+                        match element.element_type {
+                            CGPathElementType::MoveToPoint => path.move_to(points[0]),
+                            CGPathElementType::AddLineToPoint => path.line_to(points[0]),
+                            CGPathElementType::AddQuadCurveToPoint => {
+                                path.quad_to(points[0], points[1])
+                            }
+                            CGPathElementType::AddCurveToPoint => {
+                                path.curve_to(points[0], points[1], points[2])
+                            }
+                            CGPathElementType::CloseSubpath => path.close_path(),
+                        }
+                    });
+                }
+            }
+        }
+        path.into_inner()
+    }
+
+    fn inline_object_rect(&self, _position: usize) -> Option<Rect> {
+        None
+    }
 }
 
 impl CoreGraphicsTextLayout {
@@ -677,8 +1120,10 @@ impl CoreGraphicsTextLayout {
         text: Rc<dyn TextStorage>,
         attr_string: AttributedString,
         width_constraint: f64,
+        line_height: LineHeight,
         default_baseline: f64,
         default_line_height: f64,
+        backgrounds: Rc<[(Range<usize>, Color)]>,
     ) -> Self {
         let framesetter = Framesetter::new(&attr_string);
 
@@ -693,17 +1138,18 @@ impl CoreGraphicsTextLayout {
             image_bounds: Rect::ZERO,
             // NaN to ensure we always execute code in update_width
             width_constraint: f64::NAN,
+            line_height,
             default_baseline,
             default_line_height,
             line_metrics: Rc::new([]),
             x_offsets: Rc::new([]),
             trailing_ws_width: 0.0,
+            backgrounds,
         };
         layout.update_width(width_constraint).unwrap();
         layout
     }
 
-    // this used to be part of the TextLayout trait; see https://github.com/linebender/piet/issues/298
     #[allow(clippy::float_cmp)]
     fn update_width(&mut self, new_width: impl Into<Option<f64>>) -> Result<(), Error> {
         let width = new_width.into().unwrap_or(f64::INFINITY);
@@ -723,6 +1169,7 @@ impl CoreGraphicsTextLayout {
             &frame,
             frame_size.height,
             &self.text,
+            self.line_height,
             self.default_line_height,
             self.default_baseline,
         );
@@ -758,6 +1205,89 @@ impl CoreGraphicsTextLayout {
         self.unwrap_frame().draw(ctx)
     }
 
+    /// The layout's glyph runs, with the font and text color of each.
+    pub(crate) fn glyph_runs_with_fonts(&self) -> Vec<(GlyphRun, CTFont, Option<CGColor>)> {
+        let text = self.text.as_str();
+        let utf8_offset =
+            |idx: isize| util::count_until_utf16(text, idx as usize).unwrap_or(text.len());
+        // line breaks have glyphs, but they aren't visible
+        let is_break = |glyph: &Glyph| matches!(text.as_bytes()[glyph.cluster], b'\n' | b'\r');
+
+        let mut result = Vec::new();
+        for (line_num, line) in self.unwrap_frame().lines().iter().enumerate() {
+            let metric = &self.line_metrics[line_num];
+            let x_offset = self.x_offsets[line_num];
+            let line_start = result.len();
+            for run in line.runs() {
+                let font = match run.font() {
+                    Some(font) => font,
+                    None => continue,
+                };
+                let indices = run.string_indices();
+                let mut glyphs = run
+                    .glyphs()
+                    .iter()
+                    .zip(run.advances())
+                    .zip(indices.iter())
+                    .map(|((id, advance), idx)| Glyph {
+                        id: (*id).into(),
+                        advance: advance.width,
+                        offset: Vec2::ZERO,
+                        cluster: utf8_offset(*idx),
+                    })
+                    .collect::<Vec<_>>();
+                // coretext gives each glyph's position, rather than an
+                // offset from the pen; glyphs are usually in visual order, but
+                // we check in case a right-to-left run is stored reversed.
+                let mut positions = run.positions().to_vec();
+                if positions.len() > 1 && positions[0].x > positions[positions.len() - 1].x {
+                    glyphs.reverse();
+                    positions.reverse();
+                }
+                let mut visible = glyphs
+                    .into_iter()
+                    .zip(positions)
+                    .filter(|(glyph, _)| !is_break(glyph))
+                    .peekable();
+                let origin_x = match visible.peek() {
+                    Some((_, pos)) => pos.x,
+                    None => continue,
+                };
+                let mut pen_x = origin_x;
+                let glyphs = visible
+                    .map(|(mut glyph, pos)| {
+                        glyph.offset = Vec2::new(pos.x - pen_x, -pos.y);
+                        pen_x += glyph.advance;
+                        glyph
+                    })
+                    .collect();
+
+                let range = run.string_range();
+                let range = utf8_offset(range.location)..utf8_offset(range.location + range.length);
+                let info = face_info(&font);
+                let glyph_run = GlyphRun {
+                    family: FontFamily::new_unchecked(font.family_name()),
+                    weight: info.weight,
+                    style: info.style,
+                    font_size: font.pt_size(),
+                    line: line_num,
+                    range,
+                    origin: Point::new(x_offset + origin_x, metric.y_offset + metric.baseline),
+                    is_rtl: run.is_rtl(),
+                    glyphs,
+                };
+                result.push((glyph_run, font, run.fg_color()));
+            }
+            result[line_start..].sort_by(|(a, ..), (b, ..)| {
+                a.origin
+                    .x
+                    .partial_cmp(&b.origin.x)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+        }
+        result
+    }
+
     #[inline]
     fn unwrap_frame(&self) -> &Frame {
         self.frame.as_ref().expect("always inited in ::new")
@@ -800,6 +1330,7 @@ fn build_line_metrics(
     frame: &Frame,
     frame_height: f64,
     text: &str,
+    line_height: LineHeight,
     default_line_height: f64,
     default_baseline: f64,
 ) -> (Vec<LineMetric>, Vec<f64>, f64) {
@@ -852,16 +1383,33 @@ fn build_line_metrics(
         let descent = (typo_bounds.descent + 0.5).floor();
         let leading = (typo_bounds.leading + 0.5).floor();
         let height = ascent + descent + leading;
-        let y_offset = y_pos - ascent;
-
-        metrics.push(LineMetric {
-            start_offset,
-            end_offset,
-            trailing_whitespace,
-            baseline: typo_bounds.ascent,
-            height,
-            y_offset,
-        });
+        let metric = if line_height == LineHeight::Normal {
+            LineMetric {
+                start_offset,
+                end_offset,
+                trailing_whitespace,
+                baseline: typo_bounds.ascent,
+                height,
+                y_offset: y_pos - ascent,
+            }
+        } else {
+            // coretext decides where the baseline goes, so lines are stacked
+            // and their baselines are taken from the line origins.
+            let (_, height) = util::apply_line_height(line_height, ascent, height);
+            let y_offset = metrics
+                .last()
+                .map(|lm: &LineMetric| lm.y_offset + lm.height)
+                .unwrap_or(0.0);
+            LineMetric {
+                start_offset,
+                end_offset,
+                trailing_whitespace,
+                baseline: y_pos - y_offset,
+                height,
+                y_offset,
+            }
+        };
+        metrics.push(metric);
         x_offsets.push(x_offset);
     }
 
@@ -1095,9 +1643,66 @@ mod tests {
             .is_none());
     }
 
+    #[test]
+    fn list_families_and_faces() {
+        let mut text = CoreGraphicsText::new_with_unique_state();
+        let helvetica = text.font_family("Helvetica").unwrap();
+        assert!(text.families().contains(&helvetica));
+        let faces = text.faces(&helvetica);
+        assert!(faces
+            .iter()
+            .any(|face| face.weight == FontWeight::BOLD && face.style == FontStyle::Regular));
+        assert!(text.faces(&FontFamily::SANS_SERIF).is_empty());
+    }
+
     #[test]
     fn line_text_empty_string() {
         let layout = CoreGraphicsTextLayoutBuilder::new("").build().unwrap();
         assert_eq!(layout.line_text(0), Some(""));
     }
+
+    #[test]
+    fn glyph_runs() {
+        let input = "abc שלום def\nxyz";
+        let layout = CoreGraphicsTextLayoutBuilder::new(input)
+            .font(FontFamily::new_unchecked("Helvetica"), 16.0)
+            .build()
+            .unwrap();
+        let runs = layout.glyph_runs();
+        assert_eq!(runs.last().unwrap().line, 1);
+        assert_eq!(runs.last().unwrap().range.end, input.len());
+        let hebrew = runs.iter().find(|run| run.is_rtl).unwrap();
+        let clusters = hebrew
+            .glyphs
+            .iter()
+            .map(|glyph| glyph.cluster)
+            .collect::<Vec<_>>();
+        assert_eq!(clusters, vec![10, 8, 6, 4]);
+
+        // runs are positioned where their text is
+        for run in runs.iter().filter(|run| !run.is_rtl) {
+            let hit = layout.hit_test_text_position(run.range.start);
+            let start = run.glyph_positions().next().unwrap();
+            assert_close!(start.x, hit.point.x, 0.001);
+            assert_close!(start.y, hit.point.y, 0.001);
+        }
+    }
+
+    #[test]
+    fn to_path() {
+        use piet::kurbo::Shape;
+
+        let layout = CoreGraphicsTextLayoutBuilder::new("Hello\nworld")
+            .font(FontFamily::new_unchecked("Helvetica"), 16.0)
+            .build()
+            .unwrap();
+        let bounds = layout.to_path(Point::ZERO).bounding_box();
+        let image_bounds = layout.image_bounds().inflate(0.5, 0.5);
+        assert!(!bounds.is_empty());
+        assert_eq!(image_bounds.union(bounds), image_bounds);
+
+        let moved = layout.to_path((10.0, 20.0)).bounding_box();
+        assert_close!(moved.x0, bounds.x0 + 10.0, 0.001);
+        assert_close!(moved.y0, bounds.y0 + 20.0, 0.001);
+    }
 }
@@ -25,12 +25,11 @@ use winapi::um::d2d1::{
     ID2D1Geometry, ID2D1GeometrySink, ID2D1GradientStopCollection, ID2D1Image, ID2D1Layer,
     ID2D1PathGeometry, ID2D1RectangleGeometry, ID2D1RoundedRectangleGeometry, ID2D1SolidColorBrush,
     ID2D1StrokeStyle, D2D1_ANTIALIAS_MODE_PER_PRIMITIVE, D2D1_BEZIER_SEGMENT,
-    D2D1_BITMAP_INTERPOLATION_MODE, D2D1_BRUSH_PROPERTIES, D2D1_COLOR_F,
-    D2D1_COMPATIBLE_RENDER_TARGET_OPTIONS_NONE, D2D1_DEBUG_LEVEL_WARNING, D2D1_DRAW_TEXT_OPTIONS,
-    D2D1_EXTEND_MODE_CLAMP, D2D1_FACTORY_OPTIONS, D2D1_FACTORY_TYPE_MULTI_THREADED,
-    D2D1_FIGURE_BEGIN_FILLED, D2D1_FIGURE_BEGIN_HOLLOW, D2D1_FIGURE_END_CLOSED,
-    D2D1_FIGURE_END_OPEN, D2D1_FILL_MODE_ALTERNATE, D2D1_FILL_MODE_WINDING, D2D1_GAMMA_2_2,
-    D2D1_GRADIENT_STOP, D2D1_LAYER_OPTIONS_NONE, D2D1_LAYER_PARAMETERS,
+    D2D1_BRUSH_PROPERTIES, D2D1_COLOR_F, D2D1_COMPATIBLE_RENDER_TARGET_OPTIONS_NONE,
+    D2D1_DEBUG_LEVEL_WARNING, D2D1_DRAW_TEXT_OPTIONS, D2D1_EXTEND_MODE_CLAMP, D2D1_FACTORY_OPTIONS,
+    D2D1_FACTORY_TYPE_MULTI_THREADED, D2D1_FIGURE_BEGIN_FILLED, D2D1_FIGURE_BEGIN_HOLLOW,
+    D2D1_FIGURE_END_CLOSED, D2D1_FIGURE_END_OPEN, D2D1_FILL_MODE_ALTERNATE, D2D1_FILL_MODE_WINDING,
+    D2D1_GAMMA_2_2, D2D1_GRADIENT_STOP, D2D1_LAYER_OPTIONS_NONE, D2D1_LAYER_PARAMETERS,
     D2D1_LINEAR_GRADIENT_BRUSH_PROPERTIES, D2D1_MATRIX_3X2_F, D2D1_POINT_2F,
     D2D1_QUADRATIC_BEZIER_SEGMENT, D2D1_RADIAL_GRADIENT_BRUSH_PROPERTIES, D2D1_RECT_F, D2D1_SIZE_F,
     D2D1_SIZE_U, D2D1_STROKE_STYLE_PROPERTIES,
@@ -42,7 +41,11 @@ use winapi::um::d2d1_1::{
     D2D1_PROPERTY_TYPE_FLOAT,
 };
 use winapi::um::d2d1effects::{CLSID_D2D1GaussianBlur, D2D1_GAUSSIANBLUR_PROP_STANDARD_DEVIATION};
-use winapi::um::dcommon::{D2D1_ALPHA_MODE, D2D1_ALPHA_MODE_PREMULTIPLIED, D2D1_PIXEL_FORMAT};
+use winapi::um::dcommon::{
+    D2D1_ALPHA_MODE, D2D1_ALPHA_MODE_PREMULTIPLIED, D2D1_PIXEL_FORMAT,
+    DWRITE_MEASURING_MODE_NATURAL,
+};
+use winapi::um::dwrite::DWRITE_GLYPH_RUN;
 use winapi::Interface;
 
 use crate::conv::{circle_to_d2d, rect_to_rectf, rounded_rect_to_d2d, to_point2f};
@@ -718,24 +721,41 @@ impl DeviceContext {
         }
     }
 
+    pub(crate) fn draw_glyph_run(
+        &mut self,
+        baseline_origin: D2D1_POINT_2F,
+        glyph_run: &DWRITE_GLYPH_RUN,
+        brush: &Brush,
+    ) {
+        unsafe {
+            self.0.DrawGlyphRun(
+                baseline_origin,
+                glyph_run,
+                brush.as_raw(),
+                DWRITE_MEASURING_MODE_NATURAL,
+            );
+        }
+    }
+
     #[allow(clippy::trivially_copy_pass_by_ref)]
     pub(crate) fn draw_bitmap(
         &mut self,
         bitmap: &Bitmap,
         dst_rect: &D2D1_RECT_F,
         opacity: f32,
-        interp_mode: D2D1_BITMAP_INTERPOLATION_MODE,
+        interp_mode: D2D1_INTERPOLATION_MODE,
         src_rect: Option<&D2D1_RECT_F>,
     ) {
         unsafe {
-            // derefs are so we get RenderTarget method rather than DeviceContext method.
-            // pointer casts are partly to undo that :)
-            self.0.deref().deref().DrawBitmap(
+            // The DeviceContext method (rather than the RenderTarget one) is needed
+            // for the cubic interpolation modes.
+            self.0.DrawBitmap(
                 bitmap.0.as_raw() as *mut ID2D1Bitmap,
                 dst_rect,
                 opacity,
                 interp_mode,
                 src_rect.map(|r| r as *const _).unwrap_or(null()),
+                null(),
             );
         }
     }
@@ -3,36 +3,49 @@
 // TODO: get rid of this when we actually do use everything
 #![allow(unused)]
 
+use std::cell::RefCell;
 use std::convert::TryInto;
 use std::ffi::OsString;
 use std::fmt::{Debug, Display, Formatter};
 use std::mem::MaybeUninit;
+use std::ops::Range;
 use std::ptr::null_mut;
+use std::rc::Rc;
 use std::sync::Arc;
 
 use dwrote::FontCollection as DWFontCollection;
-use winapi::shared::minwindef::{FALSE, TRUE};
-use winapi::shared::ntdef::LOCALE_NAME_MAX_LENGTH;
-use winapi::shared::winerror::{HRESULT, SUCCEEDED, S_OK};
+use dwrote::FontFace as DWFontFace;
+use winapi::ctypes::c_void;
+use winapi::shared::guiddef::{IsEqualIID, REFIID};
+use winapi::shared::minwindef::{BOOL, FALSE, FLOAT, TRUE};
+use winapi::shared::ntdef::{LOCALE_NAME_MAX_LENGTH, ULONG};
+use winapi::shared::winerror::{E_NOINTERFACE, HRESULT, SUCCEEDED, S_OK};
+use winapi::um::dcommon::DWRITE_MEASURING_MODE;
 use winapi::um::dwrite::{
     DWriteCreateFactory, IDWriteFactory, IDWriteFontCollection, IDWriteFontFamily,
-    IDWriteLocalizedStrings, IDWriteTextFormat, IDWriteTextLayout, DWRITE_FACTORY_TYPE_SHARED,
+    IDWriteInlineObject, IDWriteLocalizedStrings, IDWritePixelSnapping, IDWritePixelSnappingVtbl,
+    IDWriteTextFormat, IDWriteTextLayout, IDWriteTextRenderer, IDWriteTextRendererVtbl,
+    IDWriteTypography, DWRITE_FACTORY_TYPE_SHARED, DWRITE_FONT_FEATURE, DWRITE_FONT_STRETCH,
     DWRITE_FONT_STRETCH_NORMAL, DWRITE_FONT_STYLE, DWRITE_FONT_STYLE_ITALIC,
-    DWRITE_FONT_STYLE_NORMAL, DWRITE_FONT_WEIGHT, DWRITE_FONT_WEIGHT_NORMAL,
-    DWRITE_HIT_TEST_METRICS, DWRITE_LINE_METRICS, DWRITE_OVERHANG_METRICS,
-    DWRITE_READING_DIRECTION_RIGHT_TO_LEFT, DWRITE_TEXT_ALIGNMENT_CENTER,
+    DWRITE_FONT_STYLE_NORMAL, DWRITE_FONT_WEIGHT, DWRITE_FONT_WEIGHT_NORMAL, DWRITE_GLYPH_OFFSET,
+    DWRITE_GLYPH_RUN, DWRITE_GLYPH_RUN_DESCRIPTION, DWRITE_HIT_TEST_METRICS, DWRITE_LINE_METRICS,
+    DWRITE_LINE_SPACING_METHOD_UNIFORM, DWRITE_MATRIX, DWRITE_OVERHANG_METRICS,
+    DWRITE_READING_DIRECTION_RIGHT_TO_LEFT, DWRITE_STRIKETHROUGH, DWRITE_TEXT_ALIGNMENT_CENTER,
     DWRITE_TEXT_ALIGNMENT_JUSTIFIED, DWRITE_TEXT_ALIGNMENT_LEADING, DWRITE_TEXT_ALIGNMENT_TRAILING,
-    DWRITE_TEXT_METRICS, DWRITE_TEXT_RANGE,
+    DWRITE_TEXT_METRICS, DWRITE_TEXT_RANGE, DWRITE_UNDERLINE,
 };
-use winapi::um::unknwnbase::IUnknown;
+use winapi::um::dwrite_1::IDWriteTextLayout1;
+use winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
 use winapi::um::winnls::GetUserDefaultLocaleName;
 use winapi::Interface;
 
 use wio::com::ComPtr;
 use wio::wide::{FromWide, ToWide};
 
-use piet::kurbo::Insets;
-use piet::{FontFamily as PietFontFamily, FontStyle, FontWeight, TextAlignment};
+use piet::kurbo::{BezPath, Insets, Point, Vec2};
+use piet::{
+    FontFamily as PietFontFamily, FontFeature, FontStretch, FontStyle, FontWeight, TextAlignment,
+};
 
 use crate::Brush;
 
@@ -72,6 +85,10 @@ pub struct FontCollection(ComPtr<IDWriteFontCollection>);
 #[derive(Clone)]
 pub struct TextLayout(ComPtr<IDWriteTextLayout>);
 
+/// A set of OpenType features, to be applied to a range of a layout.
+#[derive(Clone)]
+pub struct Typography(ComPtr<IDWriteTypography>);
+
 /// A range in a windows string, represented as a start position and a length.
 #[derive(Debug, Clone, Copy)]
 pub struct Utf16Range {
@@ -157,6 +174,23 @@ impl DwriteFactory {
     pub unsafe fn from_raw(raw: *mut IDWriteFactory) -> Self {
         Self(ComPtr::from_raw(raw))
     }
+
+    pub(crate) fn create_typography(&self, features: &[FontFeature]) -> Result<Typography, Error> {
+        unsafe {
+            let mut ptr = null_mut();
+            let hr = self.0.CreateTypography(&mut ptr);
+            let typography = wrap(hr, ptr, Typography)?;
+            for feature in features {
+                // DWRITE_MAKE_OPENTYPE_TAG puts the first letter in the low byte
+                let feature = DWRITE_FONT_FEATURE {
+                    nameTag: u32::from_le_bytes(feature.tag()),
+                    parameter: feature.value(),
+                };
+                typography.0.AddFontFeature(feature);
+            }
+            Ok(typography)
+        }
+    }
 }
 
 impl FontCollection {
@@ -341,6 +375,17 @@ impl TextLayout {
         }
     }
 
+    /// Give every line of this layout the same height and baseline.
+    pub(crate) fn set_line_spacing(&mut self, height: f64, baseline: f64) {
+        unsafe {
+            self.0.SetLineSpacing(
+                DWRITE_LINE_SPACING_METHOD_UNIFORM,
+                height as f32,
+                baseline as f32,
+            );
+        }
+    }
+
     /// Set the weight for a range of this layout. `start` and `len` are in utf16.
     pub(crate) fn set_weight(&mut self, range: Utf16Range, weight: FontWeight) {
         let weight = weight.to_raw() as DWRITE_FONT_WEIGHT;
@@ -372,6 +417,14 @@ impl TextLayout {
         }
     }
 
+    pub(crate) fn set_stretch(&mut self, range: Utf16Range, stretch: FontStretch) {
+        // the values of DWRITE_FONT_STRETCH are the OpenType width classes
+        let stretch = stretch.to_width_class() as DWRITE_FONT_STRETCH;
+        unsafe {
+            self.0.SetFontStretch(stretch, range.into());
+        }
+    }
+
     pub(crate) fn set_underline(&mut self, range: Utf16Range, flag: bool) {
         let flag = if flag { TRUE } else { FALSE };
         unsafe {
@@ -386,12 +439,39 @@ impl TextLayout {
         }
     }
 
+    /// Add space after each character in a range of this layout.
+    ///
+    /// This requires `IDWriteTextLayout1`, and does nothing if it isn't
+    /// available.
+    pub(crate) fn set_letter_spacing(&mut self, range: Utf16Range, spacing: f32) {
+        if let Ok(layout) = self.0.cast::<IDWriteTextLayout1>() {
+            unsafe {
+                layout.SetCharacterSpacing(0.0, spacing, 0.0, range.into());
+            }
+        }
+    }
+
+    pub(crate) fn set_typography(&mut self, range: Utf16Range, typography: &Typography) {
+        unsafe {
+            self.0.SetTypography(typography.0.as_raw(), range.into());
+        }
+    }
+
     pub(crate) fn set_size(&mut self, range: Utf16Range, size: f32) {
         unsafe {
             self.0.SetFontSize(size, range.into());
         }
     }
 
+    /// The font size at a utf16 position in this layout.
+    pub(crate) fn size_at(&self, position: usize) -> f32 {
+        let mut size = 0.0;
+        unsafe {
+            self.0.GetFontSize(position as u32, &mut size, null_mut());
+        }
+        size
+    }
+
     pub(crate) fn set_foregound_brush(&mut self, range: Utf16Range, brush: Brush) {
         unsafe {
             self.0
@@ -519,6 +599,33 @@ impl TextLayout {
             })
         }
     }
+
+    /// The glyph runs DirectWrite would draw for this layout, in drawing order.
+    pub(crate) fn glyph_runs(&self) -> Vec<GlyphRun> {
+        let mut collector = GlyphRunCollector {
+            vtbl: &GLYPH_RUN_COLLECTOR_VTBL,
+            runs: Vec::new(),
+        };
+        unsafe {
+            let renderer = &mut collector as *mut GlyphRunCollector as *mut IDWriteTextRenderer;
+            // returning an empty vec on failure feels okay?
+            let _ = self.0.Draw(null_mut(), renderer, 0.0, 0.0);
+        }
+        collector.runs
+    }
+
+    /// The font collection used for the text at `position`, if one was set.
+    pub(crate) fn font_collection_at(&self, position: u32) -> Option<DWFontCollection> {
+        unsafe {
+            let mut ptr = null_mut();
+            let hr = self.0.GetFontCollection(position, &mut ptr, null_mut());
+            if SUCCEEDED(hr) && !ptr.is_null() {
+                Some(DWFontCollection::take(ComPtr::from_raw(ptr)))
+            } else {
+                None
+            }
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -589,6 +696,283 @@ impl From<DWRITE_HIT_TEST_METRICS> for HitTestMetrics {
     }
 }
 
+/// A run of glyphs, as DirectWrite draws it.
+pub struct GlyphRun {
+    /// The start of the run's baseline, relative to the layout's origin.
+    ///
+    /// Right-to-left runs extend to the left of this point.
+    pub origin_x: f32,
+    pub origin_y: f32,
+    pub font_face: DWFontFace,
+    pub font_size: f32,
+    pub is_rtl: bool,
+    /// The utf-16 range of the text that the run covers.
+    pub text_range: Range<u32>,
+    /// The glyph indices, advances and offsets, in logical order.
+    pub glyphs: Vec<u16>,
+    pub advances: Vec<f32>,
+    pub offsets: Vec<DWRITE_GLYPH_OFFSET>,
+    /// The utf-16 position of the start of each glyph's cluster.
+    pub clusters: Vec<u32>,
+}
+
+impl GlyphRun {
+    /// The outlines of the run's glyphs, with the layout's origin at `origin`.
+    pub fn outline(&self, origin: Point) -> BezPath {
+        let path = Rc::new(RefCell::new(BezPath::new()));
+        let builder = OutlineCollector {
+            path: path.clone(),
+            offset: origin.to_vec2() + Vec2::new(self.origin_x as f64, self.origin_y as f64),
+        };
+        let result = self.font_face.glyph_run_outline(
+            self.font_size,
+            &self.glyphs,
+            Some(&self.advances),
+            Some(&self.offsets),
+            false,
+            self.is_rtl,
+            Box::new(builder),
+        );
+        if result.is_err() {
+            return BezPath::new();
+        }
+        path.replace(BezPath::new())
+    }
+}
+
+/// Builds a glyph outline from DirectWrite, which gives points relative to
+/// the start of the run's baseline.
+///
+/// The builder has to be `'static`, so the path is shared with the caller.
+struct OutlineCollector {
+    path: Rc<RefCell<BezPath>>,
+    offset: Vec2,
+}
+
+impl OutlineCollector {
+    fn point(&self, x: f32, y: f32) -> Point {
+        Point::new(x as f64, y as f64) + self.offset
+    }
+}
+
+impl dwrote::OutlineBuilder for OutlineCollector {
+    fn move_to(&mut self, x: f32, y: f32) {
+        let p = self.point(x, y);
+        self.path.borrow_mut().move_to(p);
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let p = self.point(x, y);
+        self.path.borrow_mut().line_to(p);
+    }
+
+    fn curve_to(&mut self, cp0x: f32, cp0y: f32, cp1x: f32, cp1y: f32, x: f32, y: f32) {
+        let p1 = self.point(cp0x, cp0y);
+        let p2 = self.point(cp1x, cp1y);
+        let p3 = self.point(x, y);
+        self.path.borrow_mut().curve_to(p1, p2, p3);
+    }
+
+    fn close(&mut self) {
+        self.path.borrow_mut().close_path();
+    }
+}
+
+/// An `IDWriteTextRenderer` that records the glyph runs of a layout, rather
+/// than drawing them.
+///
+/// This only lives for the duration of a call to `IDWriteTextLayout::Draw`,
+/// so it doesn't need reference counting.
+#[repr(C)]
+struct GlyphRunCollector {
+    // this must come first, so that a pointer to the collector is also a
+    // valid pointer to an `IDWriteTextRenderer`.
+    vtbl: *const IDWriteTextRendererVtbl,
+    runs: Vec<GlyphRun>,
+}
+
+static GLYPH_RUN_COLLECTOR_VTBL: IDWriteTextRendererVtbl = IDWriteTextRendererVtbl {
+    parent: IDWritePixelSnappingVtbl {
+        parent: IUnknownVtbl {
+            QueryInterface: collector_query_interface,
+            AddRef: collector_add_ref,
+            Release: collector_release,
+        },
+        IsPixelSnappingDisabled: collector_is_pixel_snapping_disabled,
+        GetCurrentTransform: collector_get_current_transform,
+        GetPixelsPerDip: collector_get_pixels_per_dip,
+    },
+    DrawGlyphRun: collector_draw_glyph_run,
+    DrawUnderline: collector_draw_underline,
+    DrawStrikethrough: collector_draw_strikethrough,
+    DrawInlineObject: collector_draw_inline_object,
+};
+
+unsafe extern "system" fn collector_query_interface(
+    this: *mut IUnknown,
+    iid: REFIID,
+    object: *mut *mut c_void,
+) -> HRESULT {
+    let iid = &*iid;
+    if IsEqualIID(iid, &IUnknown::uuidof())
+        || IsEqualIID(iid, &IDWritePixelSnapping::uuidof())
+        || IsEqualIID(iid, &IDWriteTextRenderer::uuidof())
+    {
+        *object = this as *mut c_void;
+        S_OK
+    } else {
+        *object = null_mut();
+        E_NOINTERFACE
+    }
+}
+
+unsafe extern "system" fn collector_add_ref(_this: *mut IUnknown) -> ULONG {
+    1
+}
+
+unsafe extern "system" fn collector_release(_this: *mut IUnknown) -> ULONG {
+    1
+}
+
+unsafe extern "system" fn collector_is_pixel_snapping_disabled(
+    _this: *mut IDWritePixelSnapping,
+    _context: *mut c_void,
+    is_disabled: *mut BOOL,
+) -> HRESULT {
+    *is_disabled = TRUE;
+    S_OK
+}
+
+unsafe extern "system" fn collector_get_current_transform(
+    _this: *mut IDWritePixelSnapping,
+    _context: *mut c_void,
+    transform: *mut DWRITE_MATRIX,
+) -> HRESULT {
+    *transform = DWRITE_MATRIX {
+        m11: 1.0,
+        m12: 0.0,
+        m21: 0.0,
+        m22: 1.0,
+        dx: 0.0,
+        dy: 0.0,
+    };
+    S_OK
+}
+
+unsafe extern "system" fn collector_get_pixels_per_dip(
+    _this: *mut IDWritePixelSnapping,
+    _context: *mut c_void,
+    pixels_per_dip: *mut FLOAT,
+) -> HRESULT {
+    *pixels_per_dip = 1.0;
+    S_OK
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe extern "system" fn collector_draw_glyph_run(
+    this: *mut IDWriteTextRenderer,
+    _context: *mut c_void,
+    origin_x: FLOAT,
+    origin_y: FLOAT,
+    _measuring_mode: DWRITE_MEASURING_MODE,
+    run: *const DWRITE_GLYPH_RUN,
+    description: *const DWRITE_GLYPH_RUN_DESCRIPTION,
+    _effect: *mut IUnknown,
+) -> HRESULT {
+    let collector = &mut *(this as *mut GlyphRunCollector);
+    let run = &*run;
+    let description = &*description;
+    let count = run.glyphCount as usize;
+    let start = description.textPosition;
+
+    let glyphs = slice_or_empty(run.glyphIndices, count).to_vec();
+    let advances = slice_or_empty(run.glyphAdvances, count).to_vec();
+    let offsets = if run.glyphOffsets.is_null() {
+        let zero = DWRITE_GLYPH_OFFSET {
+            advanceOffset: 0.0,
+            ascenderOffset: 0.0,
+        };
+        vec![zero; count]
+    } else {
+        slice_or_empty(run.glyphOffsets, count).to_vec()
+    };
+
+    // the cluster map gives the first glyph of the cluster of each code
+    // unit; each glyph belongs to the last cluster starting at or before it.
+    let cluster_map = slice_or_empty(description.clusterMap, description.stringLength as usize);
+    let mut clusters = vec![start; count];
+    for (unit, &first_glyph) in cluster_map.iter().enumerate() {
+        let first_glyph = (first_glyph as usize).min(count);
+        if unit == 0 || cluster_map[unit - 1] as usize != first_glyph {
+            for cluster in &mut clusters[first_glyph..] {
+                *cluster = start + unit as u32;
+            }
+        }
+    }
+
+    // the run only borrows its face, so we take a new reference
+    (*run.fontFace).AddRef();
+    let font_face = DWFontFace::take(ComPtr::from_raw(run.fontFace));
+
+    collector.runs.push(GlyphRun {
+        origin_x,
+        origin_y,
+        font_face,
+        font_size: run.fontEmSize,
+        is_rtl: run.bidiLevel % 2 == 1,
+        text_range: start..start + description.stringLength,
+        glyphs,
+        advances,
+        offsets,
+        clusters,
+    });
+    S_OK
+}
+
+unsafe extern "system" fn collector_draw_underline(
+    _this: *mut IDWriteTextRenderer,
+    _context: *mut c_void,
+    _origin_x: FLOAT,
+    _origin_y: FLOAT,
+    _underline: *const DWRITE_UNDERLINE,
+    _effect: *mut IUnknown,
+) -> HRESULT {
+    S_OK
+}
+
+unsafe extern "system" fn collector_draw_strikethrough(
+    _this: *mut IDWriteTextRenderer,
+    _context: *mut c_void,
+    _origin_x: FLOAT,
+    _origin_y: FLOAT,
+    _strikethrough: *const DWRITE_STRIKETHROUGH,
+    _effect: *mut IUnknown,
+) -> HRESULT {
+    S_OK
+}
+
+unsafe extern "system" fn collector_draw_inline_object(
+    _this: *mut IDWriteTextRenderer,
+    _context: *mut c_void,
+    _origin_x: FLOAT,
+    _origin_y: FLOAT,
+    _object: *mut IDWriteInlineObject,
+    _is_sideways: BOOL,
+    _is_rtl: BOOL,
+    _effect: *mut IUnknown,
+) -> HRESULT {
+    S_OK
+}
+
+/// The `len` items at `ptr`, or an empty slice if `ptr` is null.
+unsafe fn slice_or_empty<'a, T>(ptr: *const T, len: usize) -> &'a [T] {
+    if ptr.is_null() {
+        &[]
+    } else {
+        std::slice::from_raw_parts(ptr, len)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -17,17 +17,20 @@ use std::ops::Deref;
 use associative_cache::{AssociativeCache, Capacity1024, HashFourWay, RoundRobinReplacement};
 
 use winapi::um::d2d1::{
-    D2D1_BITMAP_INTERPOLATION_MODE_LINEAR, D2D1_BITMAP_INTERPOLATION_MODE_NEAREST_NEIGHBOR,
     D2D1_LINEAR_GRADIENT_BRUSH_PROPERTIES, D2D1_RADIAL_GRADIENT_BRUSH_PROPERTIES,
 };
-use winapi::um::d2d1_1::{D2D1_COMPOSITE_MODE_SOURCE_OVER, D2D1_INTERPOLATION_MODE_LINEAR};
+use winapi::um::d2d1_1::{
+    D2D1_COMPOSITE_MODE_SOURCE_OVER, D2D1_INTERPOLATION_MODE_CUBIC,
+    D2D1_INTERPOLATION_MODE_HIGH_QUALITY_CUBIC, D2D1_INTERPOLATION_MODE_LINEAR,
+    D2D1_INTERPOLATION_MODE_NEAREST_NEIGHBOR,
+};
 use winapi::um::dcommon::{D2D1_ALPHA_MODE_IGNORE, D2D1_ALPHA_MODE_PREMULTIPLIED};
 
 use piet::kurbo::{Affine, PathEl, Point, Rect, Shape};
 
 use piet::{
     Color, Error, FixedGradient, ImageFormat, InterpolationMode, IntoBrush, RenderContext,
-    StrokeStyle,
+    StrokeStyle, TextPathOptions,
 };
 
 use crate::d2d::wrap_unit;
@@ -281,6 +284,15 @@ impl<'a> RenderContext for D2DRenderContext<'a> {
         layout.draw(pos.into(), self);
     }
 
+    fn draw_text_on_path(
+        &mut self,
+        layout: &Self::TextLayout,
+        path: impl Shape,
+        options: &TextPathOptions,
+    ) {
+        layout.draw_on_path(path, options, self);
+    }
+
     fn save(&mut self) -> Result<(), Error> {
         let new_state = CtxState {
             transform: self.current_transform(),
@@ -554,8 +566,10 @@ fn draw_image<'a>(
         return;
     }
     let interp = match interp {
-        InterpolationMode::NearestNeighbor => D2D1_BITMAP_INTERPOLATION_MODE_NEAREST_NEIGHBOR,
-        InterpolationMode::Bilinear => D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
+        InterpolationMode::NearestNeighbor => D2D1_INTERPOLATION_MODE_NEAREST_NEIGHBOR,
+        InterpolationMode::Bilinear => D2D1_INTERPOLATION_MODE_LINEAR,
+        InterpolationMode::Bicubic => D2D1_INTERPOLATION_MODE_CUBIC,
+        InterpolationMode::HighQuality => D2D1_INTERPOLATION_MODE_HIGH_QUALITY_CUBIC,
     };
     let src_rect = match src_rect {
         Some(src_rect) => Some(rect_to_rectf(src_rect)),
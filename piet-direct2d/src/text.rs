@@ -2,7 +2,7 @@
 
 mod lines;
 
-use std::cell::{Cell, RefCell};
+use std::cell::{Cell, Ref, RefCell};
 use std::convert::TryInto;
 use std::fmt;
 use std::ops::{Range, RangeBounds};
@@ -12,14 +12,18 @@ use std::sync::Arc;
 pub use d2d::{D2DDevice, D2DFactory, DeviceContext as D2DDeviceContext};
 pub use dwrite::DwriteFactory;
 use dwrote::{CustomFontCollectionLoaderImpl, FontCollection, FontFile};
+use winapi::shared::minwindef::FALSE;
 use winapi::um::d2d1::D2D1_DRAW_TEXT_OPTIONS_NONE;
+use winapi::um::dwrite::{DWRITE_GLYPH_OFFSET, DWRITE_GLYPH_RUN};
 use wio::wide::ToWide;
 
-use piet::kurbo::{Insets, Point, Rect, Size};
+use piet::kurbo::{BezPath, Insets, Point, Rect, Shape, Size, Vec2};
 use piet::util;
 use piet::{
-    Color, Error, FontFamily, HitTestPoint, HitTestPosition, LineMetric, RenderContext, Text,
-    TextAlignment, TextAttribute, TextLayout, TextLayoutBuilder, TextStorage,
+    Color, Error, FontFaceInfo, FontFamily, FontMetrics, FontStretch, FontStyle, FontWeight, Glyph,
+    GlyphRun, HitTestPoint, HitTestPosition, Hyphens, Justification, LineHeight, LineMetric,
+    RenderContext, TabStops, Text, TextAlignment, TextAttribute, TextLayout, TextLayoutBuilder,
+    TextOverflow, TextPathOptions, TextStorage, WritingMode,
 };
 
 use crate::conv;
@@ -54,6 +58,10 @@ pub struct D2DTextLayout {
     inking_insets: Insets,
     // this is in a refcell because we need to mutate it to set colors on first draw
     layout: Rc<RefCell<dwrite::TextLayout>>,
+    max_width: f64,
+    // the width currently set on the shared dwrite layout, which may have
+    // been changed by a clone of this layout
+    layout_width: Rc<Cell<f64>>,
     // these two are used when the layout is empty, so we can still correctly
     // draw the cursor
     default_line_height: f64,
@@ -62,16 +70,26 @@ pub struct D2DTextLayout {
     // in order to generate the brushes.
     colors: Rc<[(Utf16Range, Color)]>,
     needs_to_set_colors: Cell<bool>,
+    /// Background colors are painted by us before the text is drawn, in order.
+    backgrounds: Rc<[(Range<usize>, Color)]>,
 }
 
 pub struct D2DTextLayoutBuilder {
+    dwrite: DwriteFactory,
     text: Rc<dyn TextStorage>,
     layout: Result<dwrite::TextLayout, Error>,
     len_utf16: usize,
+    max_width: f64,
     loaded_fonts: Rc<RefCell<LoadedFonts>>,
     default_font: FontFamily,
     default_font_size: f64,
+    line_height: LineHeight,
+    writing_mode: WritingMode,
+    max_lines: Option<usize>,
+    has_inline_objects: bool,
     colors: Vec<(Utf16Range, Color)>,
+    default_background: Option<Color>,
+    backgrounds: Vec<(Range<usize>, Color)>,
     // just used to assert api is used as expected
     last_range_start_pos: usize,
 }
@@ -110,10 +128,43 @@ impl Text for D2DText {
             .and_then(|fonts| fonts.font_family(family_name))
     }
 
+    fn families(&mut self) -> Vec<FontFamily> {
+        let loaded = self.loaded_fonts.borrow();
+        let mut names = loaded
+            .names
+            .iter()
+            .map(|family| family.name().to_owned())
+            .collect::<Vec<_>>();
+        names.extend(FontCollection::system().families_iter().map(|f| f.name()));
+        // the sort is stable, so loaded families come first among equal names
+        names.sort_by_key(|name| name.to_lowercase());
+        names.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+        names.into_iter().map(FontFamily::new_unchecked).collect()
+    }
+
+    fn faces(&mut self, family: &FontFamily) -> Vec<FontFaceInfo> {
+        self.loaded_fonts.borrow_mut().faces(family)
+    }
+
     fn load_font(&mut self, data: &[u8]) -> Result<FontFamily, Error> {
         self.loaded_fonts.borrow_mut().add(data)
     }
 
+    fn font_metrics(
+        &mut self,
+        family: &FontFamily,
+        size: f64,
+        weight: FontWeight,
+        style: FontStyle,
+    ) -> FontMetrics {
+        let mut loaded = self.loaded_fonts.borrow_mut();
+        loaded
+            .matching_font(family, weight, style)
+            .or_else(|| loaded.matching_font(&FontFamily::SYSTEM_UI, weight, style))
+            .map(|font| scaled_metrics(&font, size))
+            .unwrap_or_default()
+    }
+
     fn new_text_layout(&mut self, text: impl TextStorage) -> Self::TextLayoutBuilder {
         let text = Rc::new(text);
         let width = f32::INFINITY;
@@ -124,13 +175,21 @@ impl Text for D2DText {
             .map_err(Into::into);
 
         D2DTextLayoutBuilder {
+            dwrite: self.dwrite.clone(),
             layout,
             text,
             len_utf16: wide_str.len(),
+            max_width: f64::INFINITY,
             colors: Vec::new(),
+            default_background: None,
+            backgrounds: Vec::new(),
             loaded_fonts: self.loaded_fonts.clone(),
             default_font: FontFamily::default(),
             default_font_size: piet::util::DEFAULT_FONT_SIZE,
+            line_height: LineHeight::default(),
+            writing_mode: WritingMode::default(),
+            max_lines: None,
+            has_inline_objects: false,
             last_range_start_pos: 0,
         }
     }
@@ -141,6 +200,7 @@ impl TextLayoutBuilder for D2DTextLayoutBuilder {
 
     fn max_width(mut self, width: f64) -> Self {
         let width = width.max(0.0);
+        self.max_width = width;
         let result = match self.layout.as_mut() {
             Ok(layout) => layout.set_max_width(width),
             Err(_) => Ok(()),
@@ -158,6 +218,51 @@ impl TextLayoutBuilder for D2DTextLayoutBuilder {
         self
     }
 
+    fn line_height(mut self, height: LineHeight) -> Self {
+        self.line_height = height;
+        self
+    }
+
+    fn writing_mode(mut self, mode: WritingMode) -> Self {
+        self.writing_mode = mode;
+        self
+    }
+
+    fn max_lines(mut self, lines: usize) -> Self {
+        self.max_lines = Some(lines);
+        self
+    }
+
+    fn overflow(self, _overflow: TextOverflow) -> Self {
+        self
+    }
+
+    fn tab_stops(self, _stops: TabStops) -> Self {
+        self
+    }
+
+    fn hyphens(self, _hyphens: Hyphens) -> Self {
+        self
+    }
+
+    fn language(self, _language: &str) -> Self {
+        self
+    }
+
+    fn justification(self, _justification: Justification) -> Self {
+        self
+    }
+
+    fn inline_object(
+        mut self,
+        _range: impl RangeBounds<usize>,
+        _size: Size,
+        _baseline: f64,
+    ) -> Self {
+        self.has_inline_objects = true;
+        self
+    }
+
     fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
         debug_assert!(
             self.last_range_start_pos == 0,
@@ -165,7 +270,9 @@ impl TextLayoutBuilder for D2DTextLayoutBuilder {
         );
         let attribute = attribute.into();
         match &attribute {
-            TextAttribute::FontFamily(font) => self.default_font = font.clone(),
+            TextAttribute::FontFamily(font) => {
+                self.default_font = self.loaded_fonts.borrow().first_available(font)
+            }
             TextAttribute::FontSize(size) => self.default_font_size = *size,
             _ => (),
         }
@@ -191,15 +298,37 @@ impl TextLayoutBuilder for D2DTextLayoutBuilder {
     }
 
     fn build(self) -> Result<Self::Out, Error> {
+        // vertical text, truncation and inline objects aren't supported yet
+        if self.writing_mode.is_vertical() || self.max_lines.is_some() || self.has_inline_objects {
+            return Err(Error::NotSupported);
+        }
         let (default_line_height, default_baseline) = self.get_default_line_height_and_baseline();
-        let layout = self.layout?;
+        let mut layout = self.layout?;
+        // DirectWrite can only give every line the same height, so the line
+        // height is applied to the metrics of the default font.
+        let (default_baseline, default_line_height) =
+            util::apply_line_height(self.line_height, default_baseline, default_line_height);
+        if self.line_height != LineHeight::Normal {
+            layout.set_line_spacing(default_line_height, default_baseline);
+        }
+
+        let len = self.text.len();
+        let backgrounds = self
+            .default_background
+            .map(|color| (0..len, color))
+            .into_iter()
+            .chain(self.backgrounds)
+            .collect();
 
         let mut layout = D2DTextLayout {
             text: self.text,
             colors: self.colors.into(),
             needs_to_set_colors: Cell::new(true),
+            backgrounds,
             line_metrics: Rc::new([]),
             layout: Rc::new(RefCell::new(layout)),
+            max_width: self.max_width,
+            layout_width: Rc::new(Cell::new(self.max_width)),
             size: Size::ZERO,
             trailing_ws_width: 0.0,
             inking_insets: Insets::ZERO,
@@ -236,6 +365,7 @@ impl D2DTextLayoutBuilder {
 
             match attr {
                 TextAttribute::FontFamily(font) => {
+                    let font = self.loaded_fonts.borrow().first_available(&font);
                     let is_custom = self.loaded_fonts.borrow().contains(&font);
                     if is_custom {
                         let mut loaded = self.loaded_fonts.borrow_mut();
@@ -251,44 +381,63 @@ impl D2DTextLayoutBuilder {
                 TextAttribute::FontSize(size) => layout.set_size(utf16_range, size as f32),
                 TextAttribute::Weight(weight) => layout.set_weight(utf16_range, weight),
                 TextAttribute::Style(style) => layout.set_style(utf16_range, style),
+                TextAttribute::Stretch(stretch) => layout.set_stretch(utf16_range, stretch),
                 TextAttribute::Underline(flag) => layout.set_underline(utf16_range, flag),
                 TextAttribute::Strikethrough(flag) => layout.set_strikethrough(utf16_range, flag),
+                // DirectWrite only draws single decorations in the text
+                // color, and has no overline, without a custom renderer.
+                TextAttribute::UnderlineStyle(_)
+                | TextAttribute::Overline(_)
+                | TextAttribute::DecorationColor(_) => (),
                 TextAttribute::TextColor(color) => self.colors.push((utf16_range, color)),
+                TextAttribute::BackgroundColor(color) => match range {
+                    Some(range) => {
+                        // attributes don't stack, so the previous background
+                        // ends where this one starts.
+                        if let Some((prev, _)) = self.backgrounds.last_mut() {
+                            prev.end = prev.end.min(range.start).max(prev.start);
+                        }
+                        self.backgrounds.push((range, color));
+                    }
+                    None => self.default_background = Some(color),
+                },
+                TextAttribute::LetterSpacing(spacing) => {
+                    layout.set_letter_spacing(utf16_range, spacing as f32)
+                }
+                // DirectWrite has no equivalent of word spacing
+                TextAttribute::WordSpacing(_) => (),
+                TextAttribute::FontFeatures(features) => {
+                    if let Ok(typography) = self.dwrite.create_typography(&features) {
+                        layout.set_typography(utf16_range, &typography);
+                    }
+                }
+                // font axes are only available through the Windows 10 DirectWrite
+                // interfaces, which winapi doesn't bind.
+                TextAttribute::FontVariations(_) => (),
+                // DirectWrite can't offset text from the baseline without a
+                // custom renderer, so only the size of scripts is reduced.
+                TextAttribute::BaselineShift(shift) => {
+                    let size = layout.size_at(utf16_range.start) as f64;
+                    layout.set_size(utf16_range, shift.font_size(size) as f32);
+                }
             }
         }
     }
 
     fn get_default_line_height_and_baseline(&self) -> (f64, f64) {
-        let family_name = resolve_family_name(&self.default_font);
-        let is_custom = self.loaded_fonts.borrow().contains(&self.default_font);
-        let family = if is_custom {
-            let mut loaded = self.loaded_fonts.borrow_mut();
-            loaded.collection().get_font_family_by_name(&family_name)
-        } else {
-            FontCollection::system().get_font_family_by_name(&family_name)
-        };
-
-        let family = match family {
-            Some(family) => family,
-            // absolute fallback; use font size as line height
-            None => return (self.default_font_size, self.default_font_size * 0.8),
-        };
-
-        let font = family.get_first_matching_font(
-            dwrote::FontWeight::Regular,
-            dwrote::FontStretch::Normal,
-            dwrote::FontStyle::Normal,
+        let font = self.loaded_fonts.borrow_mut().matching_font(
+            &self.default_font,
+            FontWeight::REGULAR,
+            FontStyle::Regular,
         );
-        let metrics = font.metrics().metrics0();
-        let ascent = metrics.ascent as f64;
-        let vert_metrics = ascent + metrics.descent as f64 + metrics.lineGap as f64;
-        let vert_fraction = vert_metrics / metrics.designUnitsPerEm as f64;
-        let ascent_fraction = ascent / metrics.designUnitsPerEm as f64;
-
-        let line_height = self.default_font_size * vert_fraction;
-        let baseline = self.default_font_size * ascent_fraction;
-
-        (line_height, baseline)
+        match font {
+            Some(font) => {
+                let metrics = scaled_metrics(&font, self.default_font_size);
+                (metrics.line_height(), metrics.ascent)
+            }
+            // absolute fallback; use font size as line height
+            None => (self.default_font_size, self.default_font_size * 0.8),
+        }
     }
 }
 
@@ -299,6 +448,15 @@ impl fmt::Debug for D2DTextLayout {
 }
 
 impl TextLayout for D2DTextLayout {
+    fn set_max_width(&mut self, width: f64) -> Result<(), Error> {
+        let width = width.max(0.0);
+        self.layout.borrow_mut().set_max_width(width)?;
+        self.max_width = width;
+        self.layout_width.set(width);
+        self.rebuild_metrics();
+        Ok(())
+    }
+
     fn size(&self) -> Size {
         self.size
     }
@@ -340,8 +498,7 @@ impl TextLayout for D2DTextLayout {
     fn hit_test_point(&self, point: Point) -> HitTestPoint {
         // lossy from f64 to f32, but shouldn't have too much impact
         let htp = self
-            .layout
-            .borrow()
+            .dwrite_layout()
             .hit_test_point(point.x as f32, point.y as f32);
 
         // Round up to next grapheme cluster boundary if DirectWrite
@@ -379,8 +536,7 @@ impl TextLayout for D2DTextLayout {
         let idx_16: u32 = idx_16.try_into().unwrap();
 
         let mut hit_point = self
-            .layout
-            .borrow()
+            .dwrite_layout()
             .hit_test_text_position(idx_16, trailing)
             .map(|hit| Point::new(hit.point_x as f64, hit.point_y as f64))
             // if DWrite fails we just return 0, 0
@@ -391,14 +547,50 @@ impl TextLayout for D2DTextLayout {
         }
         HitTestPosition::new(hit_point, line)
     }
+
+    fn glyph_runs(&self) -> Vec<GlyphRun> {
+        self.glyph_runs_with_faces()
+            .into_iter()
+            .map(|(run, _)| run)
+            .collect()
+    }
+
+    fn to_path(&self, origin: impl Into<Point>) -> BezPath {
+        let origin = origin.into();
+        let mut path = BezPath::new();
+        for run in self.dwrite_layout().glyph_runs() {
+            path.extend(run.outline(origin));
+        }
+        path
+    }
+
+    fn inline_object_rect(&self, _position: usize) -> Option<Rect> {
+        None
+    }
 }
 
 impl D2DTextLayout {
+    /// The DirectWrite layout, at this layout's max width.
+    ///
+    /// Clones share the DirectWrite layout, so if another clone has been
+    /// given a different width, ours is applied again first.
+    #[allow(clippy::float_cmp)]
+    fn dwrite_layout(&self) -> Ref<'_, dwrite::TextLayout> {
+        if self.layout_width.get() != self.max_width {
+            // this width was already accepted once, by build or set_max_width
+            let _ = self.layout.borrow_mut().set_max_width(self.max_width);
+            self.layout_width.set(self.max_width);
+        }
+        self.layout.borrow()
+    }
+
     // must be called after build and after updating the width
     fn rebuild_metrics(&mut self) {
-        let line_metrics = lines::fetch_line_metrics(&self.text, &self.layout.borrow());
-        let text_metrics = self.layout.borrow().get_metrics();
-        let overhang = self.layout.borrow().get_overhang_metrics();
+        let layout = self.dwrite_layout();
+        let line_metrics = lines::fetch_line_metrics(&self.text, &layout);
+        let text_metrics = layout.get_metrics();
+        let overhang = layout.get_overhang_metrics();
+        drop(layout);
 
         let size = Size::new(text_metrics.width as f64, text_metrics.height as f64);
         let overhang_width = text_metrics.layoutWidth as f64 + overhang.x1;
@@ -422,13 +614,158 @@ impl D2DTextLayout {
 
     pub fn draw(&self, pos: Point, ctx: &mut D2DRenderContext) {
         if !self.text.is_empty() {
+            for (range, color) in self.backgrounds.iter() {
+                for rect in self.rects_for_range(range.clone()) {
+                    ctx.fill(rect + pos.to_vec2(), color);
+                }
+            }
             self.resolve_colors_if_needed(ctx);
             let pos = conv::to_point2f(pos);
             let black_brush = ctx.solid_brush(Color::BLACK);
             let text_options = D2D1_DRAW_TEXT_OPTIONS_NONE;
             ctx.rt
-                .draw_text_layout(pos, &self.layout.borrow(), &black_brush, text_options);
+                .draw_text_layout(pos, &self.dwrite_layout(), &black_brush, text_options);
+        }
+    }
+
+    pub fn draw_on_path(
+        &self,
+        path: impl Shape,
+        options: &TextPathOptions,
+        ctx: &mut D2DRenderContext,
+    ) {
+        let baseline = match self.line_metrics.first() {
+            Some(lm) => lm.y_offset + lm.baseline,
+            None => return,
+        };
+        let (glyph_runs, faces): (Vec<_>, Vec<_>) =
+            self.glyph_runs_with_faces().into_iter().unzip();
+        let transforms = util::glyph_run_transforms(path, &glyph_runs, baseline, options);
+        for ((glyph_run, face), transforms) in glyph_runs.iter().zip(&faces).zip(transforms) {
+            let brush = ctx.solid_brush(self.color_at(glyph_run.range.start));
+            let glyphs = glyph_run.glyphs.iter().zip(glyph_run.glyph_positions());
+            for ((glyph, pos), transform) in glyphs.zip(transforms) {
+                let transform = match transform {
+                    Some(transform) => transform,
+                    None => continue,
+                };
+                // each glyph is drawn on its own, at its position in the layout
+                let index = glyph.id as u16;
+                let advance = glyph.advance as f32;
+                let offset = DWRITE_GLYPH_OFFSET {
+                    advanceOffset: 0.0,
+                    ascenderOffset: 0.0,
+                };
+                let dw_run = DWRITE_GLYPH_RUN {
+                    fontFace: unsafe { face.as_ptr() },
+                    fontEmSize: glyph_run.font_size as f32,
+                    glyphCount: 1,
+                    glyphIndices: &index,
+                    glyphAdvances: &advance,
+                    glyphOffsets: &offset,
+                    isSideways: FALSE,
+                    bidiLevel: 0,
+                };
+                let _ = ctx.with_save(|ctx| {
+                    ctx.transform(transform);
+                    ctx.rt
+                        .draw_glyph_run(conv::to_point2f(pos), &dw_run, &brush);
+                    Ok(())
+                });
+            }
+        }
+    }
+
+    /// The text color at a utf-8 position.
+    fn color_at(&self, pos: usize) -> Color {
+        let pos_16 = util::count_utf16(&self.text.as_str()[..pos]);
+        self.colors
+            .iter()
+            .rev()
+            .find(|(range, _)| range.start <= pos_16 && pos_16 < range.start + range.len)
+            .map(|(_, color)| color.clone())
+            // the default brush in `draw`
+            .unwrap_or(Color::BLACK)
+    }
+
+    /// The layout's glyph runs, with the font face of each.
+    fn glyph_runs_with_faces(&self) -> Vec<(GlyphRun, dwrote::FontFace)> {
+        if self.text.is_empty() {
+            return Vec::new();
+        }
+        let text = self.text.as_str();
+        let utf8_offset =
+            |pos: u32| util::count_until_utf16(text, pos as usize).unwrap_or(text.len());
+        // line breaks have glyphs, but they aren't visible
+        let is_break = |glyph: &Glyph| matches!(text.as_bytes()[glyph.cluster], b'\n' | b'\r');
+
+        let layout = self.dwrite_layout();
+        let mut result = Vec::new();
+        for run in layout.glyph_runs() {
+            // DirectWrite gives glyphs in logical order, and offsets in the
+            // direction of the text
+            let direction = if run.is_rtl { -1.0 } else { 1.0 };
+            let mut glyphs = (0..run.glyphs.len())
+                .map(|idx| Glyph {
+                    id: run.glyphs[idx].into(),
+                    advance: run.advances[idx] as f64,
+                    offset: Vec2::new(
+                        direction * run.offsets[idx].advanceOffset as f64,
+                        -run.offsets[idx].ascenderOffset as f64,
+                    ),
+                    cluster: utf8_offset(run.clusters[idx]),
+                })
+                .collect::<Vec<_>>();
+            let mut x = run.origin_x as f64;
+            if run.is_rtl {
+                glyphs.reverse();
+                x -= glyphs.iter().map(|glyph| glyph.advance).sum::<f64>();
+            }
+            while glyphs.first().map_or(false, &is_break) {
+                x += glyphs.remove(0).advance;
+            }
+            while glyphs.last().map_or(false, &is_break) {
+                glyphs.pop();
+            }
+            if glyphs.is_empty() {
+                continue;
+            }
+
+            let font = layout
+                .font_collection_at(run.text_range.start)
+                .and_then(|fonts| fonts.get_font_from_face(&run.font_face))
+                .or_else(|| FontCollection::system().get_font_from_face(&run.font_face));
+            let (family, weight, style) = match font {
+                Some(font) => {
+                    let info = face_info(&font);
+                    (
+                        FontFamily::new_unchecked(font.family_name()),
+                        info.weight,
+                        info.style,
+                    )
+                }
+                // this shouldn't happen, as faces come from one of these collections
+                None => (
+                    FontFamily::default(),
+                    FontWeight::REGULAR,
+                    FontStyle::Regular,
+                ),
+            };
+            let range = utf8_offset(run.text_range.start)..utf8_offset(run.text_range.end);
+            let glyph_run = GlyphRun {
+                family,
+                weight,
+                style,
+                font_size: run.font_size as f64,
+                line: util::line_number_for_position(&self.line_metrics, range.start),
+                range,
+                origin: Point::new(x, run.origin_y as f64),
+                is_rtl: run.is_rtl,
+                glyphs,
+            };
+            result.push((glyph_run, run.font_face));
         }
+        result
     }
 
     fn resolve_colors_if_needed(&self, ctx: &mut D2DRenderContext) {
@@ -451,6 +788,43 @@ fn resolve_family_name(family: &FontFamily) -> &str {
     }
 }
 
+/// The properties of a DirectWrite font.
+fn face_info(font: &dwrote::Font) -> FontFaceInfo {
+    FontFaceInfo {
+        weight: FontWeight::new(font.weight().to_u32() as u16),
+        style: match font.style() {
+            dwrote::FontStyle::Normal => FontStyle::Regular,
+            dwrote::FontStyle::Oblique | dwrote::FontStyle::Italic => FontStyle::Italic,
+        },
+        stretch: match font.stretch().to_u32() {
+            0 => FontStretch::Normal,
+            class => FontStretch::from_width_class(class as u16),
+        },
+        // finding variable fonts needs IDWriteFontFace5, which winapi
+        // doesn't bind
+        is_variable: false,
+    }
+}
+
+/// The metrics of a DirectWrite font at the given size.
+fn scaled_metrics(font: &dwrote::Font, size: f64) -> FontMetrics {
+    let metrics = font.metrics().metrics0();
+    let scale = size / metrics.designUnitsPerEm as f64;
+    // font positions are y-up
+    FontMetrics {
+        ascent: metrics.ascent as f64 * scale,
+        descent: metrics.descent as f64 * scale,
+        line_gap: metrics.lineGap as f64 * scale,
+        x_height: metrics.xHeight as f64 * scale,
+        cap_height: metrics.capHeight as f64 * scale,
+        underline_position: -(metrics.underlinePosition as f64) * scale,
+        underline_thickness: metrics.underlineThickness as f64 * scale,
+        strikethrough_position: -(metrics.strikethroughPosition as f64) * scale,
+        strikethrough_thickness: metrics.strikethroughThickness as f64 * scale,
+        units_per_em: Some(metrics.designUnitsPerEm),
+    }
+}
+
 impl LoadedFonts {
     fn add(&mut self, font_data: &[u8]) -> Result<FontFamily, Error> {
         let font_data: Arc<Vec<u8>> = Arc::new(font_data.to_owned());
@@ -477,10 +851,86 @@ impl LoadedFonts {
         self.names.contains(family)
     }
 
+    /// The first family in a stack that is generic, loaded, or installed on
+    /// the system, or the first family if there are none.
+    fn first_available(&self, family: &FontFamily) -> FontFamily {
+        if !family.is_stack() {
+            return family.clone();
+        }
+        let families = family.families();
+        let system = FontCollection::system();
+        families
+            .iter()
+            .find(|family| {
+                family.is_generic()
+                    || self.contains(family)
+                    || system.get_font_family_by_name(family.name()).is_some()
+            })
+            .unwrap_or(&families[0])
+            .clone()
+    }
+
     fn is_empty(&self) -> bool {
         self.files.is_empty()
     }
 
+    /// The faces of a loaded or installed family, not including those that
+    /// DirectWrite would synthesize.
+    fn faces(&mut self, family: &FontFamily) -> Vec<FontFaceInfo> {
+        if family.is_generic() || family.is_stack() {
+            return Vec::new();
+        }
+        let dw_family = if self.contains(family) {
+            self.collection().get_font_family_by_name(family.name())
+        } else {
+            FontCollection::system().get_font_family_by_name(family.name())
+        };
+        let dw_family = match dw_family {
+            Some(dw_family) => dw_family,
+            None => return Vec::new(),
+        };
+        let mut faces = (0..dw_family.get_font_count())
+            .map(|idx| dw_family.get_font(idx))
+            .filter(|font| font.simulations() == dwrote::FontSimulations::None)
+            .map(|font| face_info(&font))
+            .collect::<Vec<_>>();
+        faces.sort_by_key(|face| {
+            (
+                face.stretch,
+                face.style == FontStyle::Italic,
+                face.weight.to_raw(),
+            )
+        });
+        faces.dedup();
+        faces
+    }
+
+    /// The font in `family` that best matches `weight` and `style`, if the
+    /// family is loaded or installed on the system.
+    fn matching_font(
+        &mut self,
+        family: &FontFamily,
+        weight: FontWeight,
+        style: FontStyle,
+    ) -> Option<dwrote::Font> {
+        let family = self.first_available(family);
+        let family_name = resolve_family_name(&family);
+        let family = if self.contains(&family) {
+            self.collection().get_font_family_by_name(family_name)
+        } else {
+            FontCollection::system().get_font_family_by_name(family_name)
+        }?;
+        let style = match style {
+            FontStyle::Regular => dwrote::FontStyle::Normal,
+            FontStyle::Italic => dwrote::FontStyle::Italic,
+        };
+        Some(family.get_first_matching_font(
+            dwrote::FontWeight::from_u32(weight.to_raw() as u32),
+            dwrote::FontStretch::Normal,
+            style,
+        ))
+    }
+
     fn collection(&mut self) -> &FontCollection {
         if self.collection.is_none() {
             let loader = CustomFontCollectionLoaderImpl::new(self.files.as_slice());
@@ -1076,4 +1526,68 @@ mod test {
         let mut text = D2DText::new_for_test();
         assert!(text.font_family("A Quite Unlikely Font Ñame").is_none());
     }
+
+    #[test]
+    fn list_families_and_faces() {
+        let mut text = D2DText::new_for_test();
+        let segoe = text.font_family("Segoe UI").unwrap();
+        assert!(text.families().contains(&segoe));
+        let faces = text.faces(&segoe);
+        assert!(faces
+            .iter()
+            .any(|face| face.weight == FontWeight::BOLD && face.style == FontStyle::Regular));
+        assert!(text.faces(&FontFamily::SANS_SERIF).is_empty());
+    }
+
+    #[test]
+    fn glyph_runs() {
+        let mut text = D2DText::new_for_test();
+        let input = "abc שלום def\nxyz";
+        let layout = text
+            .new_text_layout(input)
+            .font(FontFamily::new_unchecked("Arial"), 16.0)
+            .build()
+            .unwrap();
+        let runs = layout.glyph_runs();
+        assert_eq!(runs.last().unwrap().line, 1);
+        assert_eq!(runs.last().unwrap().range.end, input.len());
+        let hebrew = runs.iter().find(|run| run.is_rtl).unwrap();
+        let clusters = hebrew
+            .glyphs
+            .iter()
+            .map(|glyph| glyph.cluster)
+            .collect::<Vec<_>>();
+        assert_eq!(clusters, vec![10, 8, 6, 4]);
+
+        // runs are positioned where their text is
+        for run in &runs {
+            assert_eq!(run.family.name(), "Arial");
+            let hit = layout.hit_test_text_position(run.range.start);
+            let start = run.glyph_positions().next().unwrap();
+            assert_close!(start.y, hit.point.y, 0.001);
+            if !run.is_rtl {
+                assert_close!(start.x, hit.point.x, 0.001);
+            }
+        }
+    }
+
+    #[test]
+    fn to_path() {
+        use piet::kurbo::Shape;
+
+        let mut text = D2DText::new_for_test();
+        let layout = text
+            .new_text_layout("Hello\nworld")
+            .font(FontFamily::new_unchecked("Arial"), 16.0)
+            .build()
+            .unwrap();
+        let bounds = layout.to_path(Point::ZERO).bounding_box();
+        let image_bounds = layout.image_bounds().inflate(0.5, 0.5);
+        assert!(!bounds.is_empty());
+        assert_eq!(image_bounds.union(bounds), image_bounds);
+
+        let moved = layout.to_path((10.0, 20.0)).bounding_box();
+        assert_close!(moved.x0, bounds.x0 + 10.0, 0.001);
+        assert_close!(moved.y0, bounds.y0 + 20.0, 0.001);
+    }
 }
@@ -1,6 +1,7 @@
 //! SVG output support for piet
 //!
-//! Text and images are unimplemented and will always return errors.
+//! Text is unimplemented and will always return errors. Images are embedded
+//! as PNG data.
 
 #![deny(clippy::trivially_copy_pass_by_ref)]
 
@@ -11,8 +12,8 @@ use std::{io, mem};
 
 use piet::kurbo::{Affine, Point, Rect, Shape};
 use piet::{
-    Color, Error, FixedGradient, ImageFormat, InterpolationMode, IntoBrush, LineCap, LineJoin,
-    StrokeStyle,
+    util, Color, Error, FixedGradient, ImageFormat, InterpolationMode, IntoBrush, LineCap,
+    LineJoin, StrokeStyle, TextPathOptions,
 };
 use svg::node::Node;
 
@@ -36,7 +37,7 @@ impl RenderContext {
         Self {
             stack: Vec::new(),
             state: State::default(),
-            doc: svg::Document::new(),
+            doc: svg::Document::new().set("xmlns:xlink", "http://www.w3.org/1999/xlink"),
             next_id: 0,
             text: Text::new(),
         }
@@ -218,6 +219,15 @@ impl piet::RenderContext for RenderContext {
         unimplemented!()
     }
 
+    fn draw_text_on_path(
+        &mut self,
+        _layout: &Self::TextLayout,
+        _path: impl Shape,
+        _options: &TextPathOptions,
+    ) {
+        unimplemented!()
+    }
+
     fn save(&mut self) -> Result<()> {
         let new = self.state.clone();
         self.stack.push(mem::replace(&mut self.state, new));
@@ -243,12 +253,12 @@ impl piet::RenderContext for RenderContext {
 
     fn make_image(
         &mut self,
-        _width: usize,
-        _height: usize,
-        _buf: &[u8],
-        _format: ImageFormat,
+        width: usize,
+        height: usize,
+        buf: &[u8],
+        format: ImageFormat,
     ) -> Result<Self::Image> {
-        Err(Error::NotSupported)
+        Image::new(width, height, buf, format)
     }
 
     #[inline]
@@ -278,13 +288,57 @@ impl piet::RenderContext for RenderContext {
 }
 
 fn draw_image(
-    _ctx: &mut RenderContext,
-    _image: &<RenderContext as piet::RenderContext>::Image,
-    _src_rect: Option<Rect>,
-    _dst_rect: Rect,
-    _interp: InterpolationMode,
+    ctx: &mut RenderContext,
+    image: &<RenderContext as piet::RenderContext>::Image,
+    src_rect: Option<Rect>,
+    dst_rect: Rect,
+    interp: InterpolationMode,
 ) {
-    unimplemented!()
+    let full_rect = Rect::new(0.0, 0.0, image.width as f64, image.height as f64);
+    let src_rect = src_rect.unwrap_or(full_rect);
+    if src_rect.is_empty() || dst_rect.is_empty() {
+        return;
+    }
+
+    // Position the whole image so that `src_rect` lands on `dst_rect`.
+    let scale_x = dst_rect.width() / src_rect.width();
+    let scale_y = dst_rect.height() / src_rect.height();
+    let x = dst_rect.x0 - scale_x * src_rect.x0;
+    let y = dst_rect.y0 - scale_y * src_rect.y0;
+    let mut node = svg::node::element::Image::new()
+        .set("x", x)
+        .set("y", y)
+        .set("width", scale_x * full_rect.width())
+        .set("height", scale_y * full_rect.height())
+        .set("preserveAspectRatio", "none")
+        // SVG 1.1 renderers only understand the namespaced attribute
+        .set("xlink:href", image.href.as_str())
+        .set("transform", xf_val(&ctx.state.xf));
+    match interp {
+        InterpolationMode::NearestNeighbor => node.assign("image-rendering", "optimizeSpeed"),
+        InterpolationMode::Bilinear => {}
+        InterpolationMode::Bicubic | InterpolationMode::HighQuality => {
+            node.assign("image-rendering", "optimizeQuality")
+        }
+    }
+
+    if src_rect != full_rect {
+        let id = ctx.new_id();
+        let mut clip = svg::node::element::ClipPath::new().set("id", id);
+        add_shape(&mut clip, dst_rect, &Attrs::default());
+        ctx.doc.append(clip);
+        node.assign("clip-path", format!("url(#{})", id.to_string()));
+    }
+
+    match ctx.state.clip {
+        Some(id) => {
+            let group = svg::node::element::Group::new()
+                .set("clip-path", format!("url(#{})", id.to_string()))
+                .add(node);
+            ctx.doc.append(group);
+        }
+        None => ctx.doc.append(node),
+    }
 }
 
 #[derive(Default)]
@@ -453,8 +507,59 @@ fn fmt_opacity(color: &Color) -> String {
     }
 }
 
-/// SVG image (unimplemented)
-pub struct Image(());
+/// SVG image, stored as an encoded PNG
+#[derive(Clone)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    /// A `data:` URI holding the PNG.
+    href: String,
+}
+
+impl Image {
+    fn new(width: usize, height: usize, buf: &[u8], format: ImageFormat) -> Result<Image> {
+        let (color_type, data) = match format {
+            ImageFormat::Grayscale => (png::ColorType::Grayscale, Cow::Borrowed(buf)),
+            ImageFormat::Rgb => (png::ColorType::RGB, Cow::Borrowed(buf)),
+            ImageFormat::RgbaSeparate => (png::ColorType::RGBA, Cow::Borrowed(buf)),
+            ImageFormat::RgbaPremul => {
+                let mut data = Vec::with_capacity(buf.len());
+                for px in buf.chunks_exact(4) {
+                    let a = px[3];
+                    data.extend_from_slice(&[
+                        util::unpremul(px[0], a),
+                        util::unpremul(px[1], a),
+                        util::unpremul(px[2], a),
+                        a,
+                    ]);
+                }
+                (png::ColorType::RGBA, Cow::Owned(data))
+            }
+            _ => return Err(Error::NotSupported),
+        };
+        if data.len() != width * height * format.bytes_per_pixel() {
+            return Err(Error::InvalidInput);
+        }
+
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut encoded, width as u32, height as u32);
+            encoder.set_color(color_type);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder
+                .write_header()
+                .map_err(|e| Error::BackendError(Box::new(e)))?;
+            writer
+                .write_image_data(&data)
+                .map_err(|e| Error::BackendError(Box::new(e)))?;
+        }
+        Ok(Image {
+            width,
+            height,
+            href: format!("data:image/png;base64,{}", base64::encode(&encoded)),
+        })
+    }
+}
 
 #[derive(Debug, Copy, Clone)]
 struct Id(u64);
@@ -2,9 +2,10 @@
 
 use std::ops::RangeBounds;
 
-use piet::kurbo::{Point, Rect, Size};
+use piet::kurbo::{BezPath, Point, Rect, Size};
 use piet::{
-    Error, FontFamily, HitTestPoint, HitTestPosition, LineMetric, TextAttribute, TextStorage,
+    Error, FontFaceInfo, FontFamily, FontMetrics, FontStyle, FontWeight, GlyphRun, HitTestPoint,
+    HitTestPosition, LineMetric, TextAttribute, TextStorage,
 };
 
 type Result<T> = std::result::Result<T, Error>;
@@ -28,10 +29,28 @@ impl piet::Text for Text {
         Some(FontFamily::default())
     }
 
+    fn families(&mut self) -> Vec<FontFamily> {
+        Vec::new()
+    }
+
+    fn faces(&mut self, _family: &FontFamily) -> Vec<FontFaceInfo> {
+        Vec::new()
+    }
+
     fn load_font(&mut self, _data: &[u8]) -> Result<FontFamily> {
         Ok(FontFamily::default())
     }
 
+    fn font_metrics(
+        &mut self,
+        _family: &FontFamily,
+        _size: f64,
+        _weight: FontWeight,
+        _style: FontStyle,
+    ) -> FontMetrics {
+        FontMetrics::default()
+    }
+
     fn new_text_layout(&mut self, _text: impl TextStorage) -> TextLayoutBuilder {
         TextLayoutBuilder
     }
@@ -51,6 +70,42 @@ impl piet::TextLayoutBuilder for TextLayoutBuilder {
         self
     }
 
+    fn line_height(self, _height: piet::LineHeight) -> Self {
+        self
+    }
+
+    fn writing_mode(self, _mode: piet::WritingMode) -> Self {
+        self
+    }
+
+    fn max_lines(self, _lines: usize) -> Self {
+        self
+    }
+
+    fn overflow(self, _overflow: piet::TextOverflow) -> Self {
+        self
+    }
+
+    fn tab_stops(self, _stops: piet::TabStops) -> Self {
+        self
+    }
+
+    fn hyphens(self, _hyphens: piet::Hyphens) -> Self {
+        self
+    }
+
+    fn language(self, _language: &str) -> Self {
+        self
+    }
+
+    fn justification(self, _justification: piet::Justification) -> Self {
+        self
+    }
+
+    fn inline_object(self, _range: impl RangeBounds<usize>, _size: Size, _baseline: f64) -> Self {
+        self
+    }
+
     fn default_attribute(self, _attribute: impl Into<TextAttribute>) -> Self {
         self
     }
@@ -73,6 +128,10 @@ impl piet::TextLayoutBuilder for TextLayoutBuilder {
 pub struct TextLayout;
 
 impl piet::TextLayout for TextLayout {
+    fn set_max_width(&mut self, _width: f64) -> Result<()> {
+        unimplemented!()
+    }
+
     fn size(&self) -> Size {
         unimplemented!()
     }
@@ -108,6 +167,18 @@ impl piet::TextLayout for TextLayout {
         unimplemented!()
     }
 
+    fn glyph_runs(&self) -> Vec<GlyphRun> {
+        unimplemented!()
+    }
+
+    fn to_path(&self, _origin: impl Into<Point>) -> BezPath {
+        unimplemented!()
+    }
+
+    fn inline_object_rect(&self, _position: usize) -> Option<Rect> {
+        unimplemented!()
+    }
+
     fn text(&self) -> &str {
         unimplemented!()
     }
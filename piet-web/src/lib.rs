@@ -20,12 +20,14 @@ use web_sys::{
 
 use piet::kurbo::{Affine, PathEl, Point, Rect, Shape};
 
-use piet::util::unpremul;
+use piet::util::{self, unpremul};
 use piet::{
     Color, Error, FixedGradient, GradientStop, ImageFormat, InterpolationMode, IntoBrush, LineCap,
-    LineJoin, RenderContext, StrokeStyle,
+    LineJoin, RenderContext, StrokeStyle, TextLayout, TextPathOptions,
 };
 
+use unicode_segmentation::UnicodeSegmentation;
+
 pub use text::{WebFont, WebTextLayout, WebTextLayoutBuilder};
 
 pub struct WebRenderContext<'a> {
@@ -235,6 +237,52 @@ impl RenderContext for WebRenderContext<'_> {
         self.ctx.restore();
     }
 
+    fn draw_text_on_path(
+        &mut self,
+        layout: &Self::TextLayout,
+        path: impl Shape,
+        options: &TextPathOptions,
+    ) {
+        let lm = match layout.line_metrics.first() {
+            Some(lm) => lm,
+            None => return,
+        };
+        // the canvas API has no glyphs, so each grapheme is drawn on its own
+        let graphemes = layout.text[lm.range()]
+            .grapheme_indices(true)
+            .filter(|(_, grapheme)| !grapheme.ends_with(&['\n', '\r'][..]))
+            .map(|(idx, grapheme)| (lm.start_offset + idx, grapheme))
+            .collect::<Vec<_>>();
+        let clusters = graphemes
+            .iter()
+            .map(|(idx, grapheme)| {
+                let start = layout.hit_test_text_position(*idx).point.x;
+                let end = layout.hit_test_text_position(idx + grapheme.len()).point.x;
+                (start.min(end), (end - start).abs())
+            })
+            .collect::<Vec<_>>();
+        let baseline = lm.y_offset + lm.baseline;
+        let transforms = util::text_path_transforms(path, &clusters, baseline, options);
+
+        self.ctx.save();
+        self.ctx.set_font(&layout.font.get_font_string());
+        let brush = layout.color().make_brush(self, || layout.size().to_rect());
+        self.set_brush(&brush, true);
+        for (((_, grapheme), (x, _)), transform) in graphemes.iter().zip(&clusters).zip(transforms)
+        {
+            if let Some(transform) = transform {
+                self.ctx.save();
+                self.transform(transform);
+                let draw_grapheme = self.ctx.fill_text(grapheme, *x, baseline).wrap();
+                if let Err(e) = draw_grapheme {
+                    self.err = Err(e);
+                }
+                self.ctx.restore();
+            }
+        }
+        self.ctx.restore();
+    }
+
     fn save(&mut self) -> Result<(), Error> {
         self.ctx.save();
         Ok(())
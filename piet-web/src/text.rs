@@ -10,11 +10,12 @@ use std::rc::Rc;
 
 use web_sys::CanvasRenderingContext2d;
 
-use piet::kurbo::{Point, Rect, Size};
+use piet::kurbo::{BezPath, Point, Rect, Size};
 
 use piet::{
-    util, Color, Error, FontFamily, HitTestPoint, HitTestPosition, LineMetric, Text, TextAttribute,
-    TextLayout, TextLayoutBuilder, TextStorage,
+    util, Color, Error, FontFaceInfo, FontFamily, FontMetrics, GlyphRun, HitTestPoint,
+    HitTestPosition, Hyphens, Justification, LineHeight, LineMetric, TabStops, Text, TextAttribute,
+    TextLayout, TextLayoutBuilder, TextOverflow, TextStorage, WritingMode,
 };
 use unicode_segmentation::UnicodeSegmentation;
 
@@ -34,6 +35,7 @@ pub struct WebTextLayout {
     ctx: CanvasRenderingContext2d,
     pub(crate) font: WebFont,
     pub(crate) text: Rc<dyn TextStorage>,
+    line_height: LineHeight,
 
     // Calculated on build
     pub(crate) line_metrics: Vec<LineMetric>,
@@ -45,6 +47,10 @@ pub struct WebTextLayoutBuilder {
     ctx: CanvasRenderingContext2d,
     text: Rc<dyn TextStorage>,
     width: f64,
+    line_height: LineHeight,
+    writing_mode: WritingMode,
+    max_lines: Option<usize>,
+    has_inline_objects: bool,
     defaults: util::LayoutDefaults,
 }
 
@@ -65,10 +71,56 @@ impl Text for WebText {
         Some(FontFamily::new_unchecked(family_name))
     }
 
+    // browsers don't allow the installed fonts to be listed
+    fn families(&mut self) -> Vec<FontFamily> {
+        Vec::new()
+    }
+
+    fn faces(&mut self, _family: &FontFamily) -> Vec<FontFaceInfo> {
+        Vec::new()
+    }
+
     fn load_font(&mut self, _data: &[u8]) -> Result<FontFamily, Error> {
         Err(Error::MissingFeature)
     }
 
+    fn font_metrics(
+        &mut self,
+        family: &FontFamily,
+        size: f64,
+        weight: piet::FontWeight,
+        style: piet::FontStyle,
+    ) -> FontMetrics {
+        let font = WebFont::new(family.clone())
+            .with_size(size)
+            .with_weight(weight)
+            .with_style(style);
+        self.ctx.set_font(&font.get_font_string());
+        let ink_height = |text| {
+            self.ctx
+                .measure_text(text)
+                .map(|m| m.actual_bounding_box_ascent())
+                .unwrap_or(0.0)
+        };
+        // canvas doesn't expose the font's own metrics, so these are the
+        // estimates that layouts use for line heights.
+        let height = size * 1.2;
+        let x_height = ink_height("x");
+        let thickness = size / 16.0;
+        FontMetrics {
+            ascent: height * 0.8,
+            descent: height * 0.2,
+            line_gap: 0.0,
+            x_height,
+            cap_height: ink_height("H"),
+            underline_position: height * 0.1,
+            underline_thickness: thickness,
+            strikethrough_position: -(x_height + thickness) / 2.0,
+            strikethrough_thickness: thickness,
+            units_per_em: None,
+        }
+    }
+
     fn new_text_layout(&mut self, text: impl TextStorage) -> Self::TextLayoutBuilder {
         WebTextLayoutBuilder {
             // TODO: it's very likely possible to do this without cloning ctx, but
@@ -76,6 +128,10 @@ impl Text for WebText {
             ctx: self.ctx.clone(),
             text: Rc::new(text),
             width: f64::INFINITY,
+            line_height: LineHeight::default(),
+            writing_mode: WritingMode::default(),
+            max_lines: None,
+            has_inline_objects: false,
             defaults: Default::default(),
         }
     }
@@ -126,15 +182,32 @@ impl WebFont {
             FontStyle::Oblique(Some(angle)) => Cow::from(format!("oblique {}deg", angle)),
         };
         format!(
-            "{} {} {}px \"{}\"",
+            "{} {} {}px {}",
             style_str,
             self.weight,
             self.size,
-            self.family.name()
+            css_family_list(&self.family)
         )
     }
 }
 
+/// The CSS `font-family` value for a family or stack of families.
+fn css_family_list(family: &FontFamily) -> String {
+    family
+        .families()
+        .iter()
+        .map(|family| {
+            if family.is_generic() {
+                family.name().to_owned()
+            } else {
+                let name = family.name().replace('\\', "\\\\").replace('"', "\\\"");
+                format!("\"{}\"", name)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 impl TextLayoutBuilder for WebTextLayoutBuilder {
     type Out = WebTextLayout;
 
@@ -148,6 +221,51 @@ impl TextLayoutBuilder for WebTextLayoutBuilder {
         self
     }
 
+    fn line_height(mut self, height: LineHeight) -> Self {
+        self.line_height = height;
+        self
+    }
+
+    fn writing_mode(mut self, mode: WritingMode) -> Self {
+        self.writing_mode = mode;
+        self
+    }
+
+    fn max_lines(mut self, lines: usize) -> Self {
+        self.max_lines = Some(lines);
+        self
+    }
+
+    fn overflow(self, _overflow: TextOverflow) -> Self {
+        self
+    }
+
+    fn tab_stops(self, _stops: TabStops) -> Self {
+        self
+    }
+
+    fn hyphens(self, _hyphens: Hyphens) -> Self {
+        self
+    }
+
+    fn language(self, _language: &str) -> Self {
+        self
+    }
+
+    fn justification(self, _justification: Justification) -> Self {
+        self
+    }
+
+    fn inline_object(
+        mut self,
+        _range: impl RangeBounds<usize>,
+        _size: Size,
+        _baseline: f64,
+    ) -> Self {
+        self.has_inline_objects = true;
+        self
+    }
+
     fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
         self.defaults.set(attribute);
         self
@@ -163,8 +281,16 @@ impl TextLayoutBuilder for WebTextLayoutBuilder {
     }
 
     fn build(self) -> Result<Self::Out, Error> {
+        // vertical text, truncation and inline objects aren't supported yet
+        if self.writing_mode.is_vertical() || self.max_lines.is_some() || self.has_inline_objects {
+            return Err(Error::NotSupported);
+        }
         let font = WebFont::new(self.defaults.font)
-            .with_size(self.defaults.font_size)
+            .with_size(
+                self.defaults
+                    .baseline_shift
+                    .font_size(self.defaults.font_size),
+            )
             .with_weight(self.defaults.weight)
             .with_style(self.defaults.style);
 
@@ -172,6 +298,7 @@ impl TextLayoutBuilder for WebTextLayoutBuilder {
             ctx: self.ctx,
             font,
             text: self.text,
+            line_height: self.line_height,
             line_metrics: Vec::new(),
             size: Size::ZERO,
             color: self.defaults.fg_color,
@@ -189,6 +316,10 @@ impl fmt::Debug for WebTextLayoutBuilder {
 }
 
 impl TextLayout for WebTextLayout {
+    fn set_max_width(&mut self, width: f64) -> Result<(), Error> {
+        self.update_width(width)
+    }
+
     fn size(&self) -> Size {
         self.size
     }
@@ -291,6 +422,20 @@ impl TextLayout for WebTextLayout {
         let x_pos = hit_test_line_position(&self.ctx, line, line_position);
         HitTestPosition::new(Point::new(x_pos, y_pos), line_num)
     }
+
+    fn glyph_runs(&self) -> Vec<GlyphRun> {
+        // the canvas API draws strings, and never exposes the glyphs it uses
+        Vec::new()
+    }
+
+    fn to_path(&self, _origin: impl Into<Point>) -> BezPath {
+        // the canvas API has no access to glyph outlines
+        BezPath::new()
+    }
+
+    fn inline_object_rect(&self, _position: usize) -> Option<Rect> {
+        None
+    }
 }
 
 impl fmt::Debug for WebTextLayout {
@@ -314,8 +459,13 @@ impl WebTextLayout {
         self.ctx.set_font(&self.font.get_font_string());
         let new_width = new_width.into().unwrap_or(std::f64::INFINITY);
 
-        let line_metrics =
-            lines::calculate_line_metrics(&self.text, &self.ctx, new_width, self.font.size);
+        let line_metrics = lines::calculate_line_metrics(
+            &self.text,
+            &self.ctx,
+            new_width,
+            self.font.size,
+            self.line_height,
+        );
 
         let max_width = line_metrics
             .iter()
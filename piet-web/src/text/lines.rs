@@ -5,6 +5,7 @@
 // code in `piet` core doesn't really make sense as it's implementation specific.
 //
 
+use piet::{util, LineHeight};
 use web_sys::CanvasRenderingContext2d;
 use xi_unicode::LineBreakIterator;
 
@@ -17,6 +18,7 @@ pub(crate) fn calculate_line_metrics(
     ctx: &CanvasRenderingContext2d,
     width: f64,
     font_size: f64,
+    line_height: LineHeight,
 ) -> Vec<LineMetric> {
     // first pass, completely naive and inefficient. Check at every break to see if line longer
     // than width.
@@ -52,7 +54,7 @@ pub(crate) fn calculate_line_metrics(
     // Vertical measures constant across all lines for now (web text)
     // We use heuristics because we don't have access to web apis through web-sys yet.
     let height = font_size * 1.2;
-    let baseline = height * 0.8;
+    let (baseline, height) = util::apply_line_height(line_height, height * 0.8, height);
 
     for (line_break, is_hard_break) in LineBreakIterator::new(text) {
         if !is_hard_break {
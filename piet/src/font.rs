@@ -1,5 +1,6 @@
 //! Font families, weights, etcetera
 
+use std::fmt;
 use std::sync::Arc;
 
 /// A reference to a font family.
@@ -15,7 +16,14 @@ use std::sync::Arc;
 /// instead you should verify that the desired family exists, via the
 /// [`Text::font`] API.
 ///
+/// A `FontFamily` can also be an ordered stack of families, like the CSS
+/// `font-family` property, created with [`FontFamily::stack`] or
+/// [`FontFamily::from_css`]. Backends use the first family in the stack that
+/// is available, so a stack should generally end with a generic family.
+///
 /// [`Text::font`]: trait.Text.html#tymethod.font
+/// [`FontFamily::stack`]: #method.stack
+/// [`FontFamily::from_css`]: #method.from_css
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct FontFamily(FontFamilyInner);
 
@@ -31,6 +39,8 @@ pub enum FontFamilyInner {
     Monospace,
     SystemUi,
     Named(Arc<str>),
+    /// A stack of at least two families, none of which are stacks.
+    Stack(Arc<[FontFamily]>),
 }
 
 /// A font weight, represented as a value in the range 1..=1000.
@@ -43,6 +53,48 @@ pub enum FontFamilyInner {
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct FontWeight(u16);
 
+/// The width of a font, relative to the normal width of its family.
+///
+/// This is based on the [CSS `font-stretch`] property. If a family has no face
+/// with the requested width, the closest available width is used.
+///
+/// [CSS `font-stretch`]: https://developer.mozilla.org/en-US/docs/Web/CSS/font-stretch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FontStretch {
+    UltraCondensed,
+    ExtraCondensed,
+    Condensed,
+    SemiCondensed,
+    Normal,
+    SemiExpanded,
+    Expanded,
+    ExtraExpanded,
+    UltraExpanded,
+}
+
+/// An OpenType feature, such as tabular numerals, and its value.
+///
+/// This is identified by the feature's four-letter tag, such as `tnum`,
+/// `liga` or `ss01`. Most features are either on (1) or off (0); some, such
+/// as alternates, select one of several options.
+///
+/// Backends that do their own shaping apply these when shaping text; others
+/// may ignore them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FontFeature {
+    tag: [u8; 4],
+    value: u32,
+}
+
+/// A value for one of the axes of a variable font, such as `wght` or `opsz`.
+///
+/// Axes that a font does not have are ignored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FontVariation {
+    tag: [u8; 4],
+    value: f64,
+}
+
 /// A font style, which may be italic or regular.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum FontStyle {
@@ -55,6 +107,65 @@ pub enum FontStyle {
     Italic,
 }
 
+/// A face within a font family, as listed by [`Text::faces`].
+///
+/// [`Text::faces`]: trait.Text.html#tymethod.faces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FontFaceInfo {
+    /// The weight the face is designed for, such as [`FontWeight::BOLD`] for
+    /// a bold face.
+    ///
+    /// For a variable font, this is the weight of its default instance, not
+    /// the range it supports; backends that can't read that weight report
+    /// [`FontWeight::REGULAR`].
+    ///
+    /// [`FontWeight::BOLD`]: struct.FontWeight.html#associatedconstant.BOLD
+    /// [`FontWeight::REGULAR`]: struct.FontWeight.html#associatedconstant.REGULAR
+    pub weight: FontWeight,
+    /// Whether the face is italic; oblique faces are reported as italic.
+    pub style: FontStyle,
+    /// How condensed or expanded the face is; like `weight`, this is the
+    /// width of the default instance of a variable font, or
+    /// [`FontStretch::Normal`] if it can't be read.
+    ///
+    /// [`FontStretch::Normal`]: enum.FontStretch.html#variant.Normal
+    pub stretch: FontStretch,
+    /// Whether the face is a variable font, or a named instance of one.
+    pub is_variable: bool,
+}
+
+/// Metrics of a font at a particular size, from [`Text::font_metrics`].
+///
+/// Lengths are in the same units as the font size. Positions are offsets
+/// from the baseline to the top of the line they describe, and are positive
+/// below the baseline, like other y coordinates in piet.
+///
+/// [`Text::font_metrics`]: trait.Text.html#tymethod.font_metrics
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FontMetrics {
+    /// The distance from the baseline to the top of the font's tallest glyphs.
+    pub ascent: f64,
+    /// The distance from the baseline to the bottom of the font's lowest glyphs.
+    pub descent: f64,
+    /// The extra space the font recommends between lines.
+    pub line_gap: f64,
+    /// The height of lowercase letters such as 'x'.
+    pub x_height: f64,
+    /// The height of capital letters such as 'H'.
+    pub cap_height: f64,
+    /// The position of an underline.
+    pub underline_position: f64,
+    /// The thickness of an underline.
+    pub underline_thickness: f64,
+    /// The position of a strikethrough; this is generally negative.
+    pub strikethrough_position: f64,
+    /// The thickness of a strikethrough.
+    pub strikethrough_thickness: f64,
+    /// The number of design units per em in the font's tables, if the backend
+    /// has access to them.
+    pub units_per_em: Option<u16>,
+}
+
 impl FontFamily {
     /// A san-serif font, such as Arial or Helvetica.
     pub const SANS_SERIF: FontFamily = FontFamily(FontFamilyInner::SansSerif);
@@ -77,6 +188,85 @@ impl FontFamily {
         FontFamily(FontFamilyInner::Named(s.into()))
     }
 
+    /// Create a stack of font families, in order of preference.
+    ///
+    /// Stacks are flattened, so any stacks in `families` are replaced by
+    /// their members. A stack of a single family is just that family, and an
+    /// empty stack is the default family.
+    pub fn stack(families: impl IntoIterator<Item = FontFamily>) -> Self {
+        let mut flat: Vec<FontFamily> = Vec::new();
+        for family in families {
+            flat.extend(family.families().iter().cloned());
+        }
+        match flat.len() {
+            0 => FontFamily::default(),
+            1 => flat.pop().unwrap(),
+            _ => FontFamily(FontFamilyInner::Stack(flat.into())),
+        }
+    }
+
+    /// Parse a CSS `font-family` value, such as `"Inter, 'Noto Sans', sans-serif"`.
+    ///
+    /// Unquoted names are matched against the generic families (`serif`,
+    /// `sans-serif`, `monospace` and `system-ui`), ignoring case; any other
+    /// name is used as-is, without verifying that it exists. Returns `None`
+    /// if the value is empty or malformed.
+    pub fn from_css(css: &str) -> Option<Self> {
+        let mut families = Vec::new();
+        let mut chars = css.chars().peekable();
+        loop {
+            while chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+                chars.next();
+            }
+            let family = match chars.peek() {
+                Some(&quote) if quote == '"' || quote == '\'' => {
+                    chars.next();
+                    let mut name = String::new();
+                    loop {
+                        match chars.next()? {
+                            '\\' => name.push(chars.next()?),
+                            c if c == quote => break,
+                            c => name.push(c),
+                        }
+                    }
+                    while chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+                        chars.next();
+                    }
+                    if name.is_empty() {
+                        return None;
+                    }
+                    FontFamily::new_unchecked(name)
+                }
+                _ => {
+                    let mut name = String::new();
+                    while let Some(c) = chars.peek().copied().filter(|c| *c != ',') {
+                        name.push(c);
+                        chars.next();
+                    }
+                    // sequences of whitespace in unquoted names become a single space
+                    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+                    match name.to_ascii_lowercase().as_str() {
+                        "" => return None,
+                        "serif" => FontFamily::SERIF,
+                        "sans-serif" => FontFamily::SANS_SERIF,
+                        "monospace" => FontFamily::MONOSPACE,
+                        "system-ui" => FontFamily::SYSTEM_UI,
+                        _ => FontFamily::new_unchecked(name),
+                    }
+                }
+            };
+            families.push(family);
+            match chars.next() {
+                Some(',') => continue,
+                None => break,
+                Some(_) => return None,
+            }
+        }
+        Some(FontFamily::stack(families))
+    }
+
+    /// The name of this family; for a stack, this is the name of the first
+    /// family.
     pub fn name(&self) -> &str {
         match &self.0 {
             FontFamilyInner::Serif => "serif",
@@ -84,12 +274,30 @@ impl FontFamily {
             FontFamilyInner::SystemUi => "system-ui",
             FontFamilyInner::Monospace => "monospace",
             FontFamilyInner::Named(s) => &s,
+            FontFamilyInner::Stack(families) => families[0].name(),
         }
     }
 
     /// Returns `true` if this is a generic font family.
     pub fn is_generic(&self) -> bool {
-        !matches!(self.0, FontFamilyInner::Named(_))
+        !matches!(
+            self.0,
+            FontFamilyInner::Named(_) | FontFamilyInner::Stack(_)
+        )
+    }
+
+    /// Returns `true` if this is a stack of font families.
+    pub fn is_stack(&self) -> bool {
+        matches!(self.0, FontFamilyInner::Stack(_))
+    }
+
+    /// The families in this stack, in order of preference; if this is not a
+    /// stack, this is just the family itself.
+    pub fn families(&self) -> &[FontFamily] {
+        match &self.0 {
+            FontFamilyInner::Stack(families) => families,
+            _ => std::slice::from_ref(self),
+        }
     }
 
     /// Backend-only API; access the inner `FontFamilyInner` enum.
@@ -137,6 +345,164 @@ impl FontWeight {
     }
 }
 
+impl FontStretch {
+    /// The width as a percentage of the normal width, as in CSS.
+    pub fn to_percentage(self) -> f64 {
+        match self {
+            FontStretch::UltraCondensed => 50.0,
+            FontStretch::ExtraCondensed => 62.5,
+            FontStretch::Condensed => 75.0,
+            FontStretch::SemiCondensed => 87.5,
+            FontStretch::Normal => 100.0,
+            FontStretch::SemiExpanded => 112.5,
+            FontStretch::Expanded => 125.0,
+            FontStretch::ExtraExpanded => 150.0,
+            FontStretch::UltraExpanded => 200.0,
+        }
+    }
+
+    /// The OpenType width class (`usWidthClass`) of this stretch, from 1
+    /// for ultra-condensed to 9 for ultra-expanded.
+    pub fn to_width_class(self) -> u16 {
+        self as u16 + 1
+    }
+
+    /// The stretch closest to a width given as a percentage of the normal
+    /// width, as in CSS.
+    pub fn from_percentage(percentage: f64) -> FontStretch {
+        let distance = |stretch: FontStretch| (stretch.to_percentage() - percentage).abs();
+        (1..=9)
+            .map(FontStretch::from_width_class)
+            .fold(FontStretch::Normal, |best, stretch| {
+                if distance(stretch) < distance(best) {
+                    stretch
+                } else {
+                    best
+                }
+            })
+    }
+
+    /// The stretch for an OpenType width class; values outside of the range
+    /// 1..=9 are clamped.
+    pub fn from_width_class(class: u16) -> FontStretch {
+        match class {
+            0 | 1 => FontStretch::UltraCondensed,
+            2 => FontStretch::ExtraCondensed,
+            3 => FontStretch::Condensed,
+            4 => FontStretch::SemiCondensed,
+            5 => FontStretch::Normal,
+            6 => FontStretch::SemiExpanded,
+            7 => FontStretch::Expanded,
+            8 => FontStretch::ExtraExpanded,
+            _ => FontStretch::UltraExpanded,
+        }
+    }
+}
+
+impl FontFeature {
+    /// Create a feature with the given tag and value.
+    pub const fn new(tag: &[u8; 4], value: u32) -> FontFeature {
+        FontFeature { tag: *tag, value }
+    }
+
+    /// Turn on the feature with the given tag.
+    pub const fn enable(tag: &[u8; 4]) -> FontFeature {
+        FontFeature::new(tag, 1)
+    }
+
+    /// Turn off the feature with the given tag.
+    pub const fn disable(tag: &[u8; 4]) -> FontFeature {
+        FontFeature::new(tag, 0)
+    }
+
+    /// Parse a feature written as a tag, which turns it on, or as a tag and
+    /// a value, such as `ss01=2`.
+    ///
+    /// Returns `None` if the tag is not four ASCII characters, or the value
+    /// is not a non-negative integer.
+    pub fn parse(setting: &str) -> Option<FontFeature> {
+        let (tag, value) = split_setting(setting)?;
+        let value = match value {
+            Some(value) => value.parse().ok()?,
+            None => 1,
+        };
+        Some(FontFeature { tag, value })
+    }
+
+    /// The feature's tag.
+    pub const fn tag(self) -> [u8; 4] {
+        self.tag
+    }
+
+    /// The feature's value.
+    pub const fn value(self) -> u32 {
+        self.value
+    }
+}
+
+impl FontVariation {
+    /// Create a variation with the given axis tag and value.
+    pub const fn new(tag: &[u8; 4], value: f64) -> FontVariation {
+        FontVariation { tag: *tag, value }
+    }
+
+    /// Parse a variation written as a tag and a value, such as `wght=550`.
+    ///
+    /// Returns `None` if the tag is not four ASCII characters, or there is
+    /// no valid value.
+    pub fn parse(setting: &str) -> Option<FontVariation> {
+        let (tag, value) = split_setting(setting)?;
+        let value = value?
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite())?;
+        Some(FontVariation { tag, value })
+    }
+
+    /// The axis tag.
+    pub const fn tag(self) -> [u8; 4] {
+        self.tag
+    }
+
+    /// The value for the axis.
+    pub const fn value(self) -> f64 {
+        self.value
+    }
+}
+
+/// Split a `tag` or `tag=value` setting into its tag and value.
+fn split_setting(setting: &str) -> Option<([u8; 4], Option<&str>)> {
+    let mut parts = setting.splitn(2, '=');
+    let tag = parts.next()?.trim();
+    let value = parts.next().map(str::trim);
+    let bytes = tag.as_bytes();
+    if bytes.len() != 4 || !bytes.iter().all(|b| b.is_ascii_graphic()) {
+        return None;
+    }
+    Some(([bytes[0], bytes[1], bytes[2], bytes[3]], value))
+}
+
+impl FontMetrics {
+    /// The distance between the baselines of consecutive lines of this font.
+    pub fn line_height(&self) -> f64 {
+        self.ascent + self.descent + self.line_gap
+    }
+}
+
+impl fmt::Display for FontFeature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tag = String::from_utf8_lossy(&self.tag);
+        write!(f, "{}={}", tag, self.value)
+    }
+}
+
+impl fmt::Display for FontVariation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tag = String::from_utf8_lossy(&self.tag);
+        write!(f, "{}={}", tag, self.value)
+    }
+}
+
 impl Default for FontFamily {
     fn default() -> Self {
         FontFamily::SYSTEM_UI
@@ -149,8 +515,126 @@ impl Default for FontWeight {
     }
 }
 
+impl Default for FontStretch {
+    fn default() -> Self {
+        FontStretch::Normal
+    }
+}
+
 impl Default for FontStyle {
     fn default() -> Self {
         FontStyle::Regular
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_css_stack() {
+        let family =
+            FontFamily::from_css("Inter, 'Noto Sans',  Source   Han Sans, SANS-SERIF").unwrap();
+        assert_eq!(
+            family.families(),
+            &[
+                FontFamily::new_unchecked("Inter"),
+                FontFamily::new_unchecked("Noto Sans"),
+                FontFamily::new_unchecked("Source Han Sans"),
+                FontFamily::SANS_SERIF,
+            ]
+        );
+        assert!(family.is_stack());
+        assert_eq!(family.name(), "Inter");
+
+        // quoted generic names are family names
+        let family = FontFamily::from_css(r#""serif""#).unwrap();
+        assert_eq!(family, FontFamily::new_unchecked("serif"));
+        assert!(!family.is_generic());
+        let family = FontFamily::from_css(r#"'It\'s' , monospace"#).unwrap();
+        assert_eq!(
+            family.families(),
+            &[FontFamily::new_unchecked("It's"), FontFamily::MONOSPACE]
+        );
+
+        assert_eq!(FontFamily::from_css(""), None);
+        assert_eq!(FontFamily::from_css("Inter,"), None);
+        assert_eq!(FontFamily::from_css("Inter,,serif"), None);
+        assert_eq!(FontFamily::from_css("'Inter"), None);
+        assert_eq!(FontFamily::from_css("'Inter' Sans"), None);
+    }
+
+    #[test]
+    fn parse_settings() {
+        assert_eq!(
+            FontFeature::parse("tnum"),
+            Some(FontFeature::enable(b"tnum"))
+        );
+        assert_eq!(
+            FontFeature::parse(" ss01 = 2 "),
+            Some(FontFeature::new(b"ss01", 2))
+        );
+        assert_eq!(
+            FontFeature::parse("liga=0"),
+            Some(FontFeature::disable(b"liga"))
+        );
+        assert_eq!(FontFeature::parse("tnu"), None);
+        assert_eq!(FontFeature::parse("tnum=on"), None);
+        assert_eq!(FontFeature::parse("tnum=-1"), None);
+
+        assert_eq!(
+            FontVariation::parse("wght=550"),
+            Some(FontVariation::new(b"wght", 550.0))
+        );
+        assert_eq!(
+            FontVariation::parse("opsz=10.5"),
+            Some(FontVariation::new(b"opsz", 10.5))
+        );
+        assert_eq!(FontVariation::parse("wght"), None);
+        assert_eq!(FontVariation::parse("wdth=wide"), None);
+
+        assert_eq!(FontFeature::new(b"ss01", 2).to_string(), "ss01=2");
+        assert_eq!(FontVariation::new(b"wght", 550.0).to_string(), "wght=550");
+    }
+
+    #[test]
+    fn flatten_stacks() {
+        let inner = FontFamily::stack(vec![
+            FontFamily::new_unchecked("Inter"),
+            FontFamily::SYSTEM_UI,
+        ]);
+        let family = FontFamily::stack(vec![inner, FontFamily::SERIF]);
+        assert_eq!(family.families().len(), 3);
+        assert_eq!(
+            FontFamily::stack(vec![FontFamily::MONOSPACE]),
+            FontFamily::MONOSPACE
+        );
+        assert_eq!(FontFamily::stack(Vec::new()), FontFamily::default());
+        assert_eq!(FontFamily::SERIF.families(), &[FontFamily::SERIF]);
+    }
+
+    #[test]
+    fn stretch_width_classes() {
+        for class in 1..=9 {
+            assert_eq!(FontStretch::from_width_class(class).to_width_class(), class);
+        }
+        assert_eq!(FontStretch::Normal.to_width_class(), 5);
+        assert_eq!(
+            FontStretch::from_width_class(0),
+            FontStretch::UltraCondensed
+        );
+        assert_eq!(
+            FontStretch::from_width_class(20),
+            FontStretch::UltraExpanded
+        );
+        assert_eq!(
+            FontStretch::from_percentage(87.0),
+            FontStretch::SemiCondensed
+        );
+        assert_eq!(
+            FontStretch::from_percentage(300.0),
+            FontStretch::UltraExpanded
+        );
+        assert_eq!(FontStretch::from_percentage(f64::NAN), FontStretch::Normal);
+    }
+}
@@ -3,12 +3,13 @@
 use std::borrow::Cow;
 use std::ops::RangeBounds;
 
-use kurbo::{Affine, Point, Rect, Shape, Size};
+use kurbo::{Affine, BezPath, Point, Rect, Shape, Size};
 
 use crate::{
-    Color, Error, FixedGradient, FontFamily, HitTestPoint, HitTestPosition, ImageFormat,
-    InterpolationMode, IntoBrush, LineMetric, RenderContext, StrokeStyle, Text, TextAttribute,
-    TextLayout, TextLayoutBuilder, TextStorage,
+    Color, Error, FixedGradient, FontFaceInfo, FontFamily, FontMetrics, FontStyle, FontWeight,
+    GlyphRun, HitTestPoint, HitTestPosition, ImageFormat, InterpolationMode, IntoBrush, LineMetric,
+    RenderContext, StrokeStyle, Text, TextAttribute, TextLayout, TextLayoutBuilder,
+    TextPathOptions, TextStorage,
 };
 
 /// A render context that doesn't render.
@@ -84,6 +85,14 @@ impl RenderContext for NullRenderContext {
 
     fn draw_text(&mut self, _layout: &Self::TextLayout, _pos: impl Into<Point>) {}
 
+    fn draw_text_on_path(
+        &mut self,
+        _layout: &Self::TextLayout,
+        _path: impl Shape,
+        _options: &TextPathOptions,
+    ) {
+    }
+
     fn save(&mut self) -> Result<(), Error> {
         Ok(())
     }
@@ -135,6 +144,16 @@ impl Text for NullText {
         Ok(FontFamily::default())
     }
 
+    fn font_metrics(
+        &mut self,
+        _family: &FontFamily,
+        _size: f64,
+        _weight: FontWeight,
+        _style: FontStyle,
+    ) -> FontMetrics {
+        FontMetrics::default()
+    }
+
     fn new_text_layout(&mut self, _text: impl TextStorage) -> Self::TextLayoutBuilder {
         NullTextLayoutBuilder
     }
@@ -142,6 +161,14 @@ impl Text for NullText {
     fn font_family(&mut self, _family_name: &str) -> Option<FontFamily> {
         Some(FontFamily::default())
     }
+
+    fn families(&mut self) -> Vec<FontFamily> {
+        Vec::new()
+    }
+
+    fn faces(&mut self, _family: &FontFamily) -> Vec<FontFaceInfo> {
+        Vec::new()
+    }
 }
 
 impl TextLayoutBuilder for NullTextLayoutBuilder {
@@ -155,6 +182,42 @@ impl TextLayoutBuilder for NullTextLayoutBuilder {
         self
     }
 
+    fn line_height(self, _height: crate::LineHeight) -> Self {
+        self
+    }
+
+    fn writing_mode(self, _mode: crate::WritingMode) -> Self {
+        self
+    }
+
+    fn max_lines(self, _lines: usize) -> Self {
+        self
+    }
+
+    fn overflow(self, _overflow: crate::TextOverflow) -> Self {
+        self
+    }
+
+    fn tab_stops(self, _stops: crate::TabStops) -> Self {
+        self
+    }
+
+    fn hyphens(self, _hyphens: crate::Hyphens) -> Self {
+        self
+    }
+
+    fn language(self, _language: &str) -> Self {
+        self
+    }
+
+    fn justification(self, _justification: crate::Justification) -> Self {
+        self
+    }
+
+    fn inline_object(self, _range: impl RangeBounds<usize>, _size: Size, _baseline: f64) -> Self {
+        self
+    }
+
     fn default_attribute(self, _attribute: impl Into<TextAttribute>) -> Self {
         self
     }
@@ -173,6 +236,10 @@ impl TextLayoutBuilder for NullTextLayoutBuilder {
 }
 
 impl TextLayout for NullTextLayout {
+    fn set_max_width(&mut self, _width: f64) -> Result<(), Error> {
+        Ok(())
+    }
+
     fn size(&self) -> Size {
         Size::ZERO
     }
@@ -205,6 +272,18 @@ impl TextLayout for NullTextLayout {
         HitTestPosition::default()
     }
 
+    fn glyph_runs(&self) -> Vec<GlyphRun> {
+        Vec::new()
+    }
+
+    fn to_path(&self, _origin: impl Into<Point>) -> BezPath {
+        BezPath::new()
+    }
+
+    fn inline_object_rect(&self, _position: usize) -> Option<Rect> {
+        None
+    }
+
     fn text(&self) -> &str {
         ""
     }
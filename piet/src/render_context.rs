@@ -6,7 +6,7 @@ use kurbo::{Affine, Point, Rect, Shape};
 
 use crate::{
     Color, Error, FixedGradient, FixedLinearGradient, FixedRadialGradient, LinearGradient,
-    RadialGradient, StrokeStyle, Text, TextLayout,
+    RadialGradient, StrokeStyle, Text, TextLayout, TextPathOptions,
 };
 
 /// A requested interpolation mode for drawing images.
@@ -16,6 +16,24 @@ pub enum InterpolationMode {
     NearestNeighbor,
     /// Use bilinear interpolation.
     Bilinear,
+    /// Use bicubic interpolation.
+    ///
+    /// This gives smoother results than bilinear interpolation when an image
+    /// is enlarged, at some additional cost.
+    Bicubic,
+    /// Use the highest quality interpolation available on the platform.
+    ///
+    /// When an image is drawn smaller than its native size, the image is
+    /// prefiltered (for instance with mipmaps or a box filter) so that detail
+    /// is averaged instead of skipped; this avoids the aliasing that occurs
+    /// when a large image is drawn as a thumbnail. When an image is enlarged
+    /// this behaves like [`Bicubic`].
+    ///
+    /// This is the most expensive mode, and may require allocating temporary
+    /// images.
+    ///
+    /// [`Bicubic`]: #variant.Bicubic
+    HighQuality,
 }
 
 /// The pixel format for bitmap images.
@@ -125,6 +143,24 @@ where
     /// the text. Note: this is true even if the text is right-to-left.
     fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>);
 
+    /// Draw the first line of a text layout along a path.
+    ///
+    /// Each cluster of glyphs is drawn upright on the path, with its
+    /// baseline along the direction of the path at its center. Where the
+    /// text goes on the path is set by `options`; see [`TextPathOptions`].
+    ///
+    /// Only the glyphs of the layout are drawn, not its backgrounds or
+    /// decorations. The layout's own alignment and any lines after the
+    /// first are ignored.
+    ///
+    /// [`TextPathOptions`]: struct.TextPathOptions.html
+    fn draw_text_on_path(
+        &mut self,
+        layout: &Self::TextLayout,
+        path: impl Shape,
+        options: &TextPathOptions,
+    );
+
     /// Save the context state.
     ///
     /// Pushes the current context state onto a stack, to be popped by
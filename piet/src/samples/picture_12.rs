@@ -15,7 +15,9 @@ const HILIGHT_COLOR: Color = Color::rgba8(255, 242, 54, 96);
 pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
     rc.clear(Color::WHITE);
     let text = rc.text();
-    let font2 = text.font_family("Courier New").unwrap();
+    let font2 = text
+        .font_family("Courier New")
+        .unwrap_or(FontFamily::MONOSPACE);
     let layout = text
         .new_text_layout(TEXT)
         .max_width(200.0)
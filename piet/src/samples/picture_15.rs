@@ -0,0 +1,51 @@
+//! Images scaled up and down with each interpolation mode.
+
+use crate::kurbo::{Rect, Size};
+use crate::{Color, Error, ImageFormat, InterpolationMode, RenderContext};
+
+pub const SIZE: Size = Size::new(400., 200.);
+
+pub fn draw(rc: &mut impl RenderContext) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+
+    let small = rc.make_image(32, 32, &make_image_data(32, 32), ImageFormat::RgbaSeparate)?;
+    let large = rc.make_image(
+        512,
+        512,
+        &make_image_data(512, 512),
+        ImageFormat::RgbaSeparate,
+    )?;
+
+    // the harness draws at 2x, so everything fits in 200x100 points; at that
+    // scale, each column draws the small image 2.5x larger, and the large one
+    // 6.4x smaller
+    let mut x = 5.0;
+    for &mode in &[
+        InterpolationMode::NearestNeighbor,
+        InterpolationMode::Bilinear,
+        InterpolationMode::Bicubic,
+        InterpolationMode::HighQuality,
+    ] {
+        rc.draw_image(&small, Rect::new(x, 5.0, x + 40.0, 45.0), mode);
+        rc.draw_image(&large, Rect::new(x, 55.0, x + 40.0, 95.0), mode);
+        x += 50.0;
+    }
+    Ok(())
+}
+
+/// A checkerboard of one-pixel squares over a color gradient, so that each
+/// mode's smoothing is easy to tell apart.
+fn make_image_data(width: usize, height: usize) -> Vec<u8> {
+    let mut result = vec![0; width * height * 4];
+    for y in 0..height {
+        for x in 0..width {
+            let ix = (y * width + x) * 4;
+            let shade = if (x + y) % 2 == 0 { 255 } else { 64 };
+            result[ix] = (x * shade / (width - 1)) as u8;
+            result[ix + 1] = (y * shade / (height - 1)) as u8;
+            result[ix + 2] = (shade - x * shade / (width - 1)) as u8;
+            result[ix + 3] = 255;
+        }
+    }
+    result
+}
@@ -2,8 +2,11 @@
 
 use std::ops::{Range, RangeBounds};
 
-use crate::kurbo::{Point, Rect, Size};
-use crate::{Color, Error, FontFamily, FontStyle, FontWeight};
+use crate::kurbo::{BezPath, Point, Rect, Size, Vec2};
+use crate::{
+    Color, Error, FontFaceInfo, FontFamily, FontFeature, FontMetrics, FontStretch, FontStyle,
+    FontVariation, FontWeight,
+};
 
 /// The Piet text API.
 ///
@@ -39,6 +42,40 @@ pub trait Text: Clone {
     /// [`FontFamily`]: struct.FontFamily.html
     fn font_family(&mut self, family_name: &str) -> Option<FontFamily>;
 
+    /// Return the names of the font families available for layouts, sorted
+    /// by name.
+    ///
+    /// This includes the families of fonts loaded with [`load_font`], but
+    /// not the generic families.
+    ///
+    /// [`load_font`]: #tymethod.load_font
+    fn families(&mut self) -> Vec<FontFamily>;
+
+    /// Return the faces in a font family, such as its regular, bold and
+    /// italic faces, sorted by stretch, style and weight.
+    ///
+    /// This is empty if the family isn't available. Generic families and
+    /// stacks don't have faces of their own, so it is also empty for them.
+    ///
+    /// # Examples
+    ///
+    /// Listing the weights of each family, for a font picker.
+    ///
+    /// ```
+    /// # use piet::*;
+    /// # let mut ctx = NullRenderContext::new();
+    /// # let text = ctx.text();
+    /// for family in text.families() {
+    ///     let weights = text
+    ///         .faces(&family)
+    ///         .iter()
+    ///         .map(|face| face.weight.to_raw())
+    ///         .collect::<Vec<_>>();
+    ///     println!("{}: {:?}", family.name(), weights);
+    /// }
+    /// ```
+    fn faces(&mut self, family: &FontFamily) -> Vec<FontFaceInfo>;
+
     /// Load the provided font data and make it available for use.
     ///
     /// This method takes font data (such as the contents of a file on disk) and
@@ -92,6 +129,36 @@ pub trait Text: Clone {
     /// [`FontFamily`]: struct.FontFamily.html
     fn load_font(&mut self, data: &[u8]) -> Result<FontFamily, Error>;
 
+    /// Return the metrics of the font that layouts would use for text in
+    /// `family` with the given size, weight and style.
+    ///
+    /// This is useful for aligning other content with text, or sizing text
+    /// to fit a box, without building a [`TextLayout`].
+    ///
+    /// If `family` is not available, these are the metrics of the font that
+    /// would be used in its place.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use piet::*;
+    /// # let mut ctx = NullRenderContext::new();
+    /// # let text = ctx.text();
+    /// let metrics = text.font_metrics(&FontFamily::SERIF, 16.0, FontWeight::REGULAR, FontStyle::Regular);
+    /// // an icon that sits on the baseline and is as tall as the capitals
+    /// let baseline = 40.0;
+    /// let icon = kurbo::Rect::new(0.0, baseline - metrics.cap_height, metrics.cap_height, baseline);
+    /// ```
+    ///
+    /// [`TextLayout`]: trait.TextLayout.html
+    fn font_metrics(
+        &mut self,
+        family: &FontFamily,
+        size: f64,
+        weight: FontWeight,
+        style: FontStyle,
+    ) -> FontMetrics;
+
     /// Create a new layout object to display the provided `text`.
     ///
     /// The returned object is a [`TextLayoutBuilder`]; methods on that type
@@ -155,10 +222,91 @@ pub enum TextAttribute {
     ///
     /// [`FontStyle`]: enum.FontStyle.html
     Style(FontStyle),
+    /// The [`FontStretch`], from condensed to expanded.
+    ///
+    /// [`FontStretch`]: enum.FontStretch.html
+    Stretch(FontStretch),
     /// Underline.
     Underline(bool),
+    /// The [`UnderlineStyle`] of underlined text.
+    ///
+    /// This has no effect on text that is not also underlined.
+    ///
+    /// [`UnderlineStyle`]: enum.UnderlineStyle.html
+    UnderlineStyle(UnderlineStyle),
+    /// Overline.
+    Overline(bool),
     /// Strikethrough.
     Strikethrough(bool),
+    /// The color of the underline, overline and strikethrough.
+    ///
+    /// If this is not set, decorations use the text color.
+    DecorationColor(crate::Color),
+    /// A color painted behind the text.
+    ///
+    /// This covers the full height of each line the text is on, in the same
+    /// areas as [`TextLayout::rects_for_range`].
+    ///
+    /// [`TextLayout::rects_for_range`]: trait.TextLayout.html#method.rects_for_range
+    BackgroundColor(crate::Color),
+    /// Extra space added after each grapheme, in display points.
+    ///
+    /// Negative values bring graphemes closer together. This is also known
+    /// as tracking.
+    LetterSpacing(f64),
+    /// Extra space added to each word separator, such as a space, in display
+    /// points.
+    ///
+    /// This is in addition to any letter spacing.
+    WordSpacing(f64),
+    /// The OpenType features to apply, such as tabular numerals.
+    ///
+    /// These replace, rather than add to, any features set for the text by
+    /// another attribute. Features set here take precedence over the ones
+    /// a backend would otherwise choose, such as turning off ligatures when
+    /// letters are spaced.
+    FontFeatures(Vec<FontFeature>),
+    /// The values for the axes of a variable font.
+    ///
+    /// These replace, rather than add to, any variations set for the text by
+    /// another attribute. An axis set here takes precedence over the value
+    /// a backend would choose for it, such as the weight.
+    FontVariations(Vec<FontVariation>),
+    /// The [`BaselineShift`], for superscripts and subscripts.
+    ///
+    /// [`BaselineShift`]: enum.BaselineShift.html
+    BaselineShift(BaselineShift),
+}
+
+/// A vertical shift of text from the baseline.
+///
+/// Superscripts and subscripts are also drawn at a smaller size; the amount
+/// they are shifted by, like an explicit offset, is relative to the size the
+/// text would otherwise have.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BaselineShift {
+    /// Text sits on the baseline.
+    Normal,
+    /// Raised text at a reduced size, such as the "2" in "m²".
+    Superscript,
+    /// Lowered text at a reduced size, such as the "2" in "H₂O".
+    Subscript,
+    /// Text raised by the given distance, in display points; negative values
+    /// lower it. The size of the text is unchanged.
+    Offset(f64),
+}
+
+/// The style of an underline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnderlineStyle {
+    /// A single solid line.
+    Single,
+    /// Two solid lines.
+    Double,
+    /// A line of dots.
+    Dotted,
+    /// A wavy line, such as is used to mark spelling errors.
+    Wavy,
 }
 
 /// A trait for laying out text.
@@ -179,6 +327,114 @@ pub trait TextLayoutBuilder: Sized {
     /// [`TextAlignment`]: enum.TextAlignment.html
     fn alignment(self, alignment: TextAlignment) -> Self;
 
+    /// Set the [`LineHeight`] of the lines in this layout.
+    ///
+    /// As with the CSS `line-height` property, the difference from a line's
+    /// natural height is split evenly above and below its text. The default
+    /// is [`LineHeight::Normal`].
+    ///
+    /// [`LineHeight`]: enum.LineHeight.html
+    /// [`LineHeight::Normal`]: enum.LineHeight.html#variant.Normal
+    fn line_height(self, height: LineHeight) -> Self;
+
+    /// Set the [`WritingMode`] of this layout.
+    ///
+    /// In vertical modes, lines become columns: CJK and other characters
+    /// that are upright in vertical text are drawn upright, and other text
+    /// is rotated 90° clockwise. The width passed to [`max_width`] limits
+    /// the height of the columns, and the [`LineMetric`]s describe them.
+    /// The default is [`WritingMode::HorizontalTb`].
+    ///
+    /// Backends that don't support vertical text return
+    /// [`Error::NotSupported`] from [`build`] when a vertical mode is set.
+    ///
+    /// [`WritingMode`]: enum.WritingMode.html
+    /// [`WritingMode::HorizontalTb`]: enum.WritingMode.html#variant.HorizontalTb
+    /// [`max_width`]: #tymethod.max_width
+    /// [`LineMetric`]: struct.LineMetric.html
+    /// [`Error::NotSupported`]: enum.Error.html#variant.NotSupported
+    /// [`build`]: #tymethod.build
+    fn writing_mode(self, mode: WritingMode) -> Self;
+
+    /// Set the maximum number of lines in this layout.
+    ///
+    /// Text that doesn't fit in `lines` lines is cut off, and shown as set
+    /// by [`overflow`]; the last line is also cut off if it is wider than
+    /// [`max_width`]. At least one line is always shown. By default, the
+    /// number of lines is not limited.
+    ///
+    /// Backends that don't support truncating text return
+    /// [`Error::NotSupported`] from [`build`] when a maximum is set.
+    ///
+    /// [`overflow`]: #tymethod.overflow
+    /// [`max_width`]: #tymethod.max_width
+    /// [`Error::NotSupported`]: enum.Error.html#variant.NotSupported
+    /// [`build`]: #tymethod.build
+    fn max_lines(self, lines: usize) -> Self;
+
+    /// Set how text that is cut off by [`max_lines`] is shown.
+    ///
+    /// When the text is replaced by an ellipsis, the range of the last line
+    /// is extended to cover the hidden text; positions in the hidden text are
+    /// at the start of the ellipsis, and hit testing the ellipsis finds the
+    /// start of the hidden text. The default is [`TextOverflow::Clip`].
+    ///
+    /// [`max_lines`]: #tymethod.max_lines
+    /// [`TextOverflow::Clip`]: enum.TextOverflow.html#variant.Clip
+    fn overflow(self, overflow: TextOverflow) -> Self;
+
+    /// Set the [`TabStops`] that tab characters in this layout advance to.
+    ///
+    /// By default, a tab is as wide as its glyph in the font. Backends that
+    /// don't support tab stops ignore them.
+    ///
+    /// [`TabStops`]: struct.TabStops.html
+    fn tab_stops(self, stops: TabStops) -> Self;
+
+    /// Set whether words are [`Hyphens`] at the ends of lines.
+    ///
+    /// The default is [`Hyphens::Manual`]. Backends that can't hyphenate
+    /// words themselves treat [`Hyphens::Auto`] like [`Hyphens::Manual`].
+    ///
+    /// [`Hyphens`]: enum.Hyphens.html
+    /// [`Hyphens::Manual`]: enum.Hyphens.html#variant.Manual
+    /// [`Hyphens::Auto`]: enum.Hyphens.html#variant.Auto
+    fn hyphens(self, hyphens: Hyphens) -> Self;
+
+    /// Set the language of the text, as a BCP 47 tag such as `"en-US"`.
+    ///
+    /// This chooses the rules for automatic hyphenation; backends may also
+    /// use it when picking fonts and glyphs.
+    fn language(self, language: &str) -> Self;
+
+    /// Set how the extra space in lines is distributed when they are
+    /// [`TextAlignment::Justified`].
+    ///
+    /// The default is [`Justification::Auto`]. Backends that can't choose
+    /// where the space goes ignore this.
+    ///
+    /// [`TextAlignment::Justified`]: enum.TextAlignment.html#variant.Justified
+    /// [`Justification::Auto`]: enum.Justification.html#variant.Auto
+    fn justification(self, justification: Justification) -> Self;
+
+    /// Reserve space in the text flow for an object, such as an image or a
+    /// widget, in place of the text in `range`.
+    ///
+    /// The object is `size` big, and `baseline` is the distance from its top
+    /// to the baseline it sits on. It is laid out like a single character:
+    /// lines can break before and after it, but not inside `range`, which is
+    /// usually a single U+FFFC OBJECT REPLACEMENT CHARACTER. The text itself
+    /// isn't drawn; the caller draws the object at the rect that
+    /// [`TextLayout::inline_object_rect`] returns.
+    ///
+    /// Backends that don't support inline objects return
+    /// [`Error::NotSupported`] from [`build`].
+    ///
+    /// [`TextLayout::inline_object_rect`]: trait.TextLayout.html#tymethod.inline_object_rect
+    /// [`Error::NotSupported`]: enum.Error.html#variant.NotSupported
+    /// [`build`]: #tymethod.build
+    fn inline_object(self, range: impl RangeBounds<usize>, size: Size, baseline: f64) -> Self;
+
     /// A convenience method for setting the default font family and size.
     ///
     /// # Examples
@@ -289,9 +545,97 @@ pub enum TextAlignment {
     /// left edge in right-to-left scripts.
     End,
     Center,
+    /// Lines are stretched to fill the maximum width, except for the last
+    /// line of each paragraph, which is aligned like `Start`. How the extra
+    /// space is distributed is set with [`TextLayoutBuilder::justification`].
+    ///
+    /// [`TextLayoutBuilder::justification`]: trait.TextLayoutBuilder.html#tymethod.justification
     Justified,
 }
 
+/// Where the extra space in the lines of a [`TextLayout`] with
+/// [`TextAlignment::Justified`] goes, as with the CSS `text-justify`
+/// property.
+///
+/// [`TextLayout`]: trait.TextLayout.html
+/// [`TextAlignment::Justified`]: enum.TextAlignment.html#variant.Justified
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Justification {
+    /// Between words, and between the characters of scripts that don't
+    /// separate words with spaces, such as Chinese and Japanese.
+    Auto,
+    /// Only between words.
+    InterWord,
+    /// Between all characters.
+    InterCharacter,
+}
+
+/// Whether words in a [`TextLayout`] are hyphenated when they are broken
+/// across lines, as with the CSS `hyphens` property.
+///
+/// [`TextLayout`]: trait.TextLayout.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hyphens {
+    /// Words are only broken at spaces and other break opportunities, and
+    /// soft hyphens (U+00AD) are never shown.
+    None,
+    /// Words are also broken at soft hyphens, which are shown as a hyphen
+    /// when a line ends at one.
+    Manual,
+    /// Words are also broken where the hyphenation rules of the layout's
+    /// language allow, as well as at soft hyphens.
+    Auto,
+}
+
+/// The direction of the lines in a [`TextLayout`], and the direction in which
+/// they follow each other, as with the CSS `writing-mode` property.
+///
+/// [`TextLayout`]: trait.TextLayout.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritingMode {
+    /// Horizontal lines, from top to bottom.
+    HorizontalTb,
+    /// Vertical columns, from right to left, as is usual for CJK text.
+    VerticalRl,
+    /// Vertical columns, from left to right.
+    VerticalLr,
+}
+
+impl WritingMode {
+    /// Whether lines in this mode are vertical.
+    pub fn is_vertical(self) -> bool {
+        !matches!(self, WritingMode::HorizontalTb)
+    }
+}
+
+/// How text that is cut off by the maximum number of lines of a
+/// [`TextLayout`] is shown.
+///
+/// [`TextLayout`]: trait.TextLayout.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextOverflow {
+    /// The text is cut off after the last line.
+    Clip,
+    /// The end of the last line is replaced by an ellipsis ("…").
+    Ellipsis,
+    /// The middle of the last line is replaced by an ellipsis, followed by
+    /// as much of the end of the text as fits.
+    MiddleEllipsis,
+}
+
+/// The height of the lines in a [`TextLayout`].
+///
+/// [`TextLayout`]: trait.TextLayout.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineHeight {
+    /// The natural height of each line, from the metrics of its fonts.
+    Normal,
+    /// A fixed height, in display points.
+    Absolute(f64),
+    /// A multiple of the natural height of each line.
+    Relative(f64),
+}
+
 /// A drawable text object.
 ///
 /// ## Line Breaks
@@ -320,6 +664,18 @@ pub trait TextLayout: Clone {
         self.size().width
     }
 
+    /// Lay the text out again with a new max width, in display points.
+    ///
+    /// This has the same result as building the layout again with
+    /// [`TextLayoutBuilder::max_width`], but it reuses the work that doesn't
+    /// depend on the width, such as shaping, so it is much cheaper when a
+    /// window is resized. Clones of this layout are not affected.
+    ///
+    /// Pass `f64::INFINITY` to stop wrapping words.
+    ///
+    /// [`TextLayoutBuilder::max_width`]: trait.TextLayoutBuilder.html#tymethod.max_width
+    fn set_max_width(&mut self, width: f64) -> Result<(), Error>;
+
     /// The total size of this `TextLayout`.
     ///
     /// This is the size required to draw this `TextLayout`, as provided by the
@@ -460,9 +816,316 @@ pub trait TextLayout: Clone {
 
         result
     }
+
+    /// The glyphs of this layout, as runs that each use a single font and
+    /// are on a single line.
+    ///
+    /// Runs are in line order, and are ordered from left to right within a
+    /// line. Glyphs that are not drawn, such as those for line breaks, are
+    /// not included.
+    ///
+    /// This is intended for effects that need to know where each glyph is,
+    /// such as animating individual glyphs or exporting text to another
+    /// format; see [`GlyphRun::glyph_positions`].
+    ///
+    /// Backends that have no access to shaped glyphs return an empty `Vec`,
+    /// as do layouts with a vertical [`WritingMode`], whose glyphs are not
+    /// all on horizontal baselines.
+    ///
+    /// [`WritingMode`]: enum.WritingMode.html
+    /// [`GlyphRun::glyph_positions`]: struct.GlyphRun.html#method.glyph_positions
+    fn glyph_runs(&self) -> Vec<GlyphRun>;
+
+    /// The outlines of the glyphs of this layout, as a single path.
+    ///
+    /// The outlines are where [`draw_text`] would draw the glyphs if the
+    /// layout were drawn at `origin`. Decorations such as underlines, and
+    /// background colors, are not included.
+    ///
+    /// The path can be stroked or used as a clip, or exported to formats
+    /// that have no text of their own.
+    ///
+    /// Backends that have no access to glyph outlines return an empty path.
+    ///
+    /// [`draw_text`]: trait.RenderContext.html#tymethod.draw_text
+    fn to_path(&self, origin: impl Into<Point>) -> BezPath;
+
+    /// The rect of the inline object that replaces the text starting at
+    /// `position`, relative to the layout's origin.
+    ///
+    /// This is `None` if no object starts there, or if it is cut off by
+    /// [`TextLayoutBuilder::max_lines`].
+    ///
+    /// [`TextLayoutBuilder::max_lines`]: trait.TextLayoutBuilder.html#tymethod.max_lines
+    fn inline_object_rect(&self, position: usize) -> Option<Rect>;
+}
+
+/// Options for drawing text along a path, with
+/// [`RenderContext::draw_text_on_path`].
+///
+/// [`RenderContext::draw_text_on_path`]: trait.RenderContext.html#tymethod.draw_text_on_path
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextPathOptions {
+    /// The distance the text is moved along the path from the position
+    /// given by `alignment`, towards the end of the path.
+    pub offset: f64,
+    /// Where the text is placed on the path.
+    ///
+    /// `Start` and `End` place the text at the start and end of the path,
+    /// whatever the direction of the text. `Justified` spreads the text out
+    /// from `offset` to the end of the path, if it is shorter than that.
+    pub alignment: TextAlignment,
+    /// What happens to text that doesn't fit on the path.
+    pub overflow: TextPathOverflow,
+}
+
+/// How text that runs past either end of a path is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextPathOverflow {
+    /// Clusters of glyphs that don't fit entirely on the path are not drawn.
+    Hide,
+    /// The path is extended in a straight line past its ends, following
+    /// the direction at each end.
+    Extend,
+}
+
+impl TextPathOptions {
+    /// Options that place text at the start of a path, and hide what
+    /// doesn't fit.
+    pub fn new() -> TextPathOptions {
+        TextPathOptions {
+            offset: 0.0,
+            alignment: TextAlignment::Start,
+            overflow: TextPathOverflow::Hide,
+        }
+    }
+
+    /// Builder-style method to set the offset along the path.
+    pub fn offset(mut self, offset: f64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Builder-style method to set the [`TextAlignment`].
+    ///
+    /// [`TextAlignment`]: enum.TextAlignment.html
+    pub fn alignment(mut self, alignment: TextAlignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    /// Builder-style method to set the [`TextPathOverflow`].
+    ///
+    /// [`TextPathOverflow`]: enum.TextPathOverflow.html
+    pub fn overflow(mut self, overflow: TextPathOverflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+}
+
+impl Default for TextPathOptions {
+    fn default() -> Self {
+        TextPathOptions::new()
+    }
+}
+
+/// The positions that tab characters in a [`TextLayout`] advance to.
+///
+/// Positions are measured from the start of each line, which is its right
+/// edge in right-to-left paragraphs; there, left and right stops are
+/// mirrored. After the explicit stops, tabs advance to the next multiple of
+/// the interval.
+///
+/// [`TextLayout`]: trait.TextLayout.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct TabStops {
+    /// The distance between the stops after the explicit ones.
+    pub interval: TabInterval,
+    /// The explicit stops, in order of position.
+    pub stops: Vec<TabStop>,
+}
+
+/// The distance between uniformly spaced [`TabStops`].
+///
+/// [`TabStops`]: struct.TabStops.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TabInterval {
+    /// A number of spaces, in the layout's default font.
+    Spaces(f64),
+    /// A distance in display points.
+    Points(f64),
+}
+
+/// A tab stop at an explicit position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TabStop {
+    /// The distance of the stop from the start of the line.
+    pub position: f64,
+    /// How the text after a tab is aligned to the stop.
+    pub alignment: TabAlignment,
+}
+
+/// How the text after a tab, up to the next tab or the end of the line, is
+/// aligned to a [`TabStop`].
+///
+/// [`TabStop`]: struct.TabStop.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TabAlignment {
+    /// The text starts at the stop.
+    Left,
+    /// The text ends at the stop.
+    Right,
+    /// The text is centered on the stop.
+    Center,
+    /// The first decimal point in the text is at the stop; text without
+    /// one ends at the stop.
+    Decimal,
+}
+
+impl TabStops {
+    /// Tab stops every eight spaces, with no explicit stops.
+    pub fn new() -> TabStops {
+        TabStops {
+            interval: TabInterval::Spaces(8.0),
+            stops: Vec::new(),
+        }
+    }
+
+    /// Builder-style method to set the [`TabInterval`].
+    ///
+    /// [`TabInterval`]: enum.TabInterval.html
+    pub fn interval(mut self, interval: TabInterval) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Builder-style method to add an explicit stop.
+    pub fn stop(mut self, position: f64, alignment: TabAlignment) -> Self {
+        let idx = self
+            .stops
+            .iter()
+            .position(|stop| stop.position > position)
+            .unwrap_or(self.stops.len());
+        self.stops.insert(
+            idx,
+            TabStop {
+                position,
+                alignment,
+            },
+        );
+        self
+    }
+
+    /// The first stop after `x`, given the width of a space for intervals
+    /// that are measured in spaces.
+    pub fn next_stop(&self, x: f64, space_width: f64) -> TabStop {
+        if let Some(stop) = self.stops.iter().find(|stop| stop.position > x) {
+            return *stop;
+        }
+        let interval = match self.interval {
+            TabInterval::Spaces(spaces) => spaces * space_width,
+            TabInterval::Points(points) => points,
+        };
+        let position = if interval > 0.0 {
+            ((x / interval).floor() + 1.0) * interval
+        } else {
+            x
+        };
+        TabStop {
+            position,
+            alignment: TabAlignment::Left,
+        }
+    }
+}
+
+impl Default for TabStops {
+    fn default() -> Self {
+        TabStops::new()
+    }
+}
+
+/// A sequence of glyphs in a [`TextLayout`] that share a font and a line.
+///
+/// This type is returned by [`TextLayout::glyph_runs`].
+///
+/// [`TextLayout`]: trait.TextLayout.html
+/// [`TextLayout::glyph_runs`]: trait.TextLayout.html#tymethod.glyph_runs
+#[derive(Clone, Debug, PartialEq)]
+pub struct GlyphRun {
+    /// The family of the font the glyphs are from.
+    ///
+    /// This is the font that was used, which may be a fallback font rather
+    /// than the family that was requested for the text.
+    pub family: FontFamily,
+    /// The weight of the font.
+    pub weight: FontWeight,
+    /// The style of the font.
+    pub style: FontStyle,
+    /// The size of the font, in points.
+    pub font_size: f64,
+    /// The number of the line containing this run.
+    pub line: usize,
+    /// The range of the layout's text that this run covers.
+    pub range: Range<usize>,
+    /// The position of the left end of this run's baseline, relative to the
+    /// layout's origin.
+    pub origin: Point,
+    /// Whether the run's text is right-to-left.
+    pub is_rtl: bool,
+    /// The glyphs, in visual order.
+    pub glyphs: Vec<Glyph>,
+}
+
+/// A single glyph in a [`GlyphRun`].
+///
+/// [`GlyphRun`]: struct.GlyphRun.html
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glyph {
+    /// The glyph's index in its font.
+    pub id: u32,
+    /// The distance the pen moves to the right after this glyph.
+    pub advance: f64,
+    /// The offset of the glyph from the pen position; positive `y` is down.
+    ///
+    /// This is nonzero for glyphs such as combining marks, which are
+    /// positioned relative to the glyph before them.
+    pub offset: Vec2,
+    /// The start of the text cluster this glyph belongs to, as a utf-8
+    /// offset into the layout's text.
+    ///
+    /// A cluster is the smallest unit of text that maps to a set of glyphs;
+    /// several glyphs, such as a letter and its accent, can share a cluster,
+    /// and a single glyph, such as a ligature, can cover several characters.
+    pub cluster: usize,
+}
+
+impl GlyphRun {
+    /// The position of each glyph, relative to the layout's origin.
+    ///
+    /// A glyph's position is where its origin on the baseline is drawn.
+    pub fn glyph_positions(&self) -> impl Iterator<Item = Point> + '_ {
+        let mut pen = self.origin;
+        self.glyphs.iter().map(move |glyph| {
+            let pos = pen + glyph.offset;
+            pen.x += glyph.advance;
+            pos
+        })
+    }
+
+    /// The total advance of the glyphs in this run.
+    pub fn width(&self) -> f64 {
+        self.glyphs.iter().map(|glyph| glyph.advance).sum()
+    }
 }
 
 /// Metadata about each line in a text layout.
+///
+/// In layouts with a vertical [`WritingMode`], each line is a column, and
+/// the vertical measurements here are horizontal: `y_offset` is the x
+/// position of the column's left edge, `height` is its width, and `baseline`
+/// is the distance from its left edge to the baseline of rotated text.
+///
+/// [`WritingMode`]: enum.WritingMode.html
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct LineMetric {
     /// The start index of this line in the underlying `String` used to create the
@@ -599,12 +1262,111 @@ impl From<FontStyle> for TextAttribute {
     }
 }
 
+impl From<FontStretch> for TextAttribute {
+    fn from(src: FontStretch) -> TextAttribute {
+        TextAttribute::Stretch(src)
+    }
+}
+
 impl Default for TextAlignment {
     fn default() -> Self {
         TextAlignment::Start
     }
 }
 
+impl Default for WritingMode {
+    fn default() -> Self {
+        WritingMode::HorizontalTb
+    }
+}
+
+impl Default for TextOverflow {
+    fn default() -> Self {
+        TextOverflow::Clip
+    }
+}
+
+impl Default for Justification {
+    fn default() -> Self {
+        Justification::Auto
+    }
+}
+
+impl Default for Hyphens {
+    fn default() -> Self {
+        Hyphens::Manual
+    }
+}
+
+impl BaselineShift {
+    /// The scale applied to the font size of superscripts and subscripts.
+    pub const SCRIPT_SCALE: f64 = 0.65;
+
+    /// The size of text with this shift, given the size it would otherwise
+    /// have.
+    pub fn font_size(self, font_size: f64) -> f64 {
+        match self {
+            BaselineShift::Superscript | BaselineShift::Subscript => {
+                font_size * BaselineShift::SCRIPT_SCALE
+            }
+            BaselineShift::Normal | BaselineShift::Offset(_) => font_size,
+        }
+    }
+
+    /// How far text with this shift is raised above the baseline, given the
+    /// size it would otherwise have; negative values lower it.
+    pub fn offset(self, font_size: f64) -> f64 {
+        match self {
+            BaselineShift::Normal => 0.0,
+            BaselineShift::Superscript => font_size / 3.0,
+            BaselineShift::Subscript => -font_size / 5.0,
+            BaselineShift::Offset(offset) => offset,
+        }
+    }
+}
+
+impl From<FontFeature> for TextAttribute {
+    fn from(src: FontFeature) -> TextAttribute {
+        TextAttribute::FontFeatures(vec![src])
+    }
+}
+
+impl From<FontVariation> for TextAttribute {
+    fn from(src: FontVariation) -> TextAttribute {
+        TextAttribute::FontVariations(vec![src])
+    }
+}
+
+impl Default for UnderlineStyle {
+    fn default() -> Self {
+        UnderlineStyle::Single
+    }
+}
+
+impl From<UnderlineStyle> for TextAttribute {
+    fn from(src: UnderlineStyle) -> TextAttribute {
+        TextAttribute::UnderlineStyle(src)
+    }
+}
+
+impl Default for BaselineShift {
+    fn default() -> Self {
+        BaselineShift::Normal
+    }
+}
+
+impl From<BaselineShift> for TextAttribute {
+    fn from(src: BaselineShift) -> TextAttribute {
+        TextAttribute::BaselineShift(src)
+    }
+}
+
+impl Default for LineHeight {
+    fn default() -> Self {
+        LineHeight::Normal
+    }
+}
+
 impl TextStorage for std::sync::Arc<str> {
     fn as_str(&self) -> &str {
         self
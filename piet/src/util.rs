@@ -2,8 +2,15 @@
 
 use std::ops::{Bound, Range, RangeBounds};
 
-use crate::kurbo::{Rect, Size};
-use crate::{Color, FontFamily, FontStyle, FontWeight, LineMetric, TextAttribute};
+use crate::kurbo::{
+    self, Affine, ParamCurve, ParamCurveArclen, ParamCurveDeriv, PathSeg, Point, Rect, Shape, Size,
+    Vec2,
+};
+use crate::{
+    BaselineShift, Color, FontFamily, FontFeature, FontStretch, FontStyle, FontVariation,
+    FontWeight, GlyphRun, LineHeight, LineMetric, TextAlignment, TextAttribute, TextPathOptions,
+    TextPathOverflow, UnderlineStyle,
+};
 
 use unic_bidi::bidi_class::{BidiClass, BidiClassCategory};
 
@@ -156,8 +163,19 @@ pub struct LayoutDefaults {
     pub weight: FontWeight,
     pub fg_color: Color,
     pub style: FontStyle,
+    pub stretch: FontStretch,
     pub underline: bool,
+    pub underline_style: UnderlineStyle,
+    pub overline: bool,
     pub strikethrough: bool,
+    /// The color of decorations, if it differs from `fg_color`.
+    pub decoration_color: Option<Color>,
+    pub background_color: Option<Color>,
+    pub letter_spacing: f64,
+    pub word_spacing: f64,
+    pub font_features: Vec<FontFeature>,
+    pub font_variations: Vec<FontVariation>,
+    pub baseline_shift: BaselineShift,
 }
 
 impl LayoutDefaults {
@@ -168,9 +186,19 @@ impl LayoutDefaults {
             TextAttribute::FontSize(size) => self.font_size = size,
             TextAttribute::Weight(weight) => self.weight = weight,
             TextAttribute::Style(style) => self.style = style,
+            TextAttribute::Stretch(stretch) => self.stretch = stretch,
             TextAttribute::Underline(flag) => self.underline = flag,
             TextAttribute::TextColor(color) => self.fg_color = color,
+            TextAttribute::UnderlineStyle(style) => self.underline_style = style,
+            TextAttribute::Overline(flag) => self.overline = flag,
             TextAttribute::Strikethrough(flag) => self.strikethrough = flag,
+            TextAttribute::DecorationColor(color) => self.decoration_color = Some(color),
+            TextAttribute::BackgroundColor(color) => self.background_color = Some(color),
+            TextAttribute::LetterSpacing(spacing) => self.letter_spacing = spacing,
+            TextAttribute::WordSpacing(spacing) => self.word_spacing = spacing,
+            TextAttribute::FontFeatures(features) => self.font_features = features,
+            TextAttribute::FontVariations(variations) => self.font_variations = variations,
+            TextAttribute::BaselineShift(shift) => self.baseline_shift = shift,
         }
     }
 }
@@ -183,12 +211,55 @@ impl Default for LayoutDefaults {
             weight: FontWeight::default(),
             fg_color: DEFAULT_TEXT_COLOR,
             style: FontStyle::default(),
+            stretch: FontStretch::default(),
             underline: false,
+            underline_style: UnderlineStyle::default(),
+            overline: false,
             strikethrough: false,
+            decoration_color: None,
+            background_color: None,
+            letter_spacing: 0.0,
+            word_spacing: 0.0,
+            font_features: Vec::new(),
+            font_variations: Vec::new(),
+            baseline_shift: BaselineShift::default(),
         }
     }
 }
 
+/// The baseline and height of a line with the given [`LineHeight`], from its
+/// natural baseline and height.
+///
+/// The difference from the natural height is split evenly above and below
+/// the line's text.
+///
+/// [`LineHeight`]: ../enum.LineHeight.html
+pub fn apply_line_height(line_height: LineHeight, baseline: f64, height: f64) -> (f64, f64) {
+    let new_height = match line_height {
+        LineHeight::Normal => return (baseline, height),
+        LineHeight::Absolute(new_height) => new_height,
+        LineHeight::Relative(factor) => height * factor,
+    };
+    let new_height = new_height.max(0.0);
+    (baseline + (new_height - height) / 2.0, new_height)
+}
+
+/// Whether `c` separates words, for the purposes of word spacing.
+///
+/// These are the word-separator characters listed by the CSS Text spec.
+pub fn is_word_separator(c: char) -> bool {
+    matches!(
+        c,
+        '\u{0020}'
+            | '\u{00A0}'
+            | '\u{1361}'
+            | '\u{10100}'
+            | '\u{10101}'
+            | '\u{1039F}'
+            | '\u{1091F}'
+    )
+}
+
 /// If `x` is a single (non-alpha) channel of a premultiplied color and `a` is the alpha channel,
 /// returns the corresponding channel of the unpremultiplied version of the color.
 pub fn unpremul(x: u8, a: u8) -> u8 {
@@ -215,6 +286,193 @@ pub fn first_strong_rtl(text: &str) -> bool {
         .unwrap_or(false)
 }
 
+/// The transforms that place clusters of glyphs along a path, for
+/// [`RenderContext::draw_text_on_path`].
+///
+/// Each cluster is given as the x position of its left edge and its width,
+/// in layout coordinates, and all the clusters are on a line with its
+/// baseline at `baseline`. Clusters are placed in order of their x position.
+///
+/// For each cluster, this returns the transform from layout coordinates that
+/// puts the cluster on the path, or `None` if the cluster is hidden because
+/// it doesn't fit. If the path has more than one subpath, they are followed
+/// as if they were joined.
+///
+/// [`RenderContext::draw_text_on_path`]: ../trait.RenderContext.html#tymethod.draw_text_on_path
+pub fn text_path_transforms(
+    path: impl Shape,
+    clusters: &[(f64, f64)],
+    baseline: f64,
+    options: &TextPathOptions,
+) -> Vec<Option<Affine>> {
+    let path = PathWalker::new(path);
+    let text_start = clusters.iter().map(|c| c.0).fold(f64::INFINITY, f64::min);
+    let text_end = clusters
+        .iter()
+        .map(|c| c.0 + c.1)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let width = text_end - text_start;
+
+    let mut spacing = 0.0;
+    let start = options.offset
+        + match options.alignment {
+            TextAlignment::Start => 0.0,
+            TextAlignment::End => path.length - width,
+            TextAlignment::Center => (path.length - width) / 2.0,
+            TextAlignment::Justified => {
+                let extra = path.length - options.offset - width;
+                if extra > 0.0 && clusters.len() > 1 {
+                    spacing = extra / (clusters.len() - 1) as f64;
+                }
+                0.0
+            }
+        };
+
+    let mut order = (0..clusters.len()).collect::<Vec<_>>();
+    order.sort_by(|a, b| {
+        clusters[*a]
+            .0
+            .partial_cmp(&clusters[*b].0)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    let mut result = vec![None; clusters.len()];
+    for (n, idx) in order.into_iter().enumerate() {
+        let (x, width) = clusters[idx];
+        let cluster_start = start + x - text_start + n as f64 * spacing;
+        let fits =
+            cluster_start >= -PATH_ACCURACY && cluster_start + width <= path.length + PATH_ACCURACY;
+        if options.overflow == TextPathOverflow::Hide && !fits {
+            continue;
+        }
+        if let Some((point, direction)) = path.point_at(cluster_start + width / 2.0) {
+            result[idx] = Some(
+                Affine::translate(point.to_vec2())
+                    * Affine::rotate(direction.atan2())
+                    * Affine::translate(Vec2::new(-(x + width / 2.0), -baseline)),
+            );
+        }
+    }
+    result
+}
+
+/// The transforms that place the glyphs of the first line of a layout along
+/// a path, from its [`GlyphRun`]s.
+///
+/// This is [`text_path_transforms`] for the clusters of the glyphs; the
+/// result has a transform for each glyph of each run. Glyphs on other lines
+/// have no transform.
+///
+/// [`GlyphRun`]: ../struct.GlyphRun.html
+/// [`text_path_transforms`]: fn.text_path_transforms.html
+pub fn glyph_run_transforms(
+    path: impl Shape,
+    runs: &[GlyphRun],
+    baseline: f64,
+    options: &TextPathOptions,
+) -> Vec<Vec<Option<Affine>>> {
+    let mut clusters: Vec<(f64, f64)> = Vec::new();
+    // the cluster of each glyph of each run
+    let mut glyph_clusters = Vec::with_capacity(runs.len());
+    for run in runs {
+        let mut x = run.origin.x;
+        let mut last_cluster = None;
+        let indices = run
+            .glyphs
+            .iter()
+            .map(|glyph| {
+                if run.line != 0 {
+                    return None;
+                }
+                if last_cluster != Some(glyph.cluster) {
+                    clusters.push((x, 0.0));
+                    last_cluster = Some(glyph.cluster);
+                }
+                clusters.last_mut().unwrap().1 += glyph.advance;
+                x += glyph.advance;
+                Some(clusters.len() - 1)
+            })
+            .collect::<Vec<_>>();
+        glyph_clusters.push(indices);
+    }
+
+    let transforms = text_path_transforms(path, &clusters, baseline, options);
+    glyph_clusters
+        .into_iter()
+        .map(|indices| {
+            indices
+                .into_iter()
+                .map(|idx| idx.and_then(|idx| transforms[idx]))
+                .collect()
+        })
+        .collect()
+}
+
+const PATH_ACCURACY: f64 = 1e-3;
+
+/// Finds points along a path, by their distance from its start.
+struct PathWalker {
+    /// The segments of the path with their start distance and their length.
+    segments: Vec<(PathSeg, f64, f64)>,
+    length: f64,
+}
+
+impl PathWalker {
+    fn new(path: impl Shape) -> PathWalker {
+        let mut length = 0.0;
+        let segments = kurbo::segments(path.path_elements(PATH_ACCURACY))
+            .filter_map(|seg| {
+                let seg_length = seg.arclen(PATH_ACCURACY);
+                if seg_length <= 0.0 {
+                    return None;
+                }
+                length += seg_length;
+                Some((seg, length - seg_length, seg_length))
+            })
+            .collect();
+        PathWalker { segments, length }
+    }
+
+    /// The point at `distance` along the path, and the direction of the path
+    /// there.
+    ///
+    /// Distances before the start or past the end of the path are on the
+    /// straight lines that continue the path from its ends.
+    fn point_at(&self, distance: f64) -> Option<(Point, Vec2)> {
+        let idx = self
+            .segments
+            .iter()
+            .position(|(_, start, length)| distance < start + length)
+            .unwrap_or_else(|| self.segments.len().saturating_sub(1));
+        let (seg, start, length) = self.segments.get(idx)?;
+        let along = distance - start;
+        let (t, extension) = if along < 0.0 {
+            (0.0, along)
+        } else if along > *length {
+            (1.0, along - length)
+        } else {
+            (seg.inv_arclen(along, PATH_ACCURACY), 0.0)
+        };
+        let direction = seg_direction(seg, t);
+        Some((seg.eval(t) + direction * extension, direction))
+    }
+}
+
+/// The unit vector in the direction of a path segment at `t`.
+fn seg_direction(seg: &PathSeg, t: f64) -> Vec2 {
+    let deriv = match seg {
+        PathSeg::Line(line) => line.p1 - line.p0,
+        PathSeg::Quad(quad) => quad.deriv().eval(t).to_vec2(),
+        PathSeg::Cubic(cubic) => cubic.deriv().eval(t).to_vec2(),
+    };
+    // the derivative vanishes where control points coincide with an end
+    let deriv = if deriv.hypot() > 1e-9 {
+        deriv
+    } else {
+        seg.end() - seg.start()
+    };
+    deriv / deriv.hypot()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -237,4 +495,89 @@ mod tests {
 
         assert_eq!(count_until_utf16("", 0), None);
     }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn line_height() {
+        assert_eq!(
+            apply_line_height(LineHeight::Normal, 12.0, 16.0),
+            (12.0, 16.0)
+        );
+        assert_eq!(
+            apply_line_height(LineHeight::Absolute(20.0), 12.0, 16.0),
+            (14.0, 20.0)
+        );
+        assert_eq!(
+            apply_line_height(LineHeight::Relative(0.5), 12.0, 16.0),
+            (8.0, 8.0)
+        );
+        assert_eq!(
+            apply_line_height(LineHeight::Absolute(-4.0), 12.0, 16.0),
+            (4.0, 0.0)
+        );
+    }
+
+    fn assert_near(p: Point, expected: (f64, f64)) {
+        assert!(
+            (p - Point::from(expected)).hypot() < 0.01,
+            "{:?} != {:?}",
+            p,
+            expected
+        );
+    }
+
+    #[test]
+    fn text_path_placement() {
+        use kurbo::Line;
+
+        // three clusters, 10 wide, on a baseline at y = 8
+        let clusters = [(5.0, 10.0), (15.0, 10.0), (25.0, 10.0)];
+        let line = Line::new((0.0, 0.0), (100.0, 0.0));
+        let centers = |options: TextPathOptions| {
+            text_path_transforms(line, &clusters, 8.0, &options)
+                .into_iter()
+                .zip(&clusters)
+                .map(|(transform, (x, width))| {
+                    transform.map(|t| t * Point::new(x + width / 2.0, 8.0))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let start = centers(TextPathOptions::new().offset(10.0));
+        assert_near(start[0].unwrap(), (15.0, 0.0));
+        assert_near(start[2].unwrap(), (35.0, 0.0));
+
+        let end = centers(TextPathOptions::new().alignment(TextAlignment::End));
+        assert_near(end[2].unwrap(), (95.0, 0.0));
+
+        let center = centers(TextPathOptions::new().alignment(TextAlignment::Center));
+        assert_near(center[1].unwrap(), (50.0, 0.0));
+
+        let justified = centers(TextPathOptions::new().alignment(TextAlignment::Justified));
+        assert_near(justified[0].unwrap(), (5.0, 0.0));
+        assert_near(justified[1].unwrap(), (50.0, 0.0));
+        assert_near(justified[2].unwrap(), (95.0, 0.0));
+
+        let hidden = centers(TextPathOptions::new().offset(75.0));
+        assert!(hidden[0].is_some() && hidden[1].is_some());
+        assert!(hidden[2].is_none());
+        let extended = centers(
+            TextPathOptions::new()
+                .offset(75.0)
+                .overflow(TextPathOverflow::Extend),
+        );
+        assert_near(extended[2].unwrap(), (100.0, 0.0));
+    }
+
+    #[test]
+    fn text_path_follows_curve() {
+        // a square, clockwise in y-down coordinates
+        let square = Rect::new(0.0, 0.0, 100.0, 100.0);
+        let clusters = [(0.0, 10.0)];
+        let options = TextPathOptions::new().offset(145.0);
+        let transform = text_path_transforms(square, &clusters, 0.0, &options)[0].unwrap();
+        // the cluster is on the right edge, going down
+        assert_near(transform * Point::new(5.0, 0.0), (100.0, 50.0));
+        assert_near(transform * Point::new(5.0, -10.0), (110.0, 50.0));
+    }
 }